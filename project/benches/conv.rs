@@ -0,0 +1,26 @@
+//times the naive and im2col ConvLayer paths on an MNIST-sized batch: `cargo bench --bench conv`
+use std::time::Instant;
+use nalgebra::DMatrix;
+use project::convnn::{ConvAlgorithm, ConvLayer};
+use project::neuralnetwork::Layer;
+
+const BATCH : usize = 32;
+const ROUNDS : usize = 5;
+
+fn main() {
+    let (channels, filters, size) = (1, 8, 28);
+    let mut naive = ConvLayer::new(channels, filters, 3, 1, 1).with_input_size(size, size).with_algorithm(ConvAlgorithm::Naive);
+    let mut im2col = ConvLayer::new(channels, filters, 3, 1, 1).with_input_size(size, size).with_algorithm(ConvAlgorithm::Im2col);
+    let input = DMatrix::from_fn(channels * size * size, BATCH, |i, j| ((i * 31 + j * 17) % 255) as f64 / 255.0);
+    let error = DMatrix::from_fn(filters * size * size, BATCH, |i, j| ((i * 13 + j * 7) % 19) as f64 / 19.0 - 0.5);
+
+    for (name, layer) in [("naive", &mut naive as &mut dyn Layer), ("im2col", &mut im2col)] {
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            layer.forward(&input);
+            layer.backward(&error);
+        }
+        let per_batch = start.elapsed() / ROUNDS as u32;
+        println!("{:>7}: {:?} per forward+backward of {} {}x{} images", name, per_batch, BATCH, size, size);
+    }
+}
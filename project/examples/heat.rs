@@ -0,0 +1,66 @@
+//PINN for the 1D heat equation u_t = alpha u_xx on x in [0,1], t in [0,1]
+//with u(x,0) = sin(pi x) and u(0,t) = u(1,t) = 0; exact solution exp(-alpha pi² t) sin(pi x)
+//`cargo run --release --example heat`
+use std::f64::consts::PI;
+use nalgebra::DVector;
+use rand::Rng;
+use project::activation::{Identity, Tanh};
+use project::init::Initializer;
+use project::neuralnetwork::{DenseLayer, MeanSquaredError, NeuralNetwork};
+use project::optimizer::Adam;
+use project::pinn::{Pinn, Term};
+use project::rng;
+
+const ALPHA : f64 = 0.1;
+const EPOCHS : usize = 3000;
+const TOLERANCE : f64 = 0.05; //relative L2 error the run has to reach
+
+fn exact(x : f64, t : f64) -> f64 {
+    (-ALPHA * PI * PI * t).exp() * (PI * x).sin()
+}
+
+fn point(x : f64, t : f64) -> DVector<f64> {
+    DVector::from_vec(vec![x, t])
+}
+
+fn main() {
+    rng::set_seed(7);
+    let mut r_vals = rng::seeded(7);
+    let interior = (0..400).map(|_| point(r_vals.gen_range(0.0..1.0), r_vals.gen_range(0.0..1.0))).collect::<Vec<_>>();
+    let initial = (0..=40).map(|i| point(i as f64 / 40.0, 0.0)).collect::<Vec<_>>();
+    let boundary = (0..=20).flat_map(|i| [point(0.0, i as f64 / 20.0), point(1.0, i as f64 / 20.0)]).collect::<Vec<_>>();
+
+    let network = NeuralNetwork::with_optimizer(
+        vec![
+            Box::new(DenseLayer::with_initializer(2, 24, Box::new(Tanh), Initializer::XavierNormal)),
+            Box::new(DenseLayer::with_initializer(24, 24, Box::new(Tanh), Initializer::XavierNormal)),
+            Box::new(DenseLayer::with_initializer(24, 1, Box::new(Identity), Initializer::XavierNormal)),
+        ],
+        Box::new(MeanSquaredError),
+        Box::new(Adam::new(0.005)),
+    );
+    //inputs are (x, t): index 0 is space, 1 is time
+    let mut pinn = Pinn::new(network)
+        .with_residual(interior, 2, Box::new(|_, d| d.du(1).sub(&d.d2u(0, 0).scale(ALPHA))))
+        .with_term(Term::value("initial", initial, |x| DVector::from_element(1, (PI * x[0]).sin())).with_weight(10.0))
+        .with_term(Term::value("boundary", boundary, |_| DVector::zeros(1)).with_weight(10.0));
+
+    for epoch in 0..EPOCHS {
+        let loss = pinn.train_step();
+        if epoch % 500 == 0 {
+            let terms = loss.terms.iter().map(|(name, value)| format!("{} {:.2e}", name, value)).collect::<Vec<_>>();
+            println!("Epoch {} : Loss: {:.3e} ({})", epoch, loss.total, terms.join(", "));
+        }
+    }
+
+    let grid = (0..=20).flat_map(|i| (0..=20).map(move |j| (i as f64 / 20.0, j as f64 / 20.0))).collect::<Vec<_>>();
+    let (mut error, mut norm) = (0.0, 0.0);
+    for &(x, t) in grid.iter() {
+        let u = pinn.model().forward(&point(x, t))[0];
+        error += (u - exact(x, t)).powi(2);
+        norm += exact(x, t).powi(2);
+    }
+    let relative = (error / norm).sqrt();
+    println!("Relative L2 error against the exact solution: {:.4}", relative);
+    assert!(relative < TOLERANCE, "PINN did not converge (error {} above {})", relative, TOLERANCE);
+}
@@ -0,0 +1,62 @@
+//PINN for a damped harmonic oscillator u'' + 2 zeta omega u' + omega² u = 0 on t in [0,3]
+//with u(0) = 1 and u'(0) = 0; compared against the closed-form underdamped solution
+//`cargo run --release --example oscillator`
+use nalgebra::DVector;
+use project::activation::{Identity, Tanh};
+use project::init::Initializer;
+use project::neuralnetwork::{DenseLayer, MeanSquaredError, NeuralNetwork};
+use project::optimizer::Adam;
+use project::pinn::{Pinn, Term};
+use project::rng;
+
+const OMEGA : f64 = 2.0;
+const ZETA : f64 = 0.1;
+const T_END : f64 = 3.0;
+const EPOCHS : usize = 6000;
+const TOLERANCE : f64 = 0.05; //relative L2 error the run has to reach
+
+fn exact(t : f64) -> f64 {
+    let damped = OMEGA * (1.0 - ZETA * ZETA).sqrt();
+    (-ZETA * OMEGA * t).exp() * ((damped * t).cos() + ZETA * OMEGA / damped * (damped * t).sin())
+}
+
+fn main() {
+    rng::set_seed(3);
+    let collocation = (0..=120).map(|i| DVector::from_element(1, T_END * i as f64 / 120.0)).collect::<Vec<_>>();
+    let start = vec![DVector::zeros(1)];
+
+    let network = NeuralNetwork::with_optimizer(
+        vec![
+            Box::new(DenseLayer::with_initializer(1, 32, Box::new(Tanh), Initializer::XavierNormal)),
+            Box::new(DenseLayer::with_initializer(32, 32, Box::new(Tanh), Initializer::XavierNormal)),
+            Box::new(DenseLayer::with_initializer(32, 1, Box::new(Identity), Initializer::XavierNormal)),
+        ],
+        Box::new(MeanSquaredError),
+        Box::new(Adam::new(0.005)),
+    );
+    let mut pinn = Pinn::new(network)
+        .with_residual(collocation, 2, Box::new(|_, d| {
+            d.d2u(0, 0).add(&d.du(0).scale(2.0 * ZETA * OMEGA)).add(&d.u().scale(OMEGA * OMEGA))
+        }))
+        .with_term(Term::value("position", start.clone(), |_| DVector::from_element(1, 1.0)).with_weight(10.0))
+        .with_term(Term::new("velocity", start, 1, Box::new(|_, d| d.du(0))).with_weight(10.0));
+
+    for epoch in 0..EPOCHS {
+        let loss = pinn.train_step();
+        if epoch % 500 == 0 {
+            let terms = loss.terms.iter().map(|(name, value)| format!("{} {:.2e}", name, value)).collect::<Vec<_>>();
+            println!("Epoch {} : Loss: {:.3e} ({})", epoch, loss.total, terms.join(", "));
+        }
+    }
+
+    let (mut error, mut norm) = (0.0, 0.0);
+    for i in 0..=300 {
+        let t = T_END * i as f64 / 300.0;
+        let u = pinn.model().forward(&DVector::from_element(1, t))[0];
+        error += (u - exact(t)).powi(2);
+        norm += exact(t).powi(2);
+    }
+    let relative = (error / norm).sqrt();
+    println!("Relative L2 error against the exact solution: {:.4}", relative);
+    assert!(relative < TOLERANCE, "PINN did not converge (error {} above {})", relative, TOLERANCE);
+}
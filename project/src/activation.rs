@@ -0,0 +1,360 @@
+use nalgebra::DMatrix;
+use crate::autodiff::Var;
+use crate::neuralnetwork::Layer;
+use crate::serialization::LayerSpec;
+
+//sqrt(2/pi), used by the tanh approximation of GELU
+const GELU_COEFF : f64 = 0.7978845608028654;
+
+pub trait Activation {
+    fn value(&self, x : f64) -> f64;
+    fn derivative(&self, x : f64) -> f64; //derivative w.r.t. the pre-activation x
+    //used for input Hessians; the built-in activations override this with the exact formula
+    fn second_derivative(&self, x : f64) -> f64 {
+        let h = 1e-5 * (1.0 + x.abs());
+        (self.derivative(x + h) - self.derivative(x - h)) / (2.0 * h)
+    }
+    //used when a loss on input derivatives is trained (Pinn); overridden like second_derivative
+    fn third_derivative(&self, x : f64) -> f64 {
+        let h = 1e-5 * (1.0 + x.abs());
+        (self.second_derivative(x + h) - self.second_derivative(x - h)) / (2.0 * h)
+    }
+    fn name(&self) -> String; //round-trips through from_name, used when saving models
+    fn apply(&self, z : &DMatrix<f64>) -> DMatrix<f64> {
+        z.map(|x| self.value(x))
+    }
+    //maps dL/da to dL/dz given the cached pre-activation z
+    fn backward(&self, z : &DMatrix<f64>, error : &DMatrix<f64>) -> DMatrix<f64> {
+        error.component_mul(&z.map(|x| self.derivative(x)))
+    }
+}
+
+fn sigmoid(x : f64) -> f64 {
+    if x >= 0.0 {
+        1.0 / (1.0 + (-x).exp())
+    }
+    else {
+        let e = x.exp();
+        e / (1.0 + e)
+    }
+}
+
+pub struct Identity;
+impl Activation for Identity {
+    fn name(&self) -> String {
+        "identity".to_string()
+    }
+    fn value(&self, x : f64) -> f64 {
+        x
+    }
+    fn derivative(&self, _x : f64) -> f64 {
+        1.0
+    }
+    fn second_derivative(&self, _x : f64) -> f64 {
+        0.0
+    }
+    fn third_derivative(&self, _x : f64) -> f64 {
+        0.0
+    }
+}
+
+pub struct Relu;
+impl Activation for Relu {
+    fn name(&self) -> String {
+        "relu".to_string()
+    }
+    fn value(&self, x : f64) -> f64 {
+        x.max(0.0)
+    }
+    fn derivative(&self, x : f64) -> f64 {
+        if x > 0.0 {1.0} else {0.0}
+    }
+    fn second_derivative(&self, _x : f64) -> f64 {
+        0.0
+    }
+    fn third_derivative(&self, _x : f64) -> f64 {
+        0.0
+    }
+}
+
+pub struct LeakyRelu {
+    pub alpha : f64,
+}
+impl LeakyRelu {
+    pub fn new(alpha : f64) -> Self {
+        LeakyRelu { alpha }
+    }
+}
+impl Default for LeakyRelu {
+    fn default() -> Self {
+        LeakyRelu { alpha : 0.01 }
+    }
+}
+impl Activation for LeakyRelu {
+    fn name(&self) -> String {
+        format!("leaky_relu({})", self.alpha)
+    }
+    fn value(&self, x : f64) -> f64 {
+        if x > 0.0 {x} else {self.alpha * x}
+    }
+    fn derivative(&self, x : f64) -> f64 {
+        if x > 0.0 {1.0} else {self.alpha}
+    }
+    fn second_derivative(&self, _x : f64) -> f64 {
+        0.0
+    }
+    fn third_derivative(&self, _x : f64) -> f64 {
+        0.0
+    }
+}
+
+pub struct Sigmoid;
+impl Activation for Sigmoid {
+    fn name(&self) -> String {
+        "sigmoid".to_string()
+    }
+    fn value(&self, x : f64) -> f64 {
+        sigmoid(x)
+    }
+    fn derivative(&self, x : f64) -> f64 {
+        let s = sigmoid(x);
+        s * (1.0 - s)
+    }
+    fn second_derivative(&self, x : f64) -> f64 {
+        let s = sigmoid(x);
+        s * (1.0 - s) * (1.0 - 2.0 * s)
+    }
+    fn third_derivative(&self, x : f64) -> f64 {
+        let s = sigmoid(x);
+        s * (1.0 - s) * (1.0 - 6.0 * s + 6.0 * s * s)
+    }
+}
+
+pub struct Tanh;
+impl Activation for Tanh {
+    fn name(&self) -> String {
+        "tanh".to_string()
+    }
+    fn value(&self, x : f64) -> f64 {
+        x.tanh()
+    }
+    fn derivative(&self, x : f64) -> f64 {
+        let t = x.tanh();
+        1.0 - t * t
+    }
+    fn second_derivative(&self, x : f64) -> f64 {
+        let t = x.tanh();
+        -2.0 * t * (1.0 - t * t)
+    }
+    fn third_derivative(&self, x : f64) -> f64 {
+        let t = x.tanh();
+        -2.0 * (1.0 - t * t) * (1.0 - 3.0 * t * t)
+    }
+}
+
+//tanh approximation of GELU (Hendrycks & Gimpel)
+pub struct Gelu;
+impl Activation for Gelu {
+    fn name(&self) -> String {
+        "gelu".to_string()
+    }
+    fn value(&self, x : f64) -> f64 {
+        let inner = GELU_COEFF * (x + 0.044715 * x.powi(3));
+        0.5 * x * (1.0 + inner.tanh())
+    }
+    fn derivative(&self, x : f64) -> f64 {
+        let inner = GELU_COEFF * (x + 0.044715 * x.powi(3));
+        let t = inner.tanh();
+        let d_inner = GELU_COEFF * (1.0 + 3.0 * 0.044715 * x * x);
+        0.5 * (1.0 + t) + 0.5 * x * (1.0 - t * t) * d_inner
+    }
+    fn second_derivative(&self, x : f64) -> f64 {
+        let inner = GELU_COEFF * (x + 0.044715 * x.powi(3));
+        let t = inner.tanh();
+        let d_inner = GELU_COEFF * (1.0 + 3.0 * 0.044715 * x * x);
+        let dd_inner = GELU_COEFF * 6.0 * 0.044715 * x;
+        (1.0 - t * t) * (d_inner + 0.5 * x * (dd_inner - 2.0 * t * d_inner * d_inner))
+    }
+    fn third_derivative(&self, x : f64) -> f64 {
+        let inner = GELU_COEFF * (x + 0.044715 * x.powi(3));
+        let t = inner.tanh();
+        let d_inner = GELU_COEFF * (1.0 + 3.0 * 0.044715 * x * x);
+        let dd_inner = GELU_COEFF * 6.0 * 0.044715 * x;
+        let ddd_inner = GELU_COEFF * 6.0 * 0.044715;
+        //second_derivative is (1 - t²) h
+        let h = d_inner + 0.5 * x * (dd_inner - 2.0 * t * d_inner * d_inner);
+        let dh = 1.5 * dd_inner - t * d_inner * d_inner
+            + 0.5 * x * (ddd_inner - 2.0 * (1.0 - t * t) * d_inner.powi(3) - 4.0 * t * d_inner * dd_inner);
+        (1.0 - t * t) * (dh - 2.0 * t * d_inner * h)
+    }
+}
+
+pub struct Softplus;
+impl Activation for Softplus {
+    fn name(&self) -> String {
+        "softplus".to_string()
+    }
+    fn value(&self, x : f64) -> f64 {
+        //ln(1+e^x) written so that large |x| does not overflow
+        x.max(0.0) + (-x.abs()).exp().ln_1p()
+    }
+    fn derivative(&self, x : f64) -> f64 {
+        sigmoid(x)
+    }
+    fn second_derivative(&self, x : f64) -> f64 {
+        let s = sigmoid(x);
+        s * (1.0 - s)
+    }
+    fn third_derivative(&self, x : f64) -> f64 {
+        let s = sigmoid(x);
+        s * (1.0 - s) * (1.0 - 2.0 * s)
+    }
+}
+
+pub struct Silu;
+pub type Swish = Silu;
+impl Activation for Silu {
+    fn name(&self) -> String {
+        "silu".to_string()
+    }
+    fn value(&self, x : f64) -> f64 {
+        x * sigmoid(x)
+    }
+    fn derivative(&self, x : f64) -> f64 {
+        let s = sigmoid(x);
+        s + x * s * (1.0 - s)
+    }
+    fn second_derivative(&self, x : f64) -> f64 {
+        let s = sigmoid(x);
+        s * (1.0 - s) * (2.0 + x * (1.0 - 2.0 * s))
+    }
+    fn third_derivative(&self, x : f64) -> f64 {
+        let s = sigmoid(x);
+        s * (1.0 - s) * (3.0 * (1.0 - 2.0 * s) + x * (1.0 - 6.0 * s + 6.0 * s * s))
+    }
+}
+
+pub struct Elu {
+    pub alpha : f64,
+}
+impl Elu {
+    pub fn new(alpha : f64) -> Self {
+        Elu { alpha }
+    }
+}
+impl Default for Elu {
+    fn default() -> Self {
+        Elu { alpha : 1.0 }
+    }
+}
+impl Activation for Elu {
+    fn name(&self) -> String {
+        format!("elu({})", self.alpha)
+    }
+    fn value(&self, x : f64) -> f64 {
+        if x > 0.0 {x} else {self.alpha * x.exp_m1()}
+    }
+    fn derivative(&self, x : f64) -> f64 {
+        if x > 0.0 {1.0} else {self.alpha * x.exp()}
+    }
+    fn second_derivative(&self, x : f64) -> f64 {
+        if x > 0.0 {0.0} else {self.alpha * x.exp()}
+    }
+    fn third_derivative(&self, x : f64) -> f64 {
+        self.second_derivative(x)
+    }
+}
+
+//splits "name(arg)" into ("name", Some(arg))
+pub(crate) fn parse_name(name : &str) -> Option<(&str, Option<f64>)> {
+    match name.find('(') {
+        Some(open) => {
+            let arg = name[open + 1..].strip_suffix(')')?.parse::<f64>().ok()?;
+            Some((&name[..open], Some(arg)))
+        }
+        None => Some((name, None)),
+    }
+}
+
+//inverse of Activation::name for the activations in this module
+pub fn from_name(name : &str) -> Option<Box<dyn Activation>> {
+    let activation : Box<dyn Activation> = match parse_name(name)? {
+        ("identity", None) => Box::new(Identity),
+        ("relu", None) => Box::new(Relu),
+        ("leaky_relu", Some(alpha)) => Box::new(LeakyRelu::new(alpha)),
+        ("sigmoid", None) => Box::new(Sigmoid),
+        ("tanh", None) => Box::new(Tanh),
+        ("gelu", None) => Box::new(Gelu),
+        ("softplus", None) => Box::new(Softplus),
+        ("silu", None) => Box::new(Silu),
+        ("elu", Some(alpha)) => Box::new(Elu::new(alpha)),
+        _ => return None,
+    };
+    Some(activation)
+}
+
+//applies an activation on its own, so it can be placed between any two layers
+pub struct ActivationLayer {
+    activation : Box<dyn Activation>,
+    z : DMatrix<f64>,
+}
+
+impl ActivationLayer {
+    pub fn new(activation : Box<dyn Activation>) -> Self {
+        ActivationLayer {
+            activation,
+            z : DMatrix::zeros(0, 0),
+        }
+    }
+}
+
+impl Layer for ActivationLayer {
+    fn forward(&mut self, input : &DMatrix<f64>) -> DMatrix<f64> {
+        self.z = input.clone();
+        self.activation.apply(input)
+    }
+    fn backward(&mut self, error : &DMatrix<f64>) -> DMatrix<f64> {
+        self.activation.backward(&self.z, error)
+    }
+    fn spec(&self) -> Option<LayerSpec> {
+        Some(LayerSpec::Activation { activation : self.activation.name() })
+    }
+    fn forward_tape(&self, input : &Var, _params : &[Var]) -> Option<Var> {
+        Some(input.activation(&*self.activation))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gradcheck::{assert_close, central_difference, ACTIVATIONS};
+
+    type Scalar<'a> = &'a dyn Fn(f64) -> f64;
+
+    //away from the kinks of relu, leaky_relu and elu
+    const POINTS : [f64; 6] = [-2.7, -1.1, -0.3, 0.2, 0.9, 2.4];
+
+    #[test]
+    fn derivatives_match_central_differences() {
+        for name in ACTIVATIONS {
+            let activation = from_name(name).unwrap();
+            assert_eq!(activation.name(), name);
+            let mut points = POINTS;
+            let orders : [(&str, Scalar, Scalar); 3] = [
+                ("derivative", &|x| activation.value(x), &|x| activation.derivative(x)),
+                ("second derivative", &|x| activation.derivative(x), &|x| activation.second_derivative(x)),
+                ("third derivative", &|x| activation.second_derivative(x), &|x| activation.third_derivative(x)),
+            ];
+            for (order, below, exact) in orders {
+                let numeric = central_difference(points.len(), |i, delta| {
+                    points[i] += delta;
+                    let value = below(points[i]);
+                    points[i] -= delta;
+                    value
+                });
+                let exact = points.iter().map(|&x| exact(x)).collect::<Vec<f64>>();
+                assert_close(&format!("{} {}", name, order), &exact, &numeric, 1e-7);
+            }
+        }
+    }
+}
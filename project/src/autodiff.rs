@@ -0,0 +1,544 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use nalgebra::DMatrix;
+use crate::activation::{Activation, Relu, Sigmoid, Tanh};
+use crate::neuralnetwork::Layer;
+use crate::optimizer::Parameter;
+
+//tape-based reverse-mode autodiff over DMatrix<f64>: every operation on a Var appends a node that
+//remembers its inputs, and backward walks the tape once in reverse accumulating adjoints
+
+#[derive(Clone)]
+enum Op {
+    Leaf,
+    Add(usize, usize),
+    Sub(usize, usize),
+    Mul(usize, usize), //elementwise
+    Div(usize, usize), //elementwise
+    Scale(usize, f64),
+    Offset(usize), //adds a constant
+    MatMul(usize, usize),
+    Transpose(usize),
+    Reshape(usize),
+    Map(usize, Vec<DMatrix<f64>>), //elementwise function, with its first, second, ... derivatives at the input
+    Sum(usize),
+    RowSums(usize),
+    ColumnSums(usize),
+    Gather(usize, Vec<Option<usize>>), //output entry k copies input entry index[k] (None reads 0)
+    Stack(Vec<usize>), //rows of every part one after another
+}
+
+struct Node {
+    value : DMatrix<f64>,
+    op : Op,
+}
+
+//shared so every Var can append to the tape it came from
+#[derive(Clone, Default)]
+pub struct Tape {
+    nodes : Rc<RefCell<Vec<Node>>>,
+}
+
+impl Tape {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    //an input or parameter; gradients can be read for it after backward
+    pub fn var(&self, value : DMatrix<f64>) -> Var {
+        self.push(value, Op::Leaf)
+    }
+    pub fn len(&self) -> usize {
+        self.nodes.borrow().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    fn push(&self, value : DMatrix<f64>, op : Op) -> Var {
+        let mut nodes = self.nodes.borrow_mut();
+        nodes.push(Node { value, op });
+        Var { tape : self.clone(), index : nodes.len() - 1 }
+    }
+}
+
+#[derive(Clone)]
+pub struct Var {
+    tape : Tape,
+    index : usize,
+}
+
+impl Var {
+    pub fn tape(&self) -> &Tape {
+        &self.tape
+    }
+    pub fn value(&self) -> DMatrix<f64> {
+        self.tape.nodes.borrow()[self.index].value.clone()
+    }
+    pub fn shape(&self) -> (usize, usize) {
+        self.tape.nodes.borrow()[self.index].value.shape()
+    }
+    pub fn rows(&self) -> usize {
+        self.shape().0
+    }
+    pub fn cols(&self) -> usize {
+        self.shape().1
+    }
+
+    fn unary(&self, f : impl FnOnce(&DMatrix<f64>) -> (DMatrix<f64>, Op)) -> Var {
+        let (value, op) = f(&self.tape.nodes.borrow()[self.index].value);
+        self.tape.push(value, op)
+    }
+    fn binary(&self, other : &Var, name : &str, f : impl FnOnce(&DMatrix<f64>, &DMatrix<f64>) -> DMatrix<f64>, op : Op) -> Var {
+        assert!(Rc::ptr_eq(&self.tape.nodes, &other.tape.nodes), "{} of variables from different tapes", name);
+        let value = {
+            let nodes = self.tape.nodes.borrow();
+            f(&nodes[self.index].value, &nodes[other.index].value)
+        };
+        self.tape.push(value, op)
+    }
+    //another node of the same tape
+    fn at(&self, index : usize) -> Var {
+        Var { tape : self.tape.clone(), index }
+    }
+    //an elementwise function of self with the given value and derivatives
+    fn derived(&self, value : DMatrix<f64>, derivatives : Vec<DMatrix<f64>>) -> Var {
+        self.tape.push(value, Op::Map(self.index, derivatives))
+    }
+    fn same_shape(&self, other : &Var, name : &str) {
+        assert_eq!(self.shape(), other.shape(), "{} needs matching shapes", name);
+    }
+
+    pub fn add(&self, other : &Var) -> Var {
+        self.same_shape(other, "add");
+        self.binary(other, "add", |a, b| a + b, Op::Add(self.index, other.index))
+    }
+    pub fn sub(&self, other : &Var) -> Var {
+        self.same_shape(other, "sub");
+        self.binary(other, "sub", |a, b| a - b, Op::Sub(self.index, other.index))
+    }
+    pub fn mul(&self, other : &Var) -> Var {
+        self.same_shape(other, "mul");
+        self.binary(other, "mul", |a, b| a.component_mul(b), Op::Mul(self.index, other.index))
+    }
+    pub fn div(&self, other : &Var) -> Var {
+        self.same_shape(other, "div");
+        self.binary(other, "div", |a, b| a.component_div(b), Op::Div(self.index, other.index))
+    }
+    pub fn matmul(&self, other : &Var) -> Var {
+        assert_eq!(self.cols(), other.rows(), "matmul of {:?} by {:?}", self.shape(), other.shape());
+        self.binary(other, "matmul", |a, b| a * b, Op::MatMul(self.index, other.index))
+    }
+    pub fn neg(&self) -> Var {
+        self.scale(-1.0)
+    }
+    pub fn scale(&self, factor : f64) -> Var {
+        self.unary(|a| (a * factor, Op::Scale(self.index, factor)))
+    }
+    pub fn add_scalar(&self, offset : f64) -> Var {
+        self.unary(|a| (a.add_scalar(offset), Op::Offset(self.index)))
+    }
+    //adds a matrix that is treated as a constant
+    pub fn add_constant(&self, constant : &DMatrix<f64>) -> Var {
+        assert_eq!(self.shape(), constant.shape(), "add_constant needs matching shapes");
+        self.unary(|a| (a + constant, Op::Offset(self.index)))
+    }
+    pub fn transpose(&self) -> Var {
+        self.unary(|a| (a.transpose(), Op::Transpose(self.index)))
+    }
+    //column-major, like nalgebra's storage
+    pub fn reshape(&self, rows : usize, cols : usize) -> Var {
+        assert_eq!(self.rows() * self.cols(), rows * cols, "cannot reshape {:?} into {}x{}", self.shape(), rows, cols);
+        self.unary(|a| (DMatrix::from_column_slice(rows, cols, a.as_slice()), Op::Reshape(self.index)))
+    }
+
+    //any elementwise function given with its derivative; jets() cannot see through it
+    pub fn map(&self, f : impl Fn(f64) -> f64, df : impl Fn(f64) -> f64) -> Var {
+        self.map_derivatives(f, &[&df])
+    }
+    //map with the second derivative as well, so input Hessians stay exact
+    pub fn smooth_map(&self, f : impl Fn(f64) -> f64, df : impl Fn(f64) -> f64, d2f : impl Fn(f64) -> f64) -> Var {
+        self.map_derivatives(f, &[&df, &d2f])
+    }
+    //`derivatives` are f', f'', ...; second-order jets need three of them to be differentiable in turn
+    pub fn map_derivatives(&self, f : impl Fn(f64) -> f64, derivatives : &[&dyn Fn(f64) -> f64]) -> Var {
+        self.unary(|a| (a.map(f), Op::Map(self.index, derivatives.iter().map(|d| a.map(d)).collect())))
+    }
+    pub fn activation(&self, activation : &dyn Activation) -> Var {
+        self.map_derivatives(|x| activation.value(x), &[&|x| activation.derivative(x), &|x| activation.second_derivative(x), &|x| activation.third_derivative(x)])
+    }
+    pub fn exp(&self) -> Var {
+        self.map_derivatives(f64::exp, &[&f64::exp, &f64::exp, &f64::exp])
+    }
+    pub fn ln(&self) -> Var {
+        self.map_derivatives(f64::ln, &[&|x| 1.0 / x, &|x| -1.0 / (x * x), &|x| 2.0 / (x * x * x)])
+    }
+    pub fn powf(&self, p : f64) -> Var {
+        self.map_derivatives(|x| x.powf(p), &[
+            &|x| p * x.powf(p - 1.0),
+            &|x| p * (p - 1.0) * x.powf(p - 2.0),
+            &|x| p * (p - 1.0) * (p - 2.0) * x.powf(p - 3.0),
+        ])
+    }
+    pub fn square(&self) -> Var {
+        self.map_derivatives(|x| x * x, &[&|x| 2.0 * x, &|_| 2.0, &|_| 0.0])
+    }
+    pub fn sqrt(&self) -> Var {
+        self.powf(0.5)
+    }
+    //subgradient 0 at 0
+    pub fn abs(&self) -> Var {
+        self.map_derivatives(f64::abs, &[&|x| if x > 0.0 {1.0} else if x < 0.0 {-1.0} else {0.0}, &|_| 0.0, &|_| 0.0])
+    }
+    pub fn tanh(&self) -> Var {
+        self.activation(&Tanh)
+    }
+    pub fn sigmoid(&self) -> Var {
+        self.activation(&Sigmoid)
+    }
+    pub fn relu(&self) -> Var {
+        self.activation(&Relu)
+    }
+
+    //1×1 total of every entry
+    pub fn sum(&self) -> Var {
+        self.unary(|a| (DMatrix::from_element(1, 1, a.sum()), Op::Sum(self.index)))
+    }
+    pub fn mean(&self) -> Var {
+        let n = (self.rows() * self.cols()).max(1) as f64;
+        self.sum().scale(1.0 / n)
+    }
+    //m×1: each row summed over the columns (e.g. over a batch)
+    pub fn row_sums(&self) -> Var {
+        self.unary(|a| (DMatrix::from_column_slice(a.nrows(), 1, a.column_sum().as_slice()), Op::RowSums(self.index)))
+    }
+    //1×n: each column summed over the rows (e.g. over the outputs of one sample)
+    pub fn column_sums(&self) -> Var {
+        self.unary(|a| (DMatrix::from_row_slice(1, a.ncols(), a.row_sum().as_slice()), Op::ColumnSums(self.index)))
+    }
+
+    //rows × cols matrix whose column-major entry k is this variable's entry index[k], or 0 for None;
+    //covers broadcasting, im2col patches, pooling windows and other rearrangements
+    pub fn gather(&self, rows : usize, cols : usize, index : Vec<Option<usize>>) -> Var {
+        assert_eq!(index.len(), rows * cols, "gather needs one index per output entry");
+        self.unary(|a| {
+            let values = index.iter().map(|i| i.map_or(0.0, |i| a[i])).collect::<Vec<f64>>();
+            (DMatrix::from_column_slice(rows, cols, &values), Op::Gather(self.index, index))
+        })
+    }
+    //columns start..start+n
+    pub fn columns(&self, start : usize, n : usize) -> Var {
+        let rows = self.rows();
+        assert!(start + n <= self.cols(), "columns {}..{} of a {:?} variable", start, start + n, self.shape());
+        self.gather(rows, n, (0..rows * n).map(|k| Some(start * rows + k)).collect())
+    }
+    //m×1 repeated over n columns
+    pub fn broadcast_columns(&self, n : usize) -> Var {
+        assert_eq!(self.cols(), 1, "broadcast_columns expects a column vector");
+        let m = self.rows();
+        self.gather(m, n, (0..m * n).map(|k| Some(k % m)).collect())
+    }
+    //1×n repeated over m rows
+    pub fn broadcast_rows(&self, m : usize) -> Var {
+        assert_eq!(self.rows(), 1, "broadcast_rows expects a row vector");
+        let n = self.cols();
+        self.gather(m, n, (0..m * n).map(|k| Some(k / m)).collect())
+    }
+    //log(softmax) of every column, shifted by the column max for stability
+    pub fn log_softmax(&self) -> Var {
+        let value = self.value();
+        //the shift cancels out, so holding it constant leaves the gradient exact
+        let max = DMatrix::from_fn(value.nrows(), value.ncols(), |_, j| -value.column(j).max());
+        let shifted = self.add_constant(&max);
+        let lse = shifted.exp().column_sums().ln();
+        shifted.sub(&lse.broadcast_rows(value.nrows()))
+    }
+
+    //d(self)/d(everything) for a 1×1 variable
+    pub fn backward(&self) -> Gradients {
+        assert_eq!(self.shape(), (1, 1), "backward needs a scalar, use backward_with for {:?}", self.shape());
+        self.backward_with(DMatrix::from_element(1, 1, 1.0))
+    }
+    //vector-Jacobian product: propagates `seed` = dL/d(self) back through the tape
+    pub fn backward_with(&self, seed : DMatrix<f64>) -> Gradients {
+        assert_eq!(seed.shape(), self.shape(), "seed shape does not match the variable");
+        let nodes = self.tape.nodes.borrow();
+        let mut grads : Vec<Option<DMatrix<f64>>> = vec![None; self.index + 1];
+        grads[self.index] = Some(seed);
+        for i in (0..=self.index).rev() {
+            let Some(g) = grads[i].take() else {
+                continue;
+            };
+            let value = |j : usize| &nodes[j].value;
+            let mut add = |j : usize, contribution : DMatrix<f64>| match &mut grads[j] {
+                Some(total) => *total += contribution,
+                slot => *slot = Some(contribution),
+            };
+            match &nodes[i].op {
+                Op::Leaf => {}
+                Op::Add(a, b) => {
+                    add(*a, g.clone());
+                    add(*b, g.clone());
+                }
+                Op::Sub(a, b) => {
+                    add(*a, g.clone());
+                    add(*b, -&g);
+                }
+                Op::Mul(a, b) => {
+                    add(*a, g.component_mul(value(*b)));
+                    add(*b, g.component_mul(value(*a)));
+                }
+                Op::Div(a, b) => {
+                    add(*a, g.component_div(value(*b)));
+                    add(*b, -g.component_mul(value(*a)).component_div(&value(*b).map(|x| x * x)));
+                }
+                Op::Scale(a, factor) => add(*a, &g * *factor),
+                Op::Offset(a) => add(*a, g.clone()),
+                Op::MatMul(a, b) => {
+                    add(*a, &g * value(*b).transpose());
+                    add(*b, value(*a).transpose() * &g);
+                }
+                Op::Transpose(a) => add(*a, g.transpose()),
+                Op::Reshape(a) => {
+                    let (rows, cols) = value(*a).shape();
+                    add(*a, DMatrix::from_column_slice(rows, cols, g.as_slice()));
+                }
+                Op::Map(a, derivatives) => {
+                    let derivative = derivatives.first().expect("backward through a map without a derivative, record it with map_derivatives");
+                    add(*a, g.component_mul(derivative))
+                }
+                Op::Sum(a) => {
+                    let (rows, cols) = value(*a).shape();
+                    add(*a, DMatrix::from_element(rows, cols, g[0]));
+                }
+                Op::RowSums(a) => {
+                    let (rows, cols) = value(*a).shape();
+                    add(*a, DMatrix::from_fn(rows, cols, |r, _| g[r]));
+                }
+                Op::ColumnSums(a) => {
+                    let (rows, cols) = value(*a).shape();
+                    add(*a, DMatrix::from_fn(rows, cols, |_, c| g[c]));
+                }
+                Op::Gather(a, index) => {
+                    let (rows, cols) = value(*a).shape();
+                    let mut scattered = DMatrix::zeros(rows, cols);
+                    for (k, source) in index.iter().enumerate() {
+                        if let Some(source) = source {
+                            scattered[*source] += g[k];
+                        }
+                    }
+                    add(*a, scattered);
+                }
+                Op::Stack(parts) => {
+                    let mut start = 0;
+                    for &part in parts.iter() {
+                        let rows = value(part).nrows();
+                        add(part, g.rows(start, rows).into_owned());
+                        start += rows;
+                    }
+                }
+            }
+            grads[i] = Some(g);
+        }
+        let shapes = nodes.iter().map(|node| node.value.shape()).collect();
+        Gradients { grads, shapes }
+    }
+
+    //first and second derivatives of self along input + t·direction at t = 0, as values
+    pub fn directional(&self, input : &Var, direction : DMatrix<f64>) -> (DMatrix<f64>, DMatrix<f64>) {
+        let (first, second) = self.jets(input, direction);
+        (first.value(), second.value())
+    }
+    //directional() recorded on the tape: the tangents are Vars built from the forward pass, so a loss
+    //on them can be differentiated with respect to the parameters; with one direction per column of a
+    //batch this gives many directions in one pass
+    pub fn jets(&self, input : &Var, direction : DMatrix<f64>) -> (Var, Var) {
+        assert!(Rc::ptr_eq(&self.tape.nodes, &input.tape.nodes), "jets across different tapes");
+        assert_eq!(direction.shape(), input.shape(), "direction shape does not match the input");
+        let (rows, cols) = self.shape();
+        let zeros = || self.tape.var(DMatrix::zeros(rows, cols));
+        if input.index > self.index {
+            return (zeros(), zeros());
+        }
+        //None for nodes that do not depend on the input
+        let mut first : Vec<Option<Var>> = vec![None; self.index + 1];
+        let mut second : Vec<Option<Var>> = vec![None; self.index + 1];
+        first[input.index] = Some(self.tape.var(direction));
+        for i in input.index + 1..=self.index {
+            let (op, (rows, cols)) = {
+                let nodes = self.tape.nodes.borrow();
+                (nodes[i].op.clone(), nodes[i].value.shape())
+            };
+            let f = |j : usize| first[j].as_ref();
+            let s = |j : usize| second[j].as_ref();
+            let (d, dd) = match op {
+                Op::Leaf => (None, None),
+                Op::Mul(a, b) => product(|x, y| x.mul(y), (&self.at(a), f(a), s(a)), (&self.at(b), f(b), s(b))),
+                Op::MatMul(a, b) => product(|x, y| x.matmul(y), (&self.at(a), f(a), s(a)), (&self.at(b), f(b), s(b))),
+                Op::Div(a, b) => {
+                    //a * (1/b), with the derivatives of 1/b written out
+                    let b_value = self.at(b).value();
+                    let power = |p : i32, factor : f64| b_value.map(|x| factor * x.powi(p));
+                    let inverse = self.at(b).derived(power(-1, 1.0), vec![power(-2, -1.0), power(-3, 2.0), power(-4, -6.0)]);
+                    let (d_inverse, s_inverse) = chain(&inverse, f(b), s(b));
+                    product(|x, y| x.mul(y), (&self.at(a), f(a), s(a)), (&inverse, d_inverse.as_ref(), s_inverse.as_ref()))
+                }
+                Op::Map(a, _) => chain(&self.at(i), f(a), s(a)),
+                op => (linear(&op, &self.tape, (rows, cols), &first), linear(&op, &self.tape, (rows, cols), &second)),
+            };
+            first[i] = d;
+            second[i] = dd;
+        }
+        (first[self.index].take().unwrap_or_else(zeros), second[self.index].take().unwrap_or_else(zeros))
+    }
+}
+
+//sum of the terms that are present, None if none are
+fn total(terms : Vec<Option<Var>>) -> Option<Var> {
+    terms.into_iter().flatten().reduce(|sum, term| sum.add(&term))
+}
+
+//product rule to second order for a bilinear p: (a, da, sa) and (b, db, sb) are value, first and second
+//derivative of each factor
+type Jet<'a> = (&'a Var, Option<&'a Var>, Option<&'a Var>);
+fn product(p : impl Fn(&Var, &Var) -> Var, (a, da, sa) : Jet, (b, db, sb) : Jet) -> (Option<Var>, Option<Var>) {
+    let d = total(vec![da.map(|da| p(da, b)), db.map(|db| p(a, db))]);
+    let cross = match (da, db) {
+        (Some(da), Some(db)) => Some(p(da, db).scale(2.0)),
+        _ => None,
+    };
+    let s = total(vec![sa.map(|sa| p(sa, b)), cross, sb.map(|sb| p(a, sb))]);
+    (d, s)
+}
+
+//chain rule to second order through `mapped`, an elementwise function of a node with tangents da and sa;
+//f'(a) and f''(a) go on the tape as maps of their own, with the remaining derivatives
+fn chain(mapped : &Var, da : Option<&Var>, sa : Option<&Var>) -> (Option<Var>, Option<Var>) {
+    let (a, derivatives) = match &mapped.tape.nodes.borrow()[mapped.index].op {
+        Op::Map(a, derivatives) => (mapped.at(*a), derivatives.clone()),
+        _ => unreachable!("chain needs a map"),
+    };
+    let derivative = |order : usize| {
+        let value = derivatives.get(order - 1).unwrap_or_else(|| panic!("jets need derivative {} of a map, record it with map_derivatives", order));
+        a.derived(value.clone(), derivatives[order..].to_vec())
+    };
+    if da.is_none() && sa.is_none() {
+        return (None, None);
+    }
+    let slope = derivative(1);
+    let bend = da.map(|da| da.square().mul(&derivative(2)));
+    (da.map(|da| da.mul(&slope)), total(vec![bend, sa.map(|sa| sa.mul(&slope))]))
+}
+
+//applies an op that is linear in its inputs to their tangents
+fn linear(op : &Op, tape : &Tape, (rows, cols) : (usize, usize), tangents : &[Option<Var>]) -> Option<Var> {
+    let t = |j : usize| tangents[j].as_ref();
+    match op {
+        Op::Leaf => None,
+        Op::Add(a, b) => total(vec![t(*a).cloned(), t(*b).cloned()]),
+        Op::Sub(a, b) => total(vec![t(*a).cloned(), t(*b).map(|x| x.neg())]),
+        Op::Scale(a, factor) => t(*a).map(|x| x.scale(*factor)),
+        Op::Offset(a) => t(*a).cloned(),
+        Op::Transpose(a) => t(*a).map(|x| x.transpose()),
+        Op::Reshape(a) => t(*a).map(|x| x.reshape(rows, cols)),
+        Op::Sum(a) => t(*a).map(|x| x.sum()),
+        Op::RowSums(a) => t(*a).map(|x| x.row_sums()),
+        Op::ColumnSums(a) => t(*a).map(|x| x.column_sums()),
+        Op::Gather(a, index) => t(*a).map(|x| x.gather(rows, cols, index.clone())),
+        Op::Stack(parts) => {
+            if parts.iter().all(|&part| t(part).is_none()) {
+                return None;
+            }
+            let parts = parts.iter().map(|&part| t(part).cloned().unwrap_or_else(|| {
+                let rows = tape.nodes.borrow()[part].value.nrows();
+                tape.var(DMatrix::zeros(rows, cols))
+            })).collect::<Vec<Var>>();
+            Some(stack(&parts))
+        }
+        Op::Mul(..) | Op::Div(..) | Op::MatMul(..) | Op::Map(..) => unreachable!("not a linear op"),
+    }
+}
+
+//stacks the rows of variables with the same column count
+pub fn stack(parts : &[Var]) -> Var {
+    let first = parts.first().expect("stack needs at least one variable");
+    let cols = first.cols();
+    let value = {
+        let nodes = first.tape.nodes.borrow();
+        let rows = parts.iter().map(|part| {
+            assert!(Rc::ptr_eq(&part.tape.nodes, &first.tape.nodes), "stack of variables from different tapes");
+            assert_eq!(nodes[part.index].value.ncols(), cols, "stack needs the same number of columns");
+            nodes[part.index].value.nrows()
+        }).sum::<usize>();
+        let mut value = DMatrix::zeros(rows, cols);
+        let mut start = 0;
+        for part in parts.iter() {
+            let part = &nodes[part.index].value;
+            value.rows_mut(start, part.nrows()).copy_from(part);
+            start += part.nrows();
+        }
+        value
+    };
+    first.tape.push(value, Op::Stack(parts.iter().map(|part| part.index).collect()))
+}
+
+pub struct Gradients {
+    grads : Vec<Option<DMatrix<f64>>>,
+    shapes : Vec<(usize, usize)>,
+}
+
+impl Gradients {
+    //zeros for variables the output does not depend on
+    pub fn wrt(&self, var : &Var) -> DMatrix<f64> {
+        match self.grads.get(var.index) {
+            Some(Some(grad)) => grad.clone(),
+            _ => {
+                let (rows, cols) = self.shapes[var.index];
+                DMatrix::zeros(rows, cols)
+            }
+        }
+    }
+}
+
+pub type ForwardFn = Box<dyn Fn(&Var, &[Var]) -> Var>;
+
+//a layer written only as a forward expression; backward comes from the tape
+pub struct AutodiffLayer {
+    params : Vec<DMatrix<f64>>,
+    grads : Vec<DMatrix<f64>>,
+    forward : ForwardFn,
+    cache : Option<(Var, Var, Vec<Var>)>, //input, output and parameter leaves of the last forward
+}
+
+impl AutodiffLayer {
+    //`forward` maps the input batch (one sample per column) and the parameters to the output batch
+    pub fn new(params : Vec<DMatrix<f64>>, forward : ForwardFn) -> Self {
+        let grads = params.iter().map(|p| DMatrix::zeros(p.nrows(), p.ncols())).collect();
+        AutodiffLayer { params, grads, forward, cache : None }
+    }
+}
+
+impl Layer for AutodiffLayer {
+    fn forward(&mut self, input : &DMatrix<f64>) -> DMatrix<f64> {
+        let tape = Tape::new();
+        let x = tape.var(input.clone());
+        let params = self.params.iter().map(|p| tape.var(p.clone())).collect::<Vec<Var>>();
+        let y = (self.forward)(&x, &params);
+        let output = y.value();
+        self.cache = Some((x, y, params));
+        output
+    }
+    fn backward(&mut self, error : &DMatrix<f64>) -> DMatrix<f64> {
+        let (x, y, params) = self.cache.as_ref().expect("backward called before forward");
+        let grads = y.backward_with(error.clone());
+        self.grads = params.iter().map(|p| grads.wrt(p)).collect();
+        grads.wrt(x)
+    }
+    fn parameters(&mut self) -> Vec<Parameter<'_>> {
+        self.params.iter_mut().zip(self.grads.iter())
+            .map(|(value, grad)| Parameter { value : value.as_mut_slice(), grad : grad.as_slice() })
+            .collect()
+    }
+    fn forward_tape(&self, input : &Var, params : &[Var]) -> Option<Var> {
+        let shaped = params.iter().zip(self.params.iter()).map(|(leaf, p)| leaf.reshape(p.nrows(), p.ncols())).collect::<Vec<Var>>();
+        Some((self.forward)(input, &shaped))
+    }
+}
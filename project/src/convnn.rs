@@ -1,140 +1,465 @@
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
 use nalgebra::{DMatrix, DVector};
 use rand::Rng;
-use crate::neuralnetwork::{Layer, NeuralNetwork, DenseLayer, Loss, MeanSquaredError};
+use crate::autodiff::{stack, Var};
+use crate::init::Initializer;
+use crate::neuralnetwork::{to_batch, Layer, Loss};
+use crate::metrics::{self, Evaluation};
+use crate::optimizer::{Optimizer, Parameter, Sgd};
+use crate::scheduler::{Interval, LrScheduler, Schedule};
+use crate::rng;
+use crate::serialization::{self, LayerSpec, ModelSpec};
+use crate::shape::{build_stack, Shape, ShapeError};
+use crate::trainer::{self, Model};
+
+//a C×H×W image as one H×W matrix per channel
+pub type Tensor3 = Vec<DMatrix<f64>>;
+
+//channel-major, row-major within each channel (the layout MNIST pixels come in)
+pub fn flatten(tensor : &[DMatrix<f64>]) -> DVector<f64> {
+    let len = tensor.iter().map(|m| m.len()).sum();
+    DVector::from_iterator(len, tensor.iter().flat_map(|m| m.transpose().iter().cloned().collect::<Vec<f64>>()))
+}
+
+pub fn unflatten(values : &[f64], channels : usize, rows : usize, cols : usize) -> Tensor3 {
+    assert_eq!(values.len(), channels * rows * cols, "cannot reshape {} values into {}x{}x{}", values.len(), channels, rows, cols);
+    values.chunks(rows * cols).map(|chunk| DMatrix::from_row_slice(rows, cols, chunk)).collect()
+}
+
+fn pad(input : &DMatrix<f64>, padding : usize) -> DMatrix<f64> {
+    match padding {
+        0 => input.clone(),
+        _ => {
+            let (k_rows, k_cols) = input.shape();
+            let mut padded = DMatrix::zeros(k_rows+2*padding,k_cols+2*padding);
+            padded.slice_mut((padding,padding),(k_rows,k_cols)).copy_from(input);
+            padded
+        }
+    }
+}
+
+//how ConvLayer's Layer impl evaluates the convolution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConvAlgorithm {
+    Naive, //direct nested loops, kept as a reference implementation
+    Im2col, //unrolls patches into columns so the whole batch is one matrix multiply
+}
 
 pub struct ConvLayer {
-    filters: Vec<DMatrix<f64>>,
+    filters: Vec<Tensor3>, //F filters, each C×k×k
     bias: DVector<f64>,
     padding: usize,
     stride: usize,
     filter_size : usize,
+    in_channels : usize,
+    in_rows : usize, //spatial size of flattened inputs, 0 means square and inferred from the length
+    in_cols : usize,
+    algorithm : ConvAlgorithm,
+    cache : Vec<Tensor3>, //padded inputs from the last forward pass, one per sample
+    cols : DMatrix<f64>, //im2col patches from the last forward pass
+    grad_filters : Vec<Tensor3>,
+    grad_bias : DVector<f64>,
 }
 
 impl ConvLayer {
+    //`input` is the number of input channels, `output` the number of filters
     pub fn new(input : usize, output : usize, filter_size : usize, stride : usize, padding : usize) -> Self {
-        let mut rVals = rand::thread_rng();
-        let filters = (0..output).map(|output| {
-            DMatrix::from_fn(filter_size, filter_size, |i, j| {
-                rVals.gen_range(-1.0..1.0)
-            })
-        }).collect::<Vec<DMatrix<f64>>>();
-        let bias = DVector::from_iterator(output, (0..output).map(|_| rVals.gen_range(-1.0..1.0)));
+        rng::with_global(|r_vals| Self::new_rng(input, output, filter_size, stride, padding, r_vals))
+    }
+    pub fn new_rng<R : Rng + ?Sized>(input : usize, output : usize, filter_size : usize, stride : usize, padding : usize, r_vals : &mut R) -> Self {
+        let filters = Initializer::Uniform(-1.0, 1.0).conv(input, output, filter_size, r_vals);
+        let bias = DVector::from_iterator(output, (0..output).map(|_| r_vals.gen_range(-1.0..1.0)));
+        Self::from_parts(filters, bias, stride, padding)
+    }
+    //filters drawn from `init` with fan-in C·k·k and fan-out F·k·k, bias starts at zero
+    pub fn with_initializer(input : usize, output : usize, filter_size : usize, stride : usize, padding : usize, init : Initializer) -> Self {
+        rng::with_global(|r_vals| Self::with_initializer_rng(input, output, filter_size, stride, padding, init, r_vals))
+    }
+    pub fn with_initializer_rng<R : Rng + ?Sized>(input : usize, output : usize, filter_size : usize, stride : usize, padding : usize, init : Initializer, r_vals : &mut R) -> Self {
+        let filters = init.conv(input, output, filter_size, r_vals);
+        Self::from_parts(filters, DVector::zeros(output), stride, padding)
+    }
+    pub fn from_parts(filters : Vec<Tensor3>, bias : DVector<f64>, stride : usize, padding : usize) -> Self {
+        let in_channels = filters.first().map_or(0, |f| f.len());
+        let filter_size = filters.first().and_then(|f| f.first()).map_or(0, |k| k.nrows());
+        let grad_filters = vec![vec![DMatrix::zeros(filter_size, filter_size); in_channels]; filters.len()];
+        let grad_bias = DVector::zeros(filters.len());
         Self {
             filters,
             bias,
             padding,
             stride,
             filter_size,
+            in_channels,
+            in_rows : 0,
+            in_cols : 0,
+            algorithm : ConvAlgorithm::Im2col,
+            cache : Vec::new(),
+            cols : DMatrix::zeros(0, 0),
+            grad_filters,
+            grad_bias,
+        }
+    }
+    //spatial size of the images fed through the Layer impl
+    pub fn with_input_size(mut self, rows : usize, cols : usize) -> Self {
+        self.in_rows = rows;
+        self.in_cols = cols;
+        self
+    }
+    pub fn with_algorithm(mut self, algorithm : ConvAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+    pub fn in_channels(&self) -> usize {
+        self.in_channels
+    }
+    pub fn out_channels(&self) -> usize {
+        self.filters.len()
+    }
+    pub fn output_size(&self, rows : usize, cols : usize) -> (usize, usize) {
+        assert!(rows+2*self.padding >= self.filter_size && cols+2*self.padding >= self.filter_size,
+            "{}x{} filter does not fit a {}x{} input with padding {}", self.filter_size, self.filter_size, rows, cols, self.padding);
+        let o_rows = (rows+2*self.padding-self.filter_size)/ self.stride + 1;
+        let o_cols = (cols+2*self.padding-self.filter_size)/self.stride + 1;
+        (o_rows, o_cols)
+    }
+    //(rows, cols) of one input channel given the flattened length
+    fn input_size(&self, len : usize) -> (usize, usize) {
+        if self.in_rows > 0 {
+            return (self.in_rows, self.in_cols);
         }
+        let side = ((len / self.in_channels.max(1)) as f64).sqrt().round() as usize;
+        (side, side)
     }
-    pub fn forward(&self, input : &DMatrix<f64>) -> DMatrix<f64> {
-        let (kRows, kCols) = input.shape();
-        let oRows = (kRows+2*self.padding-self.filter_size)/ self.stride + 1;
-        let oCols = (kCols+2*self.padding-self.filter_size)/self.stride + 1;
-        let mut output = DMatrix::zeros(oRows, oCols);
-        let pad_in = match self.padding {
-            0 => input.clone(),
-            _ => {
-                let mut padded = DMatrix::zeros(kRows+2*self.padding,kCols+2*self.padding);
-                padded.slice_mut((self.padding,self.padding),(kRows,kCols)).copy_from(input);
-                padded
+    //C×H×W in, F×H'×W' out
+    pub fn convolve(&self, input : &[DMatrix<f64>]) -> Tensor3 {
+        assert_eq!(input.len(), self.in_channels, "expected {} input channels, got {}", self.in_channels, input.len());
+        let pad_in = input.iter().map(|channel| pad(channel, self.padding)).collect::<Tensor3>();
+        self.convolve_padded(&pad_in)
+    }
+    fn convolve_padded(&self, pad_in : &[DMatrix<f64>]) -> Tensor3 {
+        let (p_rows, p_cols) = pad_in[0].shape();
+        let (o_rows, o_cols) = self.output_size(p_rows - 2*self.padding, p_cols - 2*self.padding);
+        let mut output = vec![DMatrix::zeros(o_rows, o_cols); self.filters.len()];
+        for (idx, filter) in self.filters.iter().enumerate() {
+            for i in 0..o_rows {
+                for j in 0..o_cols {
+                    let r_start = i * self.stride;
+                    let c_start = j * self.stride;
+                    let mut conv : f64 = self.bias[idx];
+                    for (kernel, channel) in filter.iter().zip(pad_in.iter()) {
+                        let curr_mat = channel.slice((r_start,c_start),(self.filter_size,self.filter_size));
+                        conv+=kernel.component_mul(&curr_mat).sum();
+                    }
+                    output[idx][(i,j)] = conv;
+                }
             }
-        };
-        for i in 0..oRows {
-            for j in 0..oCols {
-                for (idx, filter) in self.filters.iter().enumerate() {
-                    let rStart = i * self.stride;
-                    let cStart = j * self.stride;
-                    let currMat = pad_in.slice((rStart,cStart),(self.filter_size,self.filter_size));
-                    let mut conv : f64 = 0 as f64;
-                    for r in 0..self.filter_size {
-                        for c in 0..self.filter_size {
-                            conv+=filter[(r,c)] * currMat[(r,c)];
+        }
+        output
+    }
+    //input, filter and bias gradients for a single sample given its cached padded input
+    fn backward_sample(&self, err : &[DMatrix<f64>], pad_in : &[DMatrix<f64>]) -> (Tensor3, Vec<Tensor3>, DVector<f64>) {
+        let (o_rows, o_cols) = err[0].shape();
+        let (p_rows, p_cols) = pad_in[0].shape();
+        let k = self.filter_size;
+        let o_size = self.filters.len();
+
+        let mut p_gradient = vec![DMatrix::zeros(p_rows, p_cols); self.in_channels];
+        let mut f_gradient = vec![vec![DMatrix::zeros(k, k); self.in_channels]; o_size];
+        let mut b_gradient : DVector<f64> = DVector::zeros(o_size);
+
+        for idx in 0..o_size {
+            let err_mat = &err[idx];
+            b_gradient[idx] = err_mat.sum();
+            //dK[c](a,b) = sum_ij err(i,j) * P[c](i*s+a, j*s+b), the input correlated with the strided error
+            for (grad, channel) in f_gradient[idx].iter_mut().zip(pad_in.iter()) {
+                for f_row in 0..k {
+                    for f_col in 0..k {
+                        let mut total = 0.0;
+                        for i in 0..o_rows {
+                            for j in 0..o_cols {
+                                total += err_mat[(i,j)] * channel[(i * self.stride + f_row, j * self.stride + f_col)];
+                            }
                         }
+                        grad[(f_row, f_col)] = total;
+                    }
+                }
+            }
+            //dP is the full correlation of the stride-dilated error with the flipped kernel
+            let d_rows = (o_rows - 1) * self.stride + 1;
+            let d_cols = (o_cols - 1) * self.stride + 1;
+            let mut full = DMatrix::zeros(d_rows + 2*(k-1), d_cols + 2*(k-1));
+            for i in 0..o_rows {
+                for j in 0..o_cols {
+                    full[(k - 1 + i * self.stride, k - 1 + j * self.stride)] = err_mat[(i,j)];
+                }
+            }
+            for (grad, kernel) in p_gradient.iter_mut().zip(self.filters[idx].iter()) {
+                //rows past d_rows+k-1 were never read by the forward pass (stride remainder) and keep zero gradient
+                for row in 0..d_rows + k - 1 {
+                    for col in 0..d_cols + k - 1 {
+                        let mut total = 0.0;
+                        for f_row in 0..k {
+                            for f_col in 0..k {
+                                total += kernel[(k - 1 - f_row, k - 1 - f_col)] * full[(row + f_row, col + f_col)];
+                            }
+                        }
+                        grad[(row, col)] += total;
                     }
-                    conv+=self.bias[idx];
-                    output[(i,j)]+=conv;
                 }
             }
         }
-        return output;
+        //padding is not part of the input, so drop its border from the gradient
+        let i_gradient = p_gradient.iter()
+            .map(|grad| grad.slice((self.padding, self.padding), (p_rows - 2*self.padding, p_cols - 2*self.padding)).into_owned())
+            .collect::<Tensor3>();
+        (i_gradient, f_gradient, b_gradient)
     }
 }
-impl Layer for ConvLayer {
-    fn forward(&mut self, input : &DVector<f64>) -> DVector<f64> {
-        let matrix = DMatrix::from_row_slice(1, input.len(), input.as_slice());
-        let output = self.forward(&DVector::from_column_slice(&matrix.as_slice()));
-        return DVector::from_iterator(output.len(), output.iter().cloned());
-    }
-    fn backward(&mut self, error : &DVector<f64>, learn : f64) -> DVector<f64> {
-        let err_mat = DMatrix::from_row_slice(1, error.len(), error.as_slice());
-        let (iRows, iCols) = err_mat.shape();
-        let oSize = self.filters.len();
-
-        let mut iGradient = DMatrix::zeros(iRows, iCols);
-        let mut fGradient = vec![DMatrix::zeros(self.filter_size, self.filter_size); oSize];
-        let mut bGradient : DVector<f64> = DVector::zeros(oSize);
-
-        let pad_in = match self.padding {
-            0 => err_mat.clone(),
-            _=>{
-                let mut padded = DMatrix::zeros(iRows+2*self.padding, iCols + 2*self.padding,);
-                padded.slice_mut((self.padding,self.padding),(iRows, iCols)).copy_from(&err_mat);
-                padded
-            }
-        };
-
-        for idx in 0..oSize {
-            for i in 0..iRows {
-                for j in 0..iCols {
-                    for fRow in 0..self.filter_size {
-                        for fCol in 0..self.filter_size {
-                            let row = i * self.stride + fRow;
-                            let col = j * self.stride + fCol;
-                            if(row < pad_in.nrows() && col < pad_in.ncols()) {
-                                fGradient[idx][(fRow, fCol)] +=pad_in[(row,col)] * err_mat[(i,j)];
+impl ConvLayer {
+    //F × (C·k·k), row f holds filter f with channels, then kernel rows, laid end to end
+    fn filter_matrix(&self) -> DMatrix<f64> {
+        let kk = self.filter_size * self.filter_size;
+        DMatrix::from_fn(self.filters.len(), self.in_channels * kk, |f, r| {
+            let (c, a, b) = (r / kk, (r % kk) / self.filter_size, r % self.filter_size);
+            self.filters[f][c][(a, b)]
+        })
+    }
+    //(C·k·k) × (N·H'·W'): column n·L + i·W' + j is the patch under output (i,j) of sample n
+    fn im2col(&self, batch : &[Tensor3], o_rows : usize, o_cols : usize) -> DMatrix<f64> {
+        let k = self.filter_size;
+        let l = o_rows * o_cols;
+        let mut cols = DMatrix::zeros(self.in_channels * k * k, batch.len() * l);
+        for (n, pad_in) in batch.iter().enumerate() {
+            for (c, channel) in pad_in.iter().enumerate() {
+                for a in 0..k {
+                    for b in 0..k {
+                        let row = (c * k + a) * k + b;
+                        for i in 0..o_rows {
+                            for j in 0..o_cols {
+                                cols[(row, n * l + i * o_cols + j)] = channel[(i * self.stride + a, j * self.stride + b)];
                             }
                         }
                     }
-                    bGradient[idx] +=err_mat[(i,j)];
-                    for fRow in 0..self.filter_size {
-                        for fCol in 0..self.filter_size {
-                            let row = i * self.stride + fRow;
-                            let col = j * self.stride + fCol;
-
-                            if(row < iGradient.nrows() && col < iGradient.ncols()) {
-                                iGradient[(row,col)] +=self.filters[idx][(fRow,fCol)] * err_mat[(i,j)];
-                            }
+                }
+            }
+        }
+        cols
+    }
+    //inverse of im2col for gradients: overlapping patches are summed back into each padded sample
+    fn col2im(&self, cols : &DMatrix<f64>, n : usize, p_rows : usize, p_cols : usize, o_rows : usize, o_cols : usize) -> Tensor3 {
+        let k = self.filter_size;
+        let l = o_rows * o_cols;
+        let mut grad = vec![DMatrix::zeros(p_rows, p_cols); self.in_channels];
+        for (c, channel) in grad.iter_mut().enumerate() {
+            for a in 0..k {
+                for b in 0..k {
+                    let row = (c * k + a) * k + b;
+                    for i in 0..o_rows {
+                        for j in 0..o_cols {
+                            channel[(i * self.stride + a, j * self.stride + b)] += cols[(row, n * l + i * o_cols + j)];
                         }
                     }
                 }
             }
         }
-        for idx in 0..oSize {
-            self.filters[idx] -= learn * fGradient[idx].clone();
-            self.bias[idx] -= learn * bGradient[idx];
+        grad
+    }
+    fn forward_im2col(&mut self, o_rows : usize, o_cols : usize) -> DMatrix<f64> {
+        let l = o_rows * o_cols;
+        self.cols = self.im2col(&self.cache, o_rows, o_cols);
+        let out = self.filter_matrix() * &self.cols;
+        DMatrix::from_fn(self.filters.len() * l, self.cache.len(), |r, n| out[(r / l, n * l + r % l)] + self.bias[r / l])
+    }
+    fn backward_im2col(&mut self, error : &DMatrix<f64>, o_rows : usize, o_cols : usize) -> DMatrix<f64> {
+        let l = o_rows * o_cols;
+        let k = self.filter_size;
+        let kk = k * k;
+        let err_all = DMatrix::from_fn(self.filters.len(), error.ncols() * l, |f, col| error[(f * l + col % l, col / l)]);
+        let grad_w = &err_all * self.cols.transpose();
+        for (f, filter) in self.grad_filters.iter_mut().enumerate() {
+            for (c, kernel) in filter.iter_mut().enumerate() {
+                *kernel = DMatrix::from_fn(k, k, |a, b| grad_w[(f, c * kk + a * k + b)]);
+            }
+        }
+        self.grad_bias = err_all.column_sum();
+        let grad_cols = self.filter_matrix().transpose() * &err_all;
+        let (p_rows, p_cols) = self.cache[0][0].shape();
+        let columns = (0..error.ncols()).map(|n| {
+            let p_gradient = self.col2im(&grad_cols, n, p_rows, p_cols, o_rows, o_cols);
+            let i_gradient = p_gradient.iter()
+                .map(|grad| grad.slice((self.padding, self.padding), (p_rows - 2*self.padding, p_cols - 2*self.padding)).into_owned())
+                .collect::<Tensor3>();
+            flatten(&i_gradient)
+        }).collect::<Vec<DVector<f64>>>();
+        DMatrix::from_columns(&columns)
+    }
+}
+impl Layer for ConvLayer {
+    //each column is a flattened C×H×W image, each output column a flattened F×H'×W' map
+    fn forward(&mut self, input : &DMatrix<f64>) -> DMatrix<f64> {
+        let (k_rows, k_cols) = self.input_size(input.nrows());
+        self.cache = input.column_iter().map(|sample| {
+            let values = sample.iter().cloned().collect::<Vec<f64>>();
+            unflatten(&values, self.in_channels, k_rows, k_cols).iter().map(|channel| pad(channel, self.padding)).collect()
+        }).collect();
+        if self.algorithm == ConvAlgorithm::Im2col {
+            let (o_rows, o_cols) = self.output_size(k_rows, k_cols);
+            return self.forward_im2col(o_rows, o_cols);
         }
-        return DVector::from_iterator(iGradient.len(), iGradient.iter().cloned());
+        let columns = self.cache.iter().map(|pad_in| flatten(&self.convolve_padded(pad_in))).collect::<Vec<DVector<f64>>>();
+        DMatrix::from_columns(&columns)
+    }
+    fn backward(&mut self, error : &DMatrix<f64>) -> DMatrix<f64> {
+        assert_eq!(error.ncols(), self.cache.len(), "backward called with a different batch size than forward");
+        let (p_rows, p_cols) = self.cache.first().map_or((2*self.padding, 2*self.padding), |pad_in| pad_in[0].shape());
+        let (o_rows, o_cols) = self.output_size(p_rows - 2*self.padding, p_cols - 2*self.padding);
+        if self.algorithm == ConvAlgorithm::Im2col {
+            return self.backward_im2col(error, o_rows, o_cols);
         }
+        let o_size = self.filters.len();
+        let mut f_total = vec![vec![DMatrix::zeros(self.filter_size, self.filter_size); self.in_channels]; o_size];
+        let mut b_total : DVector<f64> = DVector::zeros(o_size);
+        let columns = error.column_iter().zip(self.cache.iter()).map(|(sample, pad_in)| {
+            let values = sample.iter().cloned().collect::<Vec<f64>>();
+            let err = unflatten(&values, o_size, o_rows, o_cols);
+            let (i_gradient, f_gradient, b_gradient) = self.backward_sample(&err, pad_in);
+            for (total, grad) in f_total.iter_mut().flatten().zip(f_gradient.iter().flatten()) {
+                *total += grad;
+            }
+            b_total += b_gradient;
+            flatten(&i_gradient)
+        }).collect::<Vec<DVector<f64>>>();
+        self.grad_filters = f_total;
+        self.grad_bias = b_total;
+        DMatrix::from_columns(&columns)
+    }
+    fn parameters(&mut self) -> Vec<Parameter<'_>> {
+        let mut params : Vec<Parameter> = self.filters.iter_mut().flatten().zip(self.grad_filters.iter().flatten())
+            .map(|(kernel, grad)| Parameter { value : kernel.as_mut_slice(), grad : grad.as_slice() })
+            .collect();
+        params.push(Parameter { value : self.bias.as_mut_slice(), grad : self.grad_bias.as_slice() });
+        params
     }
+    fn spec(&self) -> Option<LayerSpec> {
+        Some(LayerSpec::Conv {
+            filters : self.filters.clone(),
+            bias : self.bias.clone(),
+            stride : self.stride,
+            padding : self.padding,
+            in_rows : self.in_rows,
+            in_cols : self.in_cols,
+        })
+    }
+    //im2col patches times the filter matrix, with every rearrangement expressed as a gather
+    fn forward_tape(&self, input : &Var, params : &[Var]) -> Option<Var> {
+        let (k_rows, k_cols) = self.input_size(input.rows());
+        let (o_rows, o_cols) = self.output_size(k_rows, k_cols);
+        let (f, c, k) = (self.filters.len(), self.in_channels, self.filter_size);
+        let (kk, l, n) = (k * k, o_rows * o_cols, input.cols());
+        let in_len = c * k_rows * k_cols;
+        //(C·k·k) × (N·L) like im2col, padding reads as zero
+        let mut index = Vec::with_capacity(c * kk * n * l);
+        for col in 0..n * l {
+            let (sample, i, j) = (col / l, (col % l) / o_cols, col % o_cols);
+            for row in 0..c * kk {
+                let (ch, a, b) = (row / kk, (row % kk) / k, row % k);
+                let (r, q) = ((i * self.stride + a).wrapping_sub(self.padding), (j * self.stride + b).wrapping_sub(self.padding));
+                index.push(if r < k_rows && q < k_cols {Some(sample * in_len + (ch * k_rows + r) * k_cols + q)} else {None});
+            }
+        }
+        let patches = input.gather(c * kk, n * l, index);
+        //one column-major k×k leaf per (filter, channel), laid out like filter_matrix
+        let kernels = stack(&params[..f * c]);
+        let weights = kernels.gather(f, c * kk, (0..f * c * kk).map(|e| {
+            let (row, col) = (e % f, e / f);
+            let (ch, a, b) = (col / kk, (col % kk) / k, col % k);
+            Some((row * c + ch) * kk + b * k + a)
+        }).collect());
+        let out = weights.matmul(&patches);
+        //F × (N·L) back to one flattened F×H'×W' map per column, plus the bias of each filter
+        let rows = f * l;
+        let maps = out.gather(rows, n, (0..rows * n).map(|e| {
+            let (r, sample) = (e % rows, e / rows);
+            Some((sample * l + r % l) * f + r / l)
+        }).collect());
+        let bias = params[f * c].gather(rows, n, (0..rows * n).map(|e| Some((e % rows) / l)).collect());
+        Some(maps.add(&bias))
+    }
+    fn input_shape(&self) -> Option<Shape> {
+        match self.in_rows {
+            0 => None,
+            _ => Some(Shape::new(self.in_channels, self.in_rows, self.in_cols)),
+        }
+    }
+    fn build(&mut self, input : Shape) -> Result<Shape, ShapeError> {
+        if input.channels != self.in_channels {
+            return Err(ShapeError::new(format!("expects {} input channels but receives {}", self.in_channels, input.channels)));
+        }
+        if self.in_rows > 0 && (input.rows, input.cols) != (self.in_rows, self.in_cols) {
+            return Err(ShapeError::new(format!("was configured for {}x{} images but receives {}x{}", self.in_rows, self.in_cols, input.rows, input.cols)));
+        }
+        if input.rows+2*self.padding < self.filter_size || input.cols+2*self.padding < self.filter_size {
+            return Err(ShapeError::new(format!("{}x{} filter does not fit {}x{} images with padding {}", self.filter_size, self.filter_size, input.rows, input.cols, self.padding)));
+        }
+        self.in_rows = input.rows;
+        self.in_cols = input.cols;
+        let (o_rows, o_cols) = self.output_size(input.rows, input.cols);
+        Ok(Shape::new(self.filters.len(), o_rows, o_cols))
+    }
+}
+//cLayers, dLayers and getLoss keep the names the crate has always used
+#[allow(non_snake_case)]
 pub struct CNN {
+    input_shape : Shape,
+    output_shape : Shape,
     cLayers : Vec<Box<dyn Layer>>,
     dLayers : Vec<Box<dyn Layer>>,
     loss : Box<dyn Loss>,
+    optimizer : Box<dyn Optimizer>,
+    schedule : Option<Schedule>, //applied by fit
 }
 
+#[allow(non_snake_case)]
 impl CNN {
-    pub fn new(cLayers : Vec<Box<dyn Layer>>, dLayers : Vec<Box<dyn Layer>>, loss : Box<dyn Loss>) -> Self {
-        CNN {
+    //checks that every layer accepts what the previous one produces for `input_shape` images
+    pub fn new(input_shape : Shape, cLayers : Vec<Box<dyn Layer>>, dLayers : Vec<Box<dyn Layer>>, loss : Box<dyn Loss>) -> Result<Self, ShapeError> {
+        Self::with_optimizer(input_shape, cLayers, dLayers, loss, Box::new(Sgd::new(0.01)))
+    }
+    pub fn with_optimizer(input_shape : Shape, mut cLayers : Vec<Box<dyn Layer>>, mut dLayers : Vec<Box<dyn Layer>>, loss : Box<dyn Loss>, optimizer : Box<dyn Optimizer>) -> Result<Self, ShapeError> {
+        let conv_shape = build_stack(&mut cLayers, input_shape, "convolutional")?;
+        let output_shape = build_stack(&mut dLayers, conv_shape, "dense")?;
+        Ok(CNN {
+            input_shape,
+            output_shape,
             cLayers,
             dLayers,
             loss,
-        }
+            optimizer,
+            schedule : None,
+        })
+    }
+    pub fn input_shape(&self) -> Shape {
+        self.input_shape
+    }
+    pub fn output_shape(&self) -> Shape {
+        self.output_shape
+    }
+    pub fn set_optimizer(&mut self, optimizer : Box<dyn Optimizer>) {
+        self.optimizer = optimizer;
+    }
+    pub fn set_scheduler(&mut self, scheduler : Box<dyn LrScheduler>, interval : Interval) {
+        self.schedule = Some(Schedule::new(scheduler, interval));
     }
     pub fn getLoss(&self) -> &dyn Loss {
-        return &*self.loss;
+        &*self.loss
     }
     pub fn forward(&mut self, input : &DVector<f64>) -> DVector<f64> {
+        let output = self.forward_batch(&to_batch(input));
+        output.column(0).into_owned()
+    }
+    pub fn forward_batch(&mut self, input : &DMatrix<f64>) -> DMatrix<f64> {
         let mut output = input.clone();
         for layer in self.cLayers.iter_mut() {
             output = layer.forward(&output);
@@ -142,22 +467,218 @@ impl CNN {
         for layer in self.dLayers.iter_mut() {
             output = layer.forward(&output);
         }
-        return output;
+        output
     }
     pub fn backprop(&mut self, input : &DVector<f64>, target : &DVector<f64>, learn : f64) {
-        let output = self.forward(input);
-        let mut error = self.loss.gradient(&output, target);
+        self.optimizer.set_learning_rate(learn);
+        self.backprop_batch(&to_batch(input), &to_batch(target));
+    }
+    pub fn backprop_batch(&mut self, input : &DMatrix<f64>, target : &DMatrix<f64>) -> f64 {
+        let output = self.forward_batch(input);
+        let loss = self.loss.compute_batch(&output, target);
+        let error = self.loss.gradient_batch(&output, target);
+        self.backward_batch(&error);
+        self.step();
+        loss
+    }
+    pub fn spec(&self) -> io::Result<ModelSpec> {
+        Ok(ModelSpec {
+            stacks : vec![serialization::describe_layers(&self.cLayers)?, serialization::describe_layers(&self.dLayers)?],
+            loss : self.loss.name(),
+        })
+    }
+    pub fn from_spec(spec : ModelSpec) -> io::Result<Self> {
+        let loss = serialization::build_loss(&spec.loss)?;
+        let mut stacks = spec.stacks.into_iter();
+        match (stacks.next(), stacks.next(), stacks.next()) {
+            (Some(cLayers), Some(dLayers), None) => {
+                let cLayers = serialization::build_layers(cLayers)?;
+                let dLayers = serialization::build_layers(dLayers)?;
+                let input_shape = cLayers.iter().chain(dLayers.iter()).next().and_then(|layer| layer.input_shape())
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidData, "saved model does not record its input shape"))?;
+                Self::new(input_shape, cLayers, dLayers, loss).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+            }
+            _ => Err(Error::new(ErrorKind::InvalidData, "expected convolutional and dense layer stacks")),
+        }
+    }
+    //versioned binary file with a checksum; the optimizer state is not saved
+    pub fn save<P : AsRef<Path>>(&self, path : P) -> io::Result<()> {
+        serialization::save_binary(&self.spec()?, path)
+    }
+    pub fn load<P : AsRef<Path>>(path : P) -> io::Result<Self> {
+        Self::from_spec(serialization::load_binary(path)?)
+    }
+    pub fn save_json<P : AsRef<Path>>(&self, path : P) -> io::Result<()> {
+        serialization::save_json(&self.spec()?, path)
+    }
+    pub fn load_json<P : AsRef<Path>>(path : P) -> io::Result<Self> {
+        Self::from_spec(serialization::load_json(path)?)
+    }
+    pub fn fit(&mut self, inputs : &[DVector<f64>], targets : &[DVector<f64>], batch_size : usize, epochs : usize) -> Vec<f64> {
+        let mut schedule = self.schedule.take();
+        let history = trainer::fit_samples(self, &mut schedule, inputs, targets, batch_size, epochs);
+        self.schedule = schedule;
+        history
+    }
+    pub fn train(&mut self, input : &DVector<f64>, target : &DVector<f64>, learn : f64, epochs : usize) {
+        for _ in 0..epochs {
+            self.backprop(input,target,learn);
+        }
+    }
+    //outputs for every sample, one per column
+    pub fn predict(&mut self, inputs : &[DVector<f64>]) -> DMatrix<f64> {
+        metrics::predict_batches(inputs, |batch| self.forward_batch(batch))
+    }
+    //loss on the whole set, plus the outputs for accuracy, confusion matrix, r2 and friends
+    pub fn evaluate(&mut self, inputs : &[DVector<f64>], targets : &[DVector<f64>]) -> Evaluation {
+        let output = self.predict(inputs);
+        Evaluation::new(&*self.loss, output, targets)
+    }
+}
+
+impl Model for CNN {
+    fn forward_batch(&mut self, input : &DMatrix<f64>) -> DMatrix<f64> {
+        CNN::forward_batch(self, input)
+    }
+    fn backward_batch(&mut self, error : &DMatrix<f64>) -> DMatrix<f64> {
+        let mut error = error.clone();
         for layer in self.dLayers.iter_mut().rev() {
-            error = layer.backward(&error, learn);
+            error = layer.backward(&error);
         }
 
         for layer in self.cLayers.iter_mut().rev() {
-            error = layer.backward(&error, learn);
+            error = layer.backward(&error);
         }
+        error
     }
-    pub fn train(&mut self, input : &DVector<f64>, target : &DVector<f64>, learn : f64, epochs : usize) {
-        for _ in 0..epochs {
-            self.backprop(input,target,learn);
+    fn parameters(&mut self) -> Vec<Parameter<'_>> {
+        self.cLayers.iter_mut().chain(self.dLayers.iter_mut())
+            .flat_map(|layer| layer.parameters())
+            .collect()
+    }
+    fn loss(&self) -> &dyn Loss {
+        &*self.loss
+    }
+    fn optimizer(&mut self) -> &mut dyn Optimizer {
+        &mut *self.optimizer
+    }
+    fn step(&mut self) {
+        let mut params : Vec<Parameter> = self.cLayers.iter_mut().chain(self.dLayers.iter_mut())
+            .flat_map(|layer| layer.parameters())
+            .collect();
+        self.optimizer.step(&mut params);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use crate::activation::Identity;
+    use crate::gradcheck::{assert_close, central_difference};
+    use crate::neuralnetwork::{DenseLayer, MeanSquaredError};
+    use crate::shape::Flatten;
+
+    //(channels, filters, kernel, stride, padding, rows, cols); the strided ones leave a remainder
+    //the forward pass never reads
+    const CONFIGS : [(usize, usize, usize, usize, usize, usize, usize); 5] = [
+        (1, 2, 3, 1, 0, 5, 5),
+        (2, 3, 3, 2, 1, 7, 6),
+        (3, 2, 2, 3, 2, 6, 8),
+        (2, 1, 4, 2, 0, 9, 7),
+        (1, 2, 1, 2, 1, 4, 5),
+    ];
+
+    fn layer(config : (usize, usize, usize, usize, usize, usize, usize), algorithm : ConvAlgorithm, seed : u64) -> ConvLayer {
+        let (channels, filters, k, stride, padding, rows, cols) = config;
+        ConvLayer::new_rng(channels, filters, k, stride, padding, &mut rng::seeded(seed)).with_input_size(rows, cols).with_algorithm(algorithm)
+    }
+
+    fn batch(rows : usize, cols : usize, seed : u64) -> DMatrix<f64> {
+        let mut r_vals = rng::seeded(seed);
+        DMatrix::from_fn(rows, cols, |_, _| r_vals.gen_range(-1.0..1.0))
+    }
+
+    //adds delta to entry i of the parameters taken end to end
+    fn nudge(layer : &mut ConvLayer, mut i : usize, delta : f64) {
+        for param in layer.parameters() {
+            if i < param.value.len() {
+                param.value[i] += delta;
+                return;
+            }
+            i -= param.value.len();
+        }
+    }
+
+    fn gradients(layer : &mut ConvLayer) -> Vec<f64> {
+        layer.parameters().iter().flat_map(|param| param.grad.to_vec()).collect()
+    }
+
+    #[test]
+    fn backward_matches_central_differences() {
+        for (n, &config) in CONFIGS.iter().enumerate() {
+            for algorithm in [ConvAlgorithm::Naive, ConvAlgorithm::Im2col] {
+                let (channels, _, _, _, _, rows, cols) = config;
+                let mut layer = layer(config, algorithm, n as u64);
+                let mut input = batch(channels * rows * cols, 3, 100 + n as u64);
+                let output = layer.forward(&input);
+                let target = batch(output.nrows(), 3, 200 + n as u64);
+                let loss = MeanSquaredError;
+                let grad_input = layer.backward(&loss.gradient_batch(&output, &target));
+                let exact = gradients(&mut layer);
+                let len = exact.len();
+                let numeric = central_difference(len, |i, delta| {
+                    nudge(&mut layer, i, delta);
+                    let value = loss.compute_batch(&layer.forward(&input), &target);
+                    nudge(&mut layer, i, -delta);
+                    value
+                });
+                assert_close(&format!("{:?} {:?} kernel and bias gradient", algorithm, config), &exact, &numeric, 1e-7);
+                let numeric = central_difference(input.len(), |i, delta| {
+                    input[i] += delta;
+                    let value = loss.compute_batch(&layer.forward(&input), &target);
+                    input[i] -= delta;
+                    value
+                });
+                assert_close(&format!("{:?} {:?} input gradient", algorithm, config), grad_input.as_slice(), &numeric, 1e-7);
+            }
         }
     }
+
+    #[test]
+    fn naive_and_im2col_agree() {
+        for (n, &config) in CONFIGS.iter().enumerate() {
+            let (channels, _, _, _, _, rows, cols) = config;
+            let mut naive = layer(config, ConvAlgorithm::Naive, n as u64);
+            let mut im2col = layer(config, ConvAlgorithm::Im2col, n as u64);
+            let input = batch(channels * rows * cols, 4, 100 + n as u64);
+            let output = naive.forward(&input);
+            assert!((&output - im2col.forward(&input)).abs().max() < 1e-12, "{:?}: outputs differ", config);
+            let error = batch(output.nrows(), 4, 200 + n as u64);
+            let grad_input = naive.backward(&error);
+            assert!((grad_input - im2col.backward(&error)).abs().max() < 1e-12, "{:?}: input gradients differ", config);
+            assert_close(&format!("{:?} parameter gradient", config), &gradients(&mut naive), &gradients(&mut im2col), 1e-12);
+        }
+    }
+
+    fn build_error(input : Shape, conv : Vec<Box<dyn Layer>>, dense : Vec<Box<dyn Layer>>) -> String {
+        match CNN::new(input, conv, dense, Box::new(MeanSquaredError)) {
+            Err(e) => e.to_string(),
+            Ok(_) => panic!("built a CNN from mismatched layers"),
+        }
+    }
+
+    #[test]
+    fn mismatched_stacks_name_the_layer_and_shapes() {
+        let mut r_vals = rng::seeded(7);
+        let conv = |channels : usize, r_vals : &mut StdRng| Box::new(ConvLayer::new_rng(channels, 2, 3, 1, 0, r_vals)) as Box<dyn Layer>;
+        //2x4x4 = 32 values reach a dense layer that wants 10
+        let message = build_error(Shape::new(1, 6, 6), vec![conv(1, &mut r_vals)],
+                                  vec![Box::new(Flatten), Box::new(DenseLayer::with_activation_rng(10, 3, Box::new(Identity), &mut r_vals))]);
+        assert_eq!(message, "dense layer 1 (input 32): expects 10 inputs but receives 32 values");
+        let message = build_error(Shape::new(1, 6, 6), vec![conv(1, &mut r_vals), conv(3, &mut r_vals)], vec![Box::new(Flatten)]);
+        assert_eq!(message, "convolutional layer 1 (input 2x4x4): expects 3 input channels but receives 2");
+        let message = build_error(Shape::new(1, 2, 2), vec![conv(1, &mut r_vals)], vec![]);
+        assert_eq!(message, "convolutional layer 0 (input 1x2x2): 3x3 filter does not fit 2x2 images with padding 0");
+    }
 }
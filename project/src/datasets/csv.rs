@@ -0,0 +1,401 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use nalgebra::DVector;
+use serde_json::{json, Value};
+use crate::datasets::mnist::one_hot;
+use crate::datasets::{Dataset, InMemoryDataset};
+use crate::serialization::{self, field, str_field, usize_field};
+
+const JSON_FORMAT : &str = "rust-ai-csv-encoder";
+//fields that count as missing values
+const MISSING : [&str; 7] = ["", "NA", "N/A", "NaN", "nan", "null", "?"];
+
+fn invalid(message : String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+//splits one line on commas; double quotes group a field and "" inside them is a literal quote
+pub fn split_record(line : &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_string()),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_string());
+    fields
+}
+
+//header and records of a CSV file, blank lines skipped; errors here and in CsvReader and
+//CsvEncoder count rows from 1 at the first record after the header
+pub fn read_records(text : &str) -> Result<(Vec<String>, Vec<Vec<String>>)> {
+    let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+    let header = lines.next().map(split_record).ok_or_else(|| invalid("CSV file is empty".to_string()))?;
+    let records = lines.enumerate().map(|(row, line)| {
+        let record = split_record(line);
+        if record.len() != header.len() {
+            return Err(invalid(format!("row {} has {} fields, the header has {}", row + 1, record.len(), header.len())));
+        }
+        Ok(record)
+    }).collect::<Result<Vec<Vec<String>>>>()?;
+    Ok((header, records))
+}
+
+//how missing numeric values are filled in; categorical columns always use their most frequent category
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Imputation {
+    Mean,
+    Median,
+    MostFrequent,
+    Constant(f64),
+}
+
+fn is_missing(field : &str) -> bool {
+    MISSING.contains(&field)
+}
+
+//numeric order when every value is a number (so "10" comes after "9"), otherwise lexicographic
+fn sort_categories(values : &mut [String]) {
+    if values.iter().all(|v| v.parse::<f64>().is_ok()) {
+        values.sort_by(|a, b| a.parse::<f64>().unwrap().total_cmp(&b.parse::<f64>().unwrap()));
+    }
+    else {
+        values.sort();
+    }
+}
+
+//first of the most common values in `sorted`
+fn mode<T : PartialEq + Copy>(sorted : &[T]) -> Option<T> {
+    let mut best : Option<(T, usize)> = None;
+    let mut start = 0;
+    for end in 1..=sorted.len() {
+        if end == sorted.len() || sorted[end] != sorted[start] {
+            if best.is_none_or(|(_, count)| end - start > count) {
+                best = Some((sorted[start], end - start));
+            }
+            start = end;
+        }
+    }
+    best.map(|(value, _)| value)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ColumnEncoding {
+    Numeric { fill : f64 },
+    //one-hot over `categories`; unseen categories encode as all zeros
+    Categorical { categories : Vec<String>, fill : usize },
+}
+
+//what to read from a CSV file with a header row; `fit` learns the encoding from training data
+#[derive(Debug, Clone)]
+pub struct CsvReader {
+    target : String,
+    features : Option<Vec<String>>,
+    categorical : Vec<String>,
+    imputation : Imputation,
+    classification : bool,
+}
+
+impl CsvReader {
+    //by default every other column is a numeric feature and the target is a regression value
+    pub fn new(target : &str) -> Self {
+        CsvReader {
+            target : target.to_string(),
+            features : None,
+            categorical : Vec::new(),
+            imputation : Imputation::Mean,
+            classification : false,
+        }
+    }
+    pub fn with_features(mut self, names : &[&str]) -> Self {
+        self.features = Some(names.iter().map(|name| name.to_string()).collect());
+        self
+    }
+    //columns to one-hot encode instead of parsing as numbers
+    pub fn with_categorical(mut self, names : &[&str]) -> Self {
+        self.categorical = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+    pub fn with_imputation(mut self, imputation : Imputation) -> Self {
+        self.imputation = imputation;
+        self
+    }
+    //treats the target as class labels and one-hot encodes it
+    pub fn with_classification(mut self, classification : bool) -> Self {
+        self.classification = classification;
+        self
+    }
+
+    pub fn fit(&self, text : &str) -> Result<CsvEncoder> {
+        let (header, records) = read_records(text)?;
+        let position = |name : &str| header.iter().position(|h| h == name)
+            .ok_or_else(|| invalid(format!("no column named {}", name)));
+        let names = match &self.features {
+            Some(names) => names.clone(),
+            None => header.iter().filter(|&name| *name != self.target).cloned().collect(),
+        };
+        if let Some(name) = self.categorical.iter().find(|&name| !names.contains(name)) {
+            return Err(invalid(format!("categorical column {} is not one of the features", name)));
+        }
+        let mut columns = Vec::with_capacity(names.len());
+        for name in names {
+            let index = position(&name)?;
+            //(row, field) so parse errors can name the row the field came from
+            let present = records.iter().map(|record| record[index].as_str()).enumerate().filter(|(_, field)| !is_missing(field));
+            let encoding = if self.categorical.contains(&name) {
+                let mut counts = HashMap::new();
+                for (_, field) in present {
+                    *counts.entry(field.to_string()).or_insert(0) += 1;
+                }
+                let mut categories = counts.keys().cloned().collect::<Vec<String>>();
+                sort_categories(&mut categories);
+                let fill = (0..categories.len()).rev().max_by_key(|&i| counts[&categories[i]]).unwrap_or(0);
+                ColumnEncoding::Categorical { categories, fill }
+            }
+            else {
+                let mut values = present
+                    .map(|(row, field)| field.parse::<f64>()
+                        .map_err(|_| invalid(format!("row {}, column {}: {:?} is not a number (mark the column categorical?)", row + 1, name, field))))
+                    .collect::<Result<Vec<f64>>>()?;
+                values.sort_by(f64::total_cmp);
+                let fill = match self.imputation {
+                    _ if values.is_empty() => 0.0,
+                    Imputation::Mean => values.iter().sum::<f64>() / values.len() as f64,
+                    Imputation::Median => {
+                        let mid = values.len() / 2;
+                        if values.len() % 2 == 0 {(values[mid - 1] + values[mid]) / 2.0} else {values[mid]}
+                    }
+                    Imputation::MostFrequent => mode(&values).unwrap_or(0.0),
+                    Imputation::Constant(value) => value,
+                };
+                ColumnEncoding::Numeric { fill }
+            };
+            columns.push((name, encoding));
+        }
+        let target_index = position(&self.target)?;
+        let classes = if self.classification {
+            let mut classes = records.iter().map(|record| record[target_index].clone()).filter(|field| !is_missing(field)).collect::<Vec<String>>();
+            sort_categories(&mut classes);
+            classes.dedup();
+            Some(classes)
+        }
+        else {
+            None
+        };
+        Ok(CsvEncoder { columns, target : self.target.clone(), classes })
+    }
+    pub fn fit_file<P : AsRef<Path>>(&self, path : P) -> Result<CsvEncoder> {
+        self.fit(&fs::read_to_string(path)?)
+    }
+    //fits on the file and encodes it; use the returned encoder for the test set and at inference
+    pub fn load<P : AsRef<Path>>(&self, path : P) -> Result<(CsvDataset, CsvEncoder)> {
+        let text = fs::read_to_string(path)?;
+        let encoder = self.fit(&text)?;
+        Ok((encoder.encode(&text)?, encoder))
+    }
+}
+
+//the fitted column layout, category lists and fill values; columns are matched by header name
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvEncoder {
+    columns : Vec<(String, ColumnEncoding)>,
+    target : String,
+    classes : Option<Vec<String>>, //None for a regression target
+}
+
+impl CsvEncoder {
+    //length of the encoded input vectors
+    pub fn input_len(&self) -> usize {
+        self.columns.iter().map(|(_, encoding)| match encoding {
+            ColumnEncoding::Numeric { .. } => 1,
+            ColumnEncoding::Categorical { categories, .. } => categories.len(),
+        }).sum()
+    }
+    //one name per input entry, one-hot entries as column=category
+    pub fn feature_names(&self) -> Vec<String> {
+        self.columns.iter().flat_map(|(name, encoding)| match encoding {
+            ColumnEncoding::Numeric { .. } => vec![name.clone()],
+            ColumnEncoding::Categorical { categories, .. } => categories.iter().map(|c| format!("{}={}", name, c)).collect(),
+        }).collect()
+    }
+    pub fn classes(&self) -> Option<&[String]> {
+        self.classes.as_deref()
+    }
+
+    //one record's feature fields, in the order of `columns`
+    pub fn encode_features(&self, fields : &[&str]) -> Result<DVector<f64>> {
+        if fields.len() != self.columns.len() {
+            return Err(invalid(format!("expected {} feature fields, got {}", self.columns.len(), fields.len())));
+        }
+        let mut values = Vec::with_capacity(self.input_len());
+        for ((name, encoding), &field) in self.columns.iter().zip(fields) {
+            match encoding {
+                ColumnEncoding::Numeric { fill } => values.push(if is_missing(field) {*fill} else {
+                    field.parse::<f64>().map_err(|_| invalid(format!("column {}: {:?} is not a number", name, field)))?
+                }),
+                ColumnEncoding::Categorical { categories, fill } => {
+                    let hot = if is_missing(field) {Some(*fill)} else {categories.iter().position(|c| c == field)};
+                    values.extend((0..categories.len()).map(|i| if Some(i) == hot {1.0} else {0.0}));
+                }
+            }
+        }
+        Ok(DVector::from_vec(values))
+    }
+    pub fn encode_target(&self, field : &str) -> Result<DVector<f64>> {
+        if is_missing(field) {
+            return Err(invalid(format!("target {} is missing", self.target)));
+        }
+        match &self.classes {
+            Some(classes) => classes.iter().position(|c| c == field)
+                .map(|class| one_hot(class, classes.len()))
+                .ok_or_else(|| invalid(format!("unknown class {:?}", field))),
+            None => field.parse::<f64>().map(|v| DVector::from_element(1, v))
+                .map_err(|_| invalid(format!("target {:?} is not a number", field))),
+        }
+    }
+    pub fn encode(&self, text : &str) -> Result<CsvDataset> {
+        let (header, records) = read_records(text)?;
+        let position = |name : &str| header.iter().position(|h| h == name)
+            .ok_or_else(|| invalid(format!("no column named {}", name)));
+        let indices = self.columns.iter().map(|(name, _)| position(name)).collect::<Result<Vec<usize>>>()?;
+        let target_index = position(&self.target)?;
+        let mut inputs = Vec::with_capacity(records.len());
+        let mut targets = Vec::with_capacity(records.len());
+        for (row, record) in records.iter().enumerate() {
+            let fields = indices.iter().map(|&i| record[i].as_str()).collect::<Vec<&str>>();
+            let at_row = |e : Error| invalid(format!("row {}: {}", row + 1, e));
+            inputs.push(self.encode_features(&fields).map_err(at_row)?);
+            targets.push(self.encode_target(&record[target_index]).map_err(at_row)?);
+        }
+        Ok(CsvDataset { features : self.feature_names(), samples : InMemoryDataset::new(inputs, targets) })
+    }
+    pub fn load<P : AsRef<Path>>(&self, path : P) -> Result<CsvDataset> {
+        self.encode(&fs::read_to_string(path)?)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let columns = self.columns.iter().map(|(name, encoding)| match encoding {
+            ColumnEncoding::Numeric { fill } => json!({
+                "name" : name,
+                "type" : "numeric",
+                "fill" : fill,
+            }),
+            ColumnEncoding::Categorical { categories, fill } => json!({
+                "name" : name,
+                "type" : "categorical",
+                "categories" : categories,
+                "fill" : fill,
+            }),
+        }).collect::<Vec<Value>>();
+        json!({
+            "format" : JSON_FORMAT,
+            "target" : self.target,
+            "classes" : self.classes,
+            "columns" : columns,
+        })
+    }
+    pub fn from_json(v : &Value) -> Result<Self> {
+        if field(v, "format")?.as_str() != Some(JSON_FORMAT) {
+            return Err(invalid("not a CSV encoder file (bad format tag)".to_string()));
+        }
+        let strings = |v : &Value| v.as_array().ok_or_else(|| invalid("expected an array of strings".to_string()))?
+            .iter().map(|s| s.as_str().map(|s| s.to_string()).ok_or_else(|| invalid("expected a string".to_string())))
+            .collect::<Result<Vec<String>>>();
+        let classes = match field(v, "classes")? {
+            Value::Null => None,
+            classes => Some(strings(classes)?),
+        };
+        let columns = field(v, "columns")?.as_array().ok_or_else(|| invalid("'columns' is not an array".to_string()))?
+            .iter().map(|column| {
+                let encoding = match str_field(column, "type")?.as_str() {
+                    "numeric" => ColumnEncoding::Numeric {
+                        fill : field(column, "fill")?.as_f64().ok_or_else(|| invalid("field 'fill' is not a number".to_string()))?,
+                    },
+                    "categorical" => ColumnEncoding::Categorical {
+                        categories : strings(field(column, "categories")?)?,
+                        fill : usize_field(column, "fill")?,
+                    },
+                    other => return Err(invalid(format!("unknown column type '{}'", other))),
+                };
+                Ok((str_field(column, "name")?, encoding))
+            }).collect::<Result<Vec<(String, ColumnEncoding)>>>()?;
+        Ok(CsvEncoder { columns, target : str_field(v, "target")?, classes })
+    }
+    pub fn save_json<P : AsRef<Path>>(&self, path : P) -> Result<()> {
+        serialization::write_json(&self.to_json(), path)
+    }
+    pub fn load_json<P : AsRef<Path>>(path : P) -> Result<Self> {
+        Self::from_json(&serialization::read_json(path)?)
+    }
+}
+
+//the encoded records of a CSV file, from CsvReader::load or CsvEncoder::encode
+pub struct CsvDataset {
+    features : Vec<String>, //CsvEncoder::feature_names, one per input entry
+    samples : InMemoryDataset,
+}
+
+impl CsvDataset {
+    pub fn feature_names(&self) -> &[String] {
+        &self.features
+    }
+    pub fn into_inner(self) -> InMemoryDataset {
+        self.samples
+    }
+}
+
+impl Dataset for CsvDataset {
+    fn len(&self) -> usize {
+        self.samples.len()
+    }
+    fn get(&self, index : usize) -> (DVector<f64>, DVector<f64>) {
+        self.samples.get(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_errors_name_the_row_after_missing_values() {
+        let text = "x,y\nNA,1\n?,2\nabc,3\n";
+        let message = CsvReader::new("y").fit(text).unwrap_err().to_string();
+        assert!(message.starts_with("row 3, column x"), "{}", message);
+    }
+
+    #[test]
+    fn fits_and_encodes_with_imputation() {
+        let text = "size,colour,label\n1,red,a\nNA,blue,b\n3,,a\n";
+        let encoder = CsvReader::new("label").with_categorical(&["colour"]).with_classification(true).fit(text).unwrap();
+        assert_eq!(encoder.feature_names(), vec!["size", "colour=blue", "colour=red"]);
+        let data = encoder.encode(text).unwrap();
+        assert_eq!(data.len(), 3);
+        assert_eq!(data.get(1).0.as_slice(), &[2.0, 1.0, 0.0]);
+        assert_eq!(data.get(2).1.as_slice(), &[1.0, 0.0]);
+        assert_eq!(data.feature_names(), encoder.feature_names());
+    }
+
+    #[test]
+    fn every_error_counts_rows_the_same_way() {
+        //the header and the blank line are not rows, so the bad record is row 2 each time
+        let short = read_records("x,y\n1,1\n\n2\n").unwrap_err().to_string();
+        assert!(short.starts_with("row 2 has 1 fields"), "{}", short);
+        let encoder = CsvReader::new("y").fit("x,y\n1,1\n2,2\n").unwrap();
+        let target = encoder.encode("x,y\n1,1\n\n2,b\n").err().expect("a bad target was encoded").to_string();
+        assert!(target.starts_with("row 2: "), "{}", target);
+        let feature = CsvReader::new("y").fit("x,y\n1,1\n\nb,2\n").unwrap_err().to_string();
+        assert!(feature.starts_with("row 2, column x"), "{}", feature);
+    }
+}
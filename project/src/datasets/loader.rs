@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+use nalgebra::{DMatrix, DVector};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use crate::datasets::{Dataset, Subset};
+use crate::rng;
+
+//yields (inputs, targets) batches with one sample per column
+pub struct DataLoader<'a> {
+    dataset : &'a dyn Dataset,
+    batch_size : usize,
+    shuffle : bool,
+    drop_last : bool,
+    rng : StdRng,
+}
+
+impl<'a> DataLoader<'a> {
+    pub fn new(dataset : &'a dyn Dataset, batch_size : usize) -> Self {
+        DataLoader {
+            dataset,
+            batch_size : batch_size.max(1),
+            shuffle : false,
+            drop_last : false,
+            rng : rng::fork(),
+        }
+    }
+    //reshuffles the sample order at the start of every epoch
+    pub fn with_shuffle(mut self, shuffle : bool) -> Self {
+        self.shuffle = shuffle;
+        self
+    }
+    //skips the final batch when it would be smaller than batch_size
+    pub fn with_drop_last(mut self, drop_last : bool) -> Self {
+        self.drop_last = drop_last;
+        self
+    }
+    pub fn with_seed(mut self, seed : u64) -> Self {
+        self.rng = rng::seeded(seed);
+        self
+    }
+    //number of batches per epoch
+    pub fn len(&self) -> usize {
+        let n = self.dataset.len();
+        if self.drop_last {n / self.batch_size} else {n.div_ceil(self.batch_size)}
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    //one pass over the dataset; batches are assembled lazily as the iterator advances
+    pub fn epoch(&mut self) -> Batches<'a> {
+        let mut order = (0..self.dataset.len()).collect::<Vec<usize>>();
+        if self.shuffle {
+            order.shuffle(&mut self.rng);
+        }
+        if self.drop_last {
+            order.truncate(order.len() / self.batch_size * self.batch_size);
+        }
+        Batches { dataset : self.dataset, order, batch_size : self.batch_size, position : 0 }
+    }
+}
+
+pub struct Batches<'a> {
+    dataset : &'a dyn Dataset,
+    order : Vec<usize>,
+    batch_size : usize,
+    position : usize,
+}
+
+impl Iterator for Batches<'_> {
+    type Item = (DMatrix<f64>, DMatrix<f64>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position >= self.order.len() {
+            return None;
+        }
+        let end = (self.position + self.batch_size).min(self.order.len());
+        let (inputs, targets) : (Vec<DVector<f64>>, Vec<DVector<f64>>) = self.order[self.position..end].iter()
+            .map(|&i| self.dataset.get(i))
+            .unzip();
+        self.position = end;
+        Some((DMatrix::from_columns(&inputs), DMatrix::from_columns(&targets)))
+    }
+}
+
+//sample indices for each part of a train/validation/test split
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Split {
+    pub train : Vec<usize>,
+    pub validation : Vec<usize>,
+    pub test : Vec<usize>,
+}
+
+impl Split {
+    pub fn subsets<'a>(&self, dataset : &'a dyn Dataset) -> (Subset<'a>, Subset<'a>, Subset<'a>) {
+        (
+            Subset::new(dataset, self.train.clone()),
+            Subset::new(dataset, self.validation.clone()),
+            Subset::new(dataset, self.test.clone()),
+        )
+    }
+}
+
+//sample indices grouped by class (the largest target entry), or all in one group
+fn classes(dataset : &dyn Dataset, stratify : bool) -> Vec<Vec<usize>> {
+    let mut groups = BTreeMap::new();
+    for i in 0..dataset.len() {
+        let class = if stratify {dataset.get(i).1.imax()} else {0};
+        groups.entry(class).or_insert_with(Vec::new).push(i);
+    }
+    groups.into_values().collect()
+}
+
+//puts a random `validation` and `test` fraction of the samples aside, the rest is train
+//with `stratify` each class (the largest target entry) is split separately so all three keep the class balance
+pub fn split(dataset : &dyn Dataset, validation : f64, test : f64, stratify : bool) -> Split {
+    rng::with_global(|r_vals| split_rng(dataset, validation, test, stratify, r_vals))
+}
+
+pub fn split_rng<R : Rng + ?Sized>(dataset : &dyn Dataset, validation : f64, test : f64, stratify : bool, r_vals : &mut R) -> Split {
+    assert!(validation >= 0.0 && test >= 0.0 && validation + test <= 1.0, "split fractions must be non-negative and sum to at most 1");
+    let mut split = Split::default();
+    for mut indices in classes(dataset, stratify) {
+        indices.shuffle(r_vals);
+        let n = indices.len() as f64;
+        let n_test = (test * n).round() as usize;
+        let n_val = ((validation * n).round() as usize).min(indices.len() - n_test);
+        split.test.extend_from_slice(&indices[..n_test]);
+        split.validation.extend_from_slice(&indices[n_test..n_test + n_val]);
+        split.train.extend_from_slice(&indices[n_test + n_val..]);
+    }
+    //classes were appended one after another, mix them back up
+    split.train.shuffle(r_vals);
+    split.validation.shuffle(r_vals);
+    split.test.shuffle(r_vals);
+    split
+}
+
+//k splits whose validation parts partition the samples, each trained on the other k-1 parts; no test part.
+//with `stratify` each class is dealt out round-robin so the folds differ in size by at most one per class
+pub fn k_fold(dataset : &dyn Dataset, k : usize, stratify : bool) -> Vec<Split> {
+    rng::with_global(|r_vals| k_fold_rng(dataset, k, stratify, r_vals))
+}
+
+pub fn k_fold_rng<R : Rng + ?Sized>(dataset : &dyn Dataset, k : usize, stratify : bool, r_vals : &mut R) -> Vec<Split> {
+    assert!(k >= 2, "k-fold validation needs at least 2 folds");
+    let mut held_out = vec![Vec::new(); k];
+    let mut next = 0;
+    for mut indices in classes(dataset, stratify) {
+        indices.shuffle(r_vals);
+        for i in indices {
+            held_out[next % k].push(i);
+            next += 1;
+        }
+    }
+    (0..k).map(|fold| Split {
+        train : (0..k).filter(|&other| other != fold).flat_map(|other| held_out[other].iter().cloned()).collect(),
+        validation : held_out[fold].clone(),
+        test : Vec::new(),
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datasets::InMemoryDataset;
+
+    //12 samples, a third of them in class 1
+    fn dataset() -> InMemoryDataset {
+        let inputs = (0..12).map(|i| DVector::from_element(1, i as f64)).collect();
+        let targets = (0..12).map(|i| if i % 3 == 0 {DVector::from_vec(vec![0.0, 1.0])} else {DVector::from_vec(vec![1.0, 0.0])}).collect();
+        InMemoryDataset::new(inputs, targets)
+    }
+
+    #[test]
+    fn k_fold_partitions_and_stratifies() {
+        let data = dataset();
+        let folds = k_fold_rng(&data, 4, true, &mut rng::seeded(1));
+        let mut seen = folds.iter().flat_map(|fold| fold.validation.iter().cloned()).collect::<Vec<usize>>();
+        seen.sort();
+        assert_eq!(seen, (0..12).collect::<Vec<usize>>());
+        for fold in folds.iter() {
+            assert_eq!(fold.train.len() + fold.validation.len(), 12);
+            assert!(fold.validation.iter().all(|i| !fold.train.contains(i)));
+            assert_eq!(fold.validation.iter().filter(|&&i| i % 3 == 0).count(), 1);
+        }
+    }
+
+    #[test]
+    fn loader_covers_every_sample_once() {
+        let data = dataset();
+        let mut loader = DataLoader::new(&data, 5).with_shuffle(true).with_seed(3);
+        let batches = loader.epoch().collect::<Vec<_>>();
+        assert_eq!(batches.iter().map(|(input, _)| input.ncols()).collect::<Vec<usize>>(), vec![5, 5, 2]);
+        let mut seen = batches.iter().flat_map(|(input, _)| input.iter().map(|&x| x as usize).collect::<Vec<usize>>()).collect::<Vec<usize>>();
+        seen.sort();
+        assert_eq!(seen, (0..12).collect::<Vec<usize>>());
+    }
+}
@@ -0,0 +1,177 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use nalgebra::{DMatrix, DVector};
+use crate::datasets::Dataset;
+use crate::neuralnetwork::gather;
+
+const LABEL_MAGIC : u32 = 0x0000_0801; //unsigned bytes, 1 dimension
+const IMAGE_MAGIC : u32 = 0x0000_0803; //unsigned bytes, 3 dimensions
+pub const NUM_CLASSES : usize = 10;
+
+//standard file names as distributed on yann.lecun.com
+pub const TRAIN_IMAGES : &str = "train-images.idx3-ubyte";
+pub const TRAIN_LABELS : &str = "train-labels.idx1-ubyte";
+pub const TEST_IMAGES : &str = "t10k-images.idx3-ubyte";
+pub const TEST_LABELS : &str = "t10k-labels.idx1-ubyte";
+
+fn invalid(message : String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+//big-endian u32 at `offset`
+fn read_u32(bytes : &[u8], offset : usize) -> Result<u32> {
+    bytes.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| invalid(format!("IDX header truncated at byte {}", offset)))
+}
+
+//checks the magic number and returns the dimension sizes and the payload
+fn parse_idx(bytes : &[u8], magic : u32, dims : usize) -> Result<(Vec<usize>, &[u8])> {
+    let found = read_u32(bytes, 0)?;
+    if found != magic {
+        return Err(invalid(format!("bad IDX magic number {:#010x}, expected {:#010x}", found, magic)));
+    }
+    let shape = (0..dims).map(|d| read_u32(bytes, 4 + 4 * d).map(|n| n as usize)).collect::<Result<Vec<usize>>>()?;
+    let header = 4 + 4 * dims;
+    let expected = shape.iter().try_fold(1usize, |total, &n| total.checked_mul(n))
+        .ok_or_else(|| invalid(format!("IDX header dimensions {:?} overflow the payload size", shape)))?;
+    let data = &bytes[header..];
+    if data.len() != expected {
+        return Err(invalid(format!("IDX payload has {} bytes, header promises {}", data.len(), expected)));
+    }
+    Ok((shape, data))
+}
+
+pub fn parse_labels(bytes : &[u8]) -> Result<Vec<u8>> {
+    let (_, data) = parse_idx(bytes, LABEL_MAGIC, 1)?;
+    if let Some(bad) = data.iter().find(|&&l| l as usize >= NUM_CLASSES) {
+        return Err(invalid(format!("label {} is out of range", bad)));
+    }
+    Ok(data.to_vec())
+}
+
+//returns (images, rows, cols); each image is row-major and normalized to [0,1]
+pub fn parse_images(bytes : &[u8]) -> Result<(Vec<DVector<f64>>, usize, usize)> {
+    let (shape, data) = parse_idx(bytes, IMAGE_MAGIC, 3)?;
+    let (rows, cols) = (shape[1], shape[2]);
+    if rows == 0 || cols == 0 {
+        return Err(invalid(format!("IDX header declares {}x{} images", rows, cols)));
+    }
+    let images = data.chunks(rows * cols)
+        .map(|pixels| DVector::from_iterator(rows * cols, pixels.iter().map(|&p| p as f64 / 255.0)))
+        .collect();
+    Ok((images, rows, cols))
+}
+
+pub fn read_labels<P : AsRef<Path>>(path : P) -> Result<Vec<u8>> {
+    parse_labels(&fs::read(path)?)
+}
+
+pub fn read_images<P : AsRef<Path>>(path : P) -> Result<(Vec<DVector<f64>>, usize, usize)> {
+    parse_images(&fs::read(path)?)
+}
+
+pub fn one_hot(label : usize, classes : usize) -> DVector<f64> {
+    DVector::from_fn(classes, |i, _| if i == label {1.0} else {0.0})
+}
+
+pub struct Mnist {
+    pub images : Vec<DVector<f64>>,
+    pub labels : Vec<DVector<f64>>, //one-hot
+    pub rows : usize,
+    pub cols : usize,
+}
+
+impl Mnist {
+    pub fn load<P : AsRef<Path>, Q : AsRef<Path>>(images : P, labels : Q) -> Result<Self> {
+        let (images, rows, cols) = read_images(images)?;
+        let labels = read_labels(labels)?;
+        if images.len() != labels.len() {
+            return Err(invalid(format!("{} images but {} labels", images.len(), labels.len())));
+        }
+        Ok(Mnist {
+            images,
+            labels : labels.iter().map(|&l| one_hot(l as usize, NUM_CLASSES)).collect(),
+            rows,
+            cols,
+        })
+    }
+    //(train, test) from a directory holding the four standard files
+    pub fn load_dir<P : AsRef<Path>>(dir : P) -> Result<(Self, Self)> {
+        let dir = dir.as_ref();
+        let train = Self::load(dir.join(TRAIN_IMAGES), dir.join(TRAIN_LABELS))?;
+        let test = Self::load(dir.join(TEST_IMAGES), dir.join(TEST_LABELS))?;
+        Ok((train, test))
+    }
+    pub fn exists<P : AsRef<Path>>(dir : P) -> bool {
+        let dir = dir.as_ref();
+        [TRAIN_IMAGES, TRAIN_LABELS, TEST_IMAGES, TEST_LABELS].iter().all(|f| dir.join(f).is_file())
+    }
+    pub fn len(&self) -> usize {
+        self.images.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.images.is_empty()
+    }
+    //keeps only the first n samples, handy for quick runs
+    pub fn truncate(&mut self, n : usize) {
+        self.images.truncate(n);
+        self.labels.truncate(n);
+    }
+    //in-order (images, labels) batches with one sample per column
+    pub fn batches(&self, batch_size : usize) -> Vec<(DMatrix<f64>, DMatrix<f64>)> {
+        let indices = (0..self.len()).collect::<Vec<usize>>();
+        indices.chunks(batch_size.max(1))
+            .map(|chunk| (gather(&self.images, chunk), gather(&self.labels, chunk)))
+            .collect()
+    }
+}
+
+impl Dataset for Mnist {
+    fn len(&self) -> usize {
+        self.images.len()
+    }
+    fn get(&self, index : usize) -> (DVector<f64>, DVector<f64>) {
+        (self.images[index].clone(), self.labels[index].clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn idx(magic : u32, shape : &[u32], data : &[u8]) -> Vec<u8> {
+        let mut bytes = magic.to_be_bytes().to_vec();
+        for n in shape {
+            bytes.extend(n.to_be_bytes());
+        }
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn parses_images_and_labels() {
+        let (images, rows, cols) = parse_images(&idx(IMAGE_MAGIC, &[2, 1, 2], &[0, 255, 51, 102])).unwrap();
+        assert_eq!((images.len(), rows, cols), (2, 1, 2));
+        assert_eq!(images[1], DVector::from_vec(vec![0.2, 0.4]));
+        assert_eq!(parse_labels(&idx(LABEL_MAGIC, &[3], &[0, 9, 4])).unwrap(), vec![0, 9, 4]);
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let kind = |result : Result<(Vec<DVector<f64>>, usize, usize)>| result.unwrap_err().kind();
+        assert_eq!(kind(parse_images(&idx(IMAGE_MAGIC, &[3, 0, 28], &[]))), ErrorKind::InvalidData);
+        assert_eq!(kind(parse_images(&idx(IMAGE_MAGIC, &[0, 28, 0], &[]))), ErrorKind::InvalidData);
+        assert_eq!(kind(parse_images(&idx(LABEL_MAGIC, &[1, 1, 1], &[0]))), ErrorKind::InvalidData);
+        assert_eq!(kind(parse_images(&idx(IMAGE_MAGIC, &[2, 2, 2], &[0; 7]))), ErrorKind::InvalidData);
+        assert_eq!(parse_labels(&idx(LABEL_MAGIC, &[1], &[10])).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_dimensions_whose_product_overflows() {
+        let error = parse_images(&idx(IMAGE_MAGIC, &[u32::MAX, u32::MAX, u32::MAX], &[0; 4])).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("overflow"), "{}", error);
+    }
+}
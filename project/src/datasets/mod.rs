@@ -0,0 +1,78 @@
+pub mod csv;
+pub mod loader;
+pub mod mnist;
+
+use nalgebra::DVector;
+
+pub use loader::{k_fold, k_fold_rng, split, split_rng, DataLoader, Split};
+
+//indexed (input, target) samples, so inputs and targets can't drift out of step
+pub trait Dataset {
+    fn len(&self) -> usize;
+    fn get(&self, index : usize) -> (DVector<f64>, DVector<f64>);
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+pub struct InMemoryDataset {
+    inputs : Vec<DVector<f64>>,
+    targets : Vec<DVector<f64>>,
+}
+
+impl InMemoryDataset {
+    pub fn new(inputs : Vec<DVector<f64>>, targets : Vec<DVector<f64>>) -> Self {
+        assert_eq!(inputs.len(), targets.len(), "{} inputs but {} targets", inputs.len(), targets.len());
+        Self { inputs, targets }
+    }
+    //copies every sample out of another dataset
+    pub fn from_dataset(dataset : &dyn Dataset) -> Self {
+        let (inputs, targets) = (0..dataset.len()).map(|i| dataset.get(i)).unzip();
+        Self { inputs, targets }
+    }
+    pub fn inputs(&self) -> &[DVector<f64>] {
+        &self.inputs
+    }
+    pub fn targets(&self) -> &[DVector<f64>] {
+        &self.targets
+    }
+    pub fn into_parts(self) -> (Vec<DVector<f64>>, Vec<DVector<f64>>) {
+        (self.inputs, self.targets)
+    }
+}
+
+impl Dataset for InMemoryDataset {
+    fn len(&self) -> usize {
+        self.inputs.len()
+    }
+    fn get(&self, index : usize) -> (DVector<f64>, DVector<f64>) {
+        (self.inputs[index].clone(), self.targets[index].clone())
+    }
+}
+
+//a view of some samples of another dataset, in the given order
+pub struct Subset<'a> {
+    dataset : &'a dyn Dataset,
+    indices : Vec<usize>,
+}
+
+impl<'a> Subset<'a> {
+    pub fn new(dataset : &'a dyn Dataset, indices : Vec<usize>) -> Self {
+        if let Some(bad) = indices.iter().find(|&&i| i >= dataset.len()) {
+            panic!("index {} is out of range for a dataset of {} samples", bad, dataset.len());
+        }
+        Self { dataset, indices }
+    }
+    pub fn indices(&self) -> &[usize] {
+        &self.indices
+    }
+}
+
+impl Dataset for Subset<'_> {
+    fn len(&self) -> usize {
+        self.indices.len()
+    }
+    fn get(&self, index : usize) -> (DVector<f64>, DVector<f64>) {
+        self.dataset.get(self.indices[index])
+    }
+}
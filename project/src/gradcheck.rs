@@ -0,0 +1,20 @@
+//central-difference helpers shared by the gradient tests
+
+pub const STEP : f64 = 1e-6;
+
+//every built-in activation, as activation::from_name reads them
+pub const ACTIVATIONS : [&str; 9] = ["identity", "relu", "leaky_relu(0.1)", "sigmoid", "tanh", "gelu", "softplus", "silu", "elu(0.8)"];
+
+//d(loss)/d(value i) for i in 0..len; `loss(i, delta)` evaluates with value i moved by delta and
+//must put it back before returning
+pub fn central_difference<F : FnMut(usize, f64) -> f64>(len : usize, mut loss : F) -> Vec<f64> {
+    (0..len).map(|i| (loss(i, STEP) - loss(i, -STEP)) / (2.0 * STEP)).collect()
+}
+
+//largest difference relative to the largest exact entry (at least 1)
+pub fn assert_close(name : &str, exact : &[f64], numeric : &[f64], tolerance : f64) {
+    assert_eq!(exact.len(), numeric.len(), "{}: {} exact entries but {} numeric ones", name, exact.len(), numeric.len());
+    let scale = exact.iter().fold(1.0_f64, |m, x| m.max(x.abs()));
+    let error = exact.iter().zip(numeric.iter()).map(|(e, n)| (e - n).abs()).fold(0.0, f64::max) / scale;
+    assert!(error < tolerance, "{} is off by {:e} (tolerance {:e})\nexact   {:?}\nnumeric {:?}", name, error, tolerance, exact, numeric);
+}
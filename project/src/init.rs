@@ -0,0 +1,172 @@
+use nalgebra::DMatrix;
+use rand::Rng;
+use crate::convnn::Tensor3;
+
+//how to draw a layer's starting weights; fan-in/fan-out come from the layer's shape
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Initializer {
+    XavierUniform, //Glorot: U(±sqrt(6/(fan_in+fan_out)))
+    XavierNormal, //Glorot: N(0, 2/(fan_in+fan_out))
+    HeUniform, //Kaiming, for ReLU-family layers: U(±sqrt(6/fan_in))
+    HeNormal, //Kaiming: N(0, 2/fan_in)
+    LecunUniform, //U(±sqrt(3/fan_in))
+    LecunNormal, //N(0, 1/fan_in)
+    Orthogonal(f64), //gain times a (semi-)orthogonal matrix
+    Zeros,
+    Constant(f64),
+    Uniform(f64, f64), //U[low, high]; low == high gives a constant
+}
+
+//standard normal sample via Box-Muller, so we do not need rand_distr
+pub fn standard_normal<R : Rng + ?Sized>(rng : &mut R) -> f64 {
+    let u1 = 1.0 - rng.gen::<f64>(); //(0,1], keeps ln() finite
+    let u2 = rng.gen::<f64>();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+fn uniform<R : Rng + ?Sized>(rows : usize, cols : usize, limit : f64, rng : &mut R) -> DMatrix<f64> {
+    DMatrix::from_fn(rows, cols, |_, _| rng.gen_range(-limit..=limit))
+}
+
+fn normal<R : Rng + ?Sized>(rows : usize, cols : usize, std : f64, rng : &mut R) -> DMatrix<f64> {
+    DMatrix::from_fn(rows, cols, |_, _| std * standard_normal(rng))
+}
+
+//rows × cols with orthonormal rows or columns (whichever there are fewer of)
+fn orthogonal<R : Rng + ?Sized>(rows : usize, cols : usize, gain : f64, rng : &mut R) -> DMatrix<f64> {
+    let (tall, short) = (rows.max(cols), rows.min(cols));
+    let qr = normal(tall, short, 1.0, rng).qr();
+    let mut q = qr.q();
+    //fix the signs so the result is uniformly distributed over orthogonal matrices
+    let r = qr.r();
+    for (j, mut column) in q.column_iter_mut().enumerate() {
+        if r[(j, j)] < 0.0 {
+            column.neg_mut();
+        }
+    }
+    let q = if rows >= cols {q} else {q.transpose()};
+    q * gain
+}
+
+impl Initializer {
+    pub fn sample<R : Rng + ?Sized>(&self, rows : usize, cols : usize, fan_in : usize, fan_out : usize, rng : &mut R) -> DMatrix<f64> {
+        let (fan_in, fan_out) = (fan_in.max(1) as f64, fan_out.max(1) as f64);
+        match *self {
+            Initializer::XavierUniform => uniform(rows, cols, (6.0 / (fan_in + fan_out)).sqrt(), rng),
+            Initializer::XavierNormal => normal(rows, cols, (2.0 / (fan_in + fan_out)).sqrt(), rng),
+            Initializer::HeUniform => uniform(rows, cols, (6.0 / fan_in).sqrt(), rng),
+            Initializer::HeNormal => normal(rows, cols, (2.0 / fan_in).sqrt(), rng),
+            Initializer::LecunUniform => uniform(rows, cols, (3.0 / fan_in).sqrt(), rng),
+            Initializer::LecunNormal => normal(rows, cols, (1.0 / fan_in).sqrt(), rng),
+            Initializer::Orthogonal(gain) => orthogonal(rows, cols, gain, rng),
+            Initializer::Zeros => DMatrix::zeros(rows, cols),
+            Initializer::Constant(value) => DMatrix::from_element(rows, cols, value),
+            Initializer::Uniform(low, high) => {
+                assert!(low.is_finite() && high.is_finite() && low <= high, "uniform initializer needs finite bounds with low <= high, got [{}, {}]", low, high);
+                DMatrix::from_fn(rows, cols, |_, _| rng.gen_range(low..=high))
+            }
+        }
+    }
+    //output × input weight matrix of a dense layer
+    pub fn dense<R : Rng + ?Sized>(&self, input : usize, output : usize, rng : &mut R) -> DMatrix<f64> {
+        self.sample(output, input, input, output, rng)
+    }
+    //F filters of C×k×k; each output sees C·k·k inputs and each input feeds F·k·k outputs
+    pub fn conv<R : Rng + ?Sized>(&self, input : usize, output : usize, filter_size : usize, rng : &mut R) -> Vec<Tensor3> {
+        let kk = filter_size * filter_size;
+        let flat = self.sample(output, input * kk, input * kk, output * kk, rng);
+        (0..output).map(|f| {
+            (0..input).map(|c| DMatrix::from_fn(filter_size, filter_size, |a, b| flat[(f, c * kk + a * filter_size + b)])).collect()
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng;
+
+    #[test]
+    fn uniform_accepts_a_degenerate_range() {
+        let weights = Initializer::Uniform(0.5, 0.5).dense(3, 2, &mut rng::seeded(0));
+        assert!(weights.iter().all(|&w| w == 0.5));
+        let weights = Initializer::Uniform(-0.1, 0.2).dense(30, 20, &mut rng::seeded(0));
+        assert!(weights.iter().all(|&w| (-0.1..=0.2).contains(&w)));
+    }
+
+    #[test]
+    #[should_panic(expected = "low <= high")]
+    fn uniform_rejects_reversed_bounds() {
+        Initializer::Uniform(1.0, -1.0).dense(3, 2, &mut rng::seeded(0));
+    }
+
+    fn flatten(filters : &[Tensor3]) -> Vec<f64> {
+        filters.iter().flatten().flat_map(|m| m.iter().copied()).collect()
+    }
+
+    //every draw inside ±limit, and enough of them that the extremes get close to it
+    fn assert_uniform(name : &str, values : &[f64], limit : f64) {
+        let widest = values.iter().fold(0.0f64, |acc, w| acc.max(w.abs()));
+        assert!(widest <= limit, "{}: {} exceeds the limit {}", name, widest, limit);
+        assert!(widest > 0.95 * limit, "{}: widest draw {} is well inside the limit {}", name, widest, limit);
+    }
+
+    fn assert_normal(name : &str, values : &[f64], std : f64) {
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let sample_std = (values.iter().map(|w| (w - mean).powi(2)).sum::<f64>() / n).sqrt();
+        assert!(mean.abs() < 0.1 * std, "{}: mean {} is not near 0", name, mean);
+        assert!((sample_std / std - 1.0).abs() < 0.05, "{}: std {} where {} was expected", name, sample_std, std);
+    }
+
+    #[test]
+    fn dense_scales_follow_fan_in_and_fan_out() {
+        let (fan_in, fan_out) = (60.0, 40.0);
+        let draw = |init : Initializer| -> Vec<f64> {
+            init.dense(60, 40, &mut rng::seeded(1)).iter().copied().collect()
+        };
+        assert_uniform("xavier", &draw(Initializer::XavierUniform), (6.0f64 / (fan_in + fan_out)).sqrt());
+        assert_uniform("he", &draw(Initializer::HeUniform), (6.0f64 / fan_in).sqrt());
+        assert_uniform("lecun", &draw(Initializer::LecunUniform), (3.0f64 / fan_in).sqrt());
+        assert_normal("xavier", &draw(Initializer::XavierNormal), (2.0f64 / (fan_in + fan_out)).sqrt());
+        assert_normal("he", &draw(Initializer::HeNormal), (2.0f64 / fan_in).sqrt());
+        assert_normal("lecun", &draw(Initializer::LecunNormal), (1.0f64 / fan_in).sqrt());
+    }
+
+    #[test]
+    fn conv_fans_count_the_whole_filter() {
+        //3 channels, 8 filters of 5×5: fan_in = 3·25, fan_out = 8·25
+        let (fan_in, fan_out) = (75.0, 200.0);
+        let draw = |init : Initializer| -> Vec<f64> {
+            let filters = init.conv(3, 8, 5, &mut rng::seeded(2));
+            assert_eq!(filters.len(), 8);
+            assert!(filters.iter().all(|f| f.len() == 3 && f.iter().all(|m| m.shape() == (5, 5))));
+            flatten(&filters)
+        };
+        assert_uniform("xavier", &draw(Initializer::XavierUniform), (6.0f64 / (fan_in + fan_out)).sqrt());
+        assert_uniform("he", &draw(Initializer::HeUniform), (6.0f64 / fan_in).sqrt());
+        assert_uniform("lecun", &draw(Initializer::LecunUniform), (3.0f64 / fan_in).sqrt());
+        assert_normal("xavier", &draw(Initializer::XavierNormal), (2.0f64 / (fan_in + fan_out)).sqrt());
+        assert_normal("he", &draw(Initializer::HeNormal), (2.0f64 / fan_in).sqrt());
+        assert_normal("lecun", &draw(Initializer::LecunNormal), (1.0f64 / fan_in).sqrt());
+    }
+
+    #[test]
+    fn orthogonal_rows_or_columns_are_orthonormal() {
+        let gain = 1.5;
+        let tall = Initializer::Orthogonal(gain).dense(4, 7, &mut rng::seeded(3));
+        assert_eq!(tall.shape(), (7, 4));
+        let gram = tall.transpose() * &tall;
+        assert!((gram - DMatrix::identity(4, 4) * gain * gain).abs().max() < 1e-10);
+
+        let wide = Initializer::Orthogonal(gain).dense(7, 4, &mut rng::seeded(3));
+        assert_eq!(wide.shape(), (4, 7));
+        let gram = &wide * wide.transpose();
+        assert!((gram - DMatrix::identity(4, 4) * gain * gain).abs().max() < 1e-10);
+
+        //conv filters flatten to F × C·k·k rows, which are orthonormal when F is the smaller side
+        let filters = Initializer::Orthogonal(1.0).conv(2, 3, 3, &mut rng::seeded(4));
+        let flat = DMatrix::from_row_slice(3, 18, &flatten(&filters));
+        assert!((&flat * flat.transpose() - DMatrix::identity(3, 3)).abs().max() < 1e-10);
+    }
+}
@@ -0,0 +1,20 @@
+pub mod activation;
+pub mod autodiff;
+pub mod convnn;
+pub mod datasets;
+#[cfg(test)]
+mod gradcheck;
+pub mod init;
+pub mod loss;
+pub mod metrics;
+pub mod neuralnetwork;
+pub mod optimizer;
+pub mod pinn;
+pub mod pooling;
+pub mod preprocessing;
+pub mod rng;
+pub mod scheduler;
+pub mod serialization;
+pub mod shape;
+pub mod trainer;
+pub mod tuning;
@@ -0,0 +1,485 @@
+use nalgebra::{DMatrix, DVector};
+use crate::activation::parse_name;
+use crate::autodiff::Var;
+use crate::neuralnetwork::{Loss, MeanSquaredError};
+
+//keeps log() away from zero probabilities
+const EPS : f64 = 1e-12;
+
+//mixes a one-hot target with the uniform distribution: t*(1-e) + e/K
+pub fn smooth_labels(target : &DVector<f64>, smoothing : f64) -> DVector<f64> {
+    if smoothing == 0.0 {
+        return target.clone();
+    }
+    let uniform = smoothing / target.len() as f64;
+    target.map(|t| t * (1.0 - smoothing) + uniform)
+}
+
+//smooth_labels for every column of a batch
+fn smooth_batch(target : &DMatrix<f64>, smoothing : f64) -> DMatrix<f64> {
+    let uniform = smoothing / target.nrows() as f64;
+    target.map(|t| t * (1.0 - smoothing) + uniform)
+}
+
+//the targets as a leaf of the result's tape, for products with it
+fn constant(result : &Var, target : DMatrix<f64>) -> Var {
+    result.tape().var(target)
+}
+
+fn log_sum_exp(logits : &DVector<f64>) -> f64 {
+    let max = logits.max();
+    max + logits.iter().map(|z| (z - max).exp()).sum::<f64>().ln()
+}
+
+pub fn softmax(logits : &DVector<f64>) -> DVector<f64> {
+    let lse = log_sum_exp(logits);
+    logits.map(|z| (z - lse).exp())
+}
+
+//expects `result` to already be a probability distribution (e.g. softmax output)
+pub struct CrossEntropy {
+    pub smoothing : f64,
+}
+impl CrossEntropy {
+    pub fn new() -> Self {
+        CrossEntropy { smoothing : 0.0 }
+    }
+    pub fn with_label_smoothing(smoothing : f64) -> Self {
+        CrossEntropy { smoothing }
+    }
+}
+impl Default for CrossEntropy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Loss for CrossEntropy {
+    fn name(&self) -> String {
+        format!("cross_entropy({})", self.smoothing)
+    }
+    fn compute(&self, result : &DVector<f64>, test : &DVector<f64>) -> f64 {
+        let target = smooth_labels(test, self.smoothing);
+        -result.iter().zip(target.iter()).map(|(p, t)| t * p.max(EPS).ln()).sum::<f64>()
+    }
+    fn gradient(&self, result : &DVector<f64>, test : &DVector<f64>) -> DVector<f64> {
+        let target = smooth_labels(test, self.smoothing);
+        DVector::from_iterator(result.len(), result.iter().zip(target.iter()).map(|(p, t)| -t / p.max(EPS)))
+    }
+    fn compute_tape(&self, result : &Var, test : &DMatrix<f64>) -> Var {
+        let log = result.map(|p| p.max(EPS).ln(), |p| 1.0 / p.max(EPS));
+        log.mul(&constant(result, smooth_batch(test, self.smoothing))).sum().scale(-1.0 / result.cols() as f64)
+    }
+}
+
+//independent yes/no probabilities per output, averaged over the outputs
+pub struct BinaryCrossEntropy;
+impl Loss for BinaryCrossEntropy {
+    fn name(&self) -> String {
+        "binary_cross_entropy".to_string()
+    }
+    fn compute(&self, result : &DVector<f64>, test : &DVector<f64>) -> f64 {
+        let total = result.iter().zip(test.iter()).map(|(p, t)| {
+            let p = p.clamp(EPS, 1.0 - EPS);
+            -(t * p.ln() + (1.0 - t) * (1.0 - p).ln())
+        }).sum::<f64>();
+        total / result.len() as f64
+    }
+    fn gradient(&self, result : &DVector<f64>, test : &DVector<f64>) -> DVector<f64> {
+        let n = result.len() as f64;
+        DVector::from_iterator(result.len(), result.iter().zip(test.iter()).map(|(p, t)| {
+            let p = p.clamp(EPS, 1.0 - EPS);
+            (p - t) / (p * (1.0 - p)) / n
+        }))
+    }
+    fn compute_tape(&self, result : &Var, test : &DMatrix<f64>) -> Var {
+        let p = result.map(|p| p.clamp(EPS, 1.0 - EPS), |_| 1.0);
+        let (yes, no) = (p.ln(), p.neg().add_scalar(1.0).ln());
+        let total = yes.mul(&constant(result, test.clone())).add(&no.mul(&constant(result, test.map(|t| 1.0 - t))));
+        total.mean().neg()
+    }
+}
+
+//takes raw logits; softmax and cross-entropy are fused so the gradient is just softmax(z) - t
+pub struct SoftmaxCrossEntropy {
+    pub smoothing : f64,
+}
+impl SoftmaxCrossEntropy {
+    pub fn new() -> Self {
+        SoftmaxCrossEntropy { smoothing : 0.0 }
+    }
+    pub fn with_label_smoothing(smoothing : f64) -> Self {
+        SoftmaxCrossEntropy { smoothing }
+    }
+}
+impl Default for SoftmaxCrossEntropy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Loss for SoftmaxCrossEntropy {
+    fn name(&self) -> String {
+        format!("softmax_cross_entropy({})", self.smoothing)
+    }
+    fn compute(&self, result : &DVector<f64>, test : &DVector<f64>) -> f64 {
+        let target = smooth_labels(test, self.smoothing);
+        let lse = log_sum_exp(result);
+        result.iter().zip(target.iter()).map(|(z, t)| t * (lse - z)).sum::<f64>()
+    }
+    fn gradient(&self, result : &DVector<f64>, test : &DVector<f64>) -> DVector<f64> {
+        let target = smooth_labels(test, self.smoothing);
+        //the target mass is 1 for a proper distribution, but keep it general
+        softmax(result) * target.sum() - target
+    }
+    fn compute_tape(&self, result : &Var, test : &DMatrix<f64>) -> Var {
+        let target = constant(result, smooth_batch(test, self.smoothing));
+        result.log_softmax().mul(&target).sum().scale(-1.0 / result.cols() as f64)
+    }
+}
+
+//expects `result` to hold log-probabilities
+pub struct NegativeLogLikelihood {
+    pub smoothing : f64,
+}
+impl NegativeLogLikelihood {
+    pub fn new() -> Self {
+        NegativeLogLikelihood { smoothing : 0.0 }
+    }
+    pub fn with_label_smoothing(smoothing : f64) -> Self {
+        NegativeLogLikelihood { smoothing }
+    }
+}
+impl Default for NegativeLogLikelihood {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Loss for NegativeLogLikelihood {
+    fn name(&self) -> String {
+        format!("negative_log_likelihood({})", self.smoothing)
+    }
+    fn compute(&self, result : &DVector<f64>, test : &DVector<f64>) -> f64 {
+        let target = smooth_labels(test, self.smoothing);
+        -result.dot(&target)
+    }
+    fn gradient(&self, _result : &DVector<f64>, test : &DVector<f64>) -> DVector<f64> {
+        -smooth_labels(test, self.smoothing)
+    }
+    fn compute_tape(&self, result : &Var, test : &DMatrix<f64>) -> Var {
+        let target = constant(result, smooth_batch(test, self.smoothing));
+        result.mul(&target).sum().scale(-1.0 / result.cols() as f64)
+    }
+}
+
+pub struct MeanAbsoluteError;
+impl Loss for MeanAbsoluteError {
+    fn name(&self) -> String {
+        "mean_absolute_error".to_string()
+    }
+    fn compute(&self, result : &DVector<f64>, test : &DVector<f64>) -> f64 {
+        (result - test).iter().map(|d| d.abs()).sum::<f64>() / result.len() as f64
+    }
+    fn gradient(&self, result : &DVector<f64>, test : &DVector<f64>) -> DVector<f64> {
+        let n = result.len() as f64;
+        //subgradient 0 at d == 0
+        (result - test).map(|d| if d > 0.0 {1.0 / n} else if d < 0.0 {-1.0 / n} else {0.0})
+    }
+    fn compute_tape(&self, result : &Var, test : &DMatrix<f64>) -> Var {
+        result.add_constant(&-test).abs().mean()
+    }
+}
+
+//quadratic for |d| <= delta, linear beyond it
+pub struct Huber {
+    pub delta : f64,
+}
+impl Huber {
+    pub fn new(delta : f64) -> Self {
+        Huber { delta }
+    }
+}
+impl Default for Huber {
+    fn default() -> Self {
+        Huber { delta : 1.0 }
+    }
+}
+impl Loss for Huber {
+    fn name(&self) -> String {
+        format!("huber({})", self.delta)
+    }
+    fn compute(&self, result : &DVector<f64>, test : &DVector<f64>) -> f64 {
+        let total = (result - test).iter().map(|d| {
+            if d.abs() <= self.delta {
+                0.5 * d * d
+            }
+            else {
+                self.delta * (d.abs() - 0.5 * self.delta)
+            }
+        }).sum::<f64>();
+        total / result.len() as f64
+    }
+    fn gradient(&self, result : &DVector<f64>, test : &DVector<f64>) -> DVector<f64> {
+        let n = result.len() as f64;
+        (result - test).map(|d| d.clamp(-self.delta, self.delta) / n)
+    }
+    fn compute_tape(&self, result : &Var, test : &DMatrix<f64>) -> Var {
+        let delta = self.delta;
+        let huber = move |d : f64| if d.abs() <= delta {0.5 * d * d} else {delta * (d.abs() - 0.5 * delta)};
+        result.add_constant(&-test).map(huber, move |d| d.clamp(-delta, delta)).mean()
+    }
+}
+
+pub struct LogCosh;
+impl Loss for LogCosh {
+    fn name(&self) -> String {
+        "log_cosh".to_string()
+    }
+    fn compute(&self, result : &DVector<f64>, test : &DVector<f64>) -> f64 {
+        //ln(cosh(d)) = |d| + ln(1 + e^(-2|d|)) - ln(2), which does not overflow for large d
+        let total = (result - test).iter().map(|d| {
+            d.abs() + (-2.0 * d.abs()).exp().ln_1p() - std::f64::consts::LN_2
+        }).sum::<f64>();
+        total / result.len() as f64
+    }
+    fn gradient(&self, result : &DVector<f64>, test : &DVector<f64>) -> DVector<f64> {
+        let n = result.len() as f64;
+        (result - test).map(|d| d.tanh() / n)
+    }
+    fn compute_tape(&self, result : &Var, test : &DMatrix<f64>) -> Var {
+        let log_cosh = |d : f64| d.abs() + (-2.0 * d.abs()).exp().ln_1p() - std::f64::consts::LN_2;
+        result.add_constant(&-test).map(log_cosh, f64::tanh).mean()
+    }
+}
+
+//pinball loss; quantile = 0.5 gives half the MAE
+pub struct Quantile {
+    pub quantile : f64,
+}
+impl Quantile {
+    pub fn new(quantile : f64) -> Self {
+        Quantile { quantile }
+    }
+}
+impl Loss for Quantile {
+    fn name(&self) -> String {
+        format!("quantile({})", self.quantile)
+    }
+    fn compute(&self, result : &DVector<f64>, test : &DVector<f64>) -> f64 {
+        let q = self.quantile;
+        let total = (test - result).iter().map(|d| (q * d).max((q - 1.0) * d)).sum::<f64>();
+        total / result.len() as f64
+    }
+    fn gradient(&self, result : &DVector<f64>, test : &DVector<f64>) -> DVector<f64> {
+        let n = result.len() as f64;
+        let q = self.quantile;
+        (test - result).map(|d| if d > 0.0 {-q / n} else if d < 0.0 {(1.0 - q) / n} else {0.0})
+    }
+    fn compute_tape(&self, result : &Var, test : &DMatrix<f64>) -> Var {
+        let q = self.quantile;
+        //d = result - test here, so under-prediction is d < 0
+        let pinball = move |d : f64| (-q * d).max((1.0 - q) * d);
+        result.add_constant(&-test).map(pinball, move |d| if d < 0.0 {-q} else if d > 0.0 {1.0 - q} else {0.0}).mean()
+    }
+}
+
+//predictions and targets are expected to be > -1
+pub struct MeanSquaredLogError;
+impl Loss for MeanSquaredLogError {
+    fn name(&self) -> String {
+        "mean_squared_log_error".to_string()
+    }
+    fn compute(&self, result : &DVector<f64>, test : &DVector<f64>) -> f64 {
+        let total = result.iter().zip(test.iter()).map(|(r, t)| {
+            let d = r.max(EPS - 1.0).ln_1p() - t.ln_1p();
+            d * d
+        }).sum::<f64>();
+        total / result.len() as f64
+    }
+    fn gradient(&self, result : &DVector<f64>, test : &DVector<f64>) -> DVector<f64> {
+        let n = result.len() as f64;
+        DVector::from_iterator(result.len(), result.iter().zip(test.iter()).map(|(r, t)| {
+            let r = r.max(EPS - 1.0);
+            2.0 * (r.ln_1p() - t.ln_1p()) / (1.0 + r) / n
+        }))
+    }
+    fn compute_tape(&self, result : &Var, test : &DMatrix<f64>) -> Var {
+        let log = result.map(|r| r.max(EPS - 1.0).ln_1p(), |r| 1.0 / (1.0 + r.max(EPS - 1.0)));
+        log.add_constant(&-test.map(f64::ln_1p)).square().mean()
+    }
+}
+
+//inverse of Loss::name for every loss in the crate
+pub fn from_name(name : &str) -> Option<Box<dyn Loss>> {
+    let loss : Box<dyn Loss> = match parse_name(name)? {
+        ("mean_squared_error", None) => Box::new(MeanSquaredError),
+        ("cross_entropy", Some(smoothing)) => Box::new(CrossEntropy::with_label_smoothing(smoothing)),
+        ("binary_cross_entropy", None) => Box::new(BinaryCrossEntropy),
+        ("softmax_cross_entropy", Some(smoothing)) => Box::new(SoftmaxCrossEntropy::with_label_smoothing(smoothing)),
+        ("negative_log_likelihood", Some(smoothing)) => Box::new(NegativeLogLikelihood::with_label_smoothing(smoothing)),
+        ("mean_absolute_error", None) => Box::new(MeanAbsoluteError),
+        ("huber", Some(delta)) => Box::new(Huber::new(delta)),
+        ("log_cosh", None) => Box::new(LogCosh),
+        ("quantile", Some(quantile)) => Box::new(Quantile::new(quantile)),
+        ("mean_squared_log_error", None) => Box::new(MeanSquaredLogError),
+        _ => return None,
+    };
+    Some(loss)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::autodiff::Tape;
+    use crate::gradcheck::{assert_close, central_difference, STEP};
+
+    //gradient_batch and the tape's gradient against central differences of compute_batch
+    fn check(loss : &dyn Loss, mut result : DMatrix<f64>, test : &DMatrix<f64>, tolerance : f64) {
+        let exact = loss.gradient_batch(&result, test);
+        let tape = Tape::new();
+        let output = tape.var(result.clone());
+        let value = loss.compute_tape(&output, test);
+        assert!((value.value()[0] - loss.compute_batch(&result, test)).abs() < 1e-12, "{} tape value", loss.name());
+        assert_close(&format!("{} tape gradient", loss.name()), exact.as_slice(), value.backward().wrt(&output).as_slice(), 1e-12);
+        let numeric = central_difference(result.len(), |i, delta| {
+            result[i] += delta;
+            let value = loss.compute_batch(&result, test);
+            result[i] -= delta;
+            value
+        });
+        assert_close(&format!("{} gradient", loss.name()), exact.as_slice(), &numeric, tolerance);
+    }
+
+    //the targets moved by the offsets, cycling through them in column-major order
+    fn offset(test : &DMatrix<f64>, offsets : &[f64]) -> DMatrix<f64> {
+        DMatrix::from_fn(test.nrows(), test.ncols(), |r, c| test[(r, c)] + offsets[(r + c * test.nrows()) % offsets.len()])
+    }
+
+    fn targets() -> DMatrix<f64> {
+        DMatrix::from_fn(3, 4, |r, c| 0.3 * r as f64 - 0.2 * c as f64 + 0.1)
+    }
+
+    #[test]
+    fn regression_losses_match_central_differences() {
+        let test = targets();
+        let offsets = [-2.5, -0.7, -0.1, 0.05, 0.4, 1.3, 3.0];
+        let losses : Vec<Box<dyn Loss>> = vec![
+            Box::new(MeanSquaredError),
+            Box::new(MeanAbsoluteError),
+            Box::new(Huber::new(0.5)),
+            Box::new(LogCosh),
+            Box::new(Quantile::new(0.2)),
+            Box::new(Quantile::new(0.9)),
+        ];
+        for loss in losses.iter() {
+            check(&**loss, offset(&test, &offsets), &test, 1e-8);
+        }
+    }
+
+    #[test]
+    fn huber_is_smooth_across_delta() {
+        let test = targets();
+        let delta = 0.5;
+        //on the boundary the second derivative jumps, so the central difference is only first-order
+        check(&Huber::new(delta), offset(&test, &[delta, -delta]), &test, 1e-5);
+        let near = 3.0 * STEP;
+        check(&Huber::new(delta), offset(&test, &[delta - near, delta + near, -delta - near, -delta + near]), &test, 1e-8);
+    }
+
+    #[test]
+    fn kinks_use_a_subgradient() {
+        let test = targets();
+        let near = 3.0 * STEP;
+        for q in [0.1, 0.5, 0.75] {
+            let loss = Quantile::new(q);
+            check(&loss, offset(&test, &[near, -near]), &test, 1e-8);
+            //exactly on the kink any value in [-q, 1-q] (per sample, before averaging) is a valid subgradient
+            let n = test.len() as f64;
+            for g in loss.gradient_batch(&test, &test).iter() {
+                assert!(*g >= -q / n - 1e-15 && *g <= (1.0 - q) / n + 1e-15, "quantile({}) subgradient {} is outside the subdifferential", q, g);
+            }
+        }
+        check(&MeanAbsoluteError, offset(&test, &[near, -near]), &test, 1e-8);
+    }
+
+    #[test]
+    fn msle_near_minus_one() {
+        let test = DMatrix::from_fn(2, 3, |r, c| 0.5 * (r + c) as f64);
+        let result = DMatrix::from_row_slice(2, 3, &[-0.999, -0.99, -0.9, 0.0, 1.0, 4.0]);
+        check(&MeanSquaredLogError, result, &test, 1e-6);
+        //targets near -1 too
+        let test = DMatrix::from_row_slice(2, 3, &[-0.995, -0.9, 0.0, -0.99, 2.0, -0.5]);
+        check(&MeanSquaredLogError, offset(&test, &[0.3, -0.002, 0.01]), &test, 1e-6);
+    }
+
+    //one-hot columns, class c % 3 for sample c
+    fn labels() -> DMatrix<f64> {
+        DMatrix::from_fn(3, 4, |r, c| if r == c % 3 {1.0} else {0.0})
+    }
+
+    fn logits() -> DMatrix<f64> {
+        DMatrix::from_row_slice(3, 4, &[0.3, -1.2, 2.0, 0.1, -0.4, 0.8, 0.5, -2.2, 1.1, 0.0, -0.9, 0.7])
+    }
+
+    fn columns(m : &DMatrix<f64>, f : fn(&DVector<f64>) -> DVector<f64>) -> DMatrix<f64> {
+        DMatrix::from_columns(&m.column_iter().map(|c| f(&c.into_owned())).collect::<Vec<DVector<f64>>>())
+    }
+
+    #[test]
+    fn classification_losses_match_central_differences() {
+        let test = labels();
+        let probabilities = columns(&logits(), softmax);
+        let log_probabilities = probabilities.map(f64::ln);
+        for smoothing in [0.0, 0.1] {
+            check(&CrossEntropy::with_label_smoothing(smoothing), probabilities.clone(), &test, 1e-7);
+            check(&SoftmaxCrossEntropy::with_label_smoothing(smoothing), logits(), &test, 1e-8);
+            check(&NegativeLogLikelihood::with_label_smoothing(smoothing), log_probabilities.clone(), &test, 1e-8);
+        }
+        //binary targets need not be one-hot, and soft ones are allowed
+        let binary = DMatrix::from_row_slice(3, 4, &[1.0, 0.0, 0.0, 1.0, 1.0, 1.0, 0.0, 0.3, 0.0, 0.5, 1.0, 0.0]);
+        check(&BinaryCrossEntropy, probabilities.map(|p| p.clamp(0.05, 0.95)), &binary, 1e-7);
+    }
+
+    #[test]
+    fn softmax_cross_entropy_is_stable_for_large_logits() {
+        let test = labels();
+        let large = logits().map(|z| 400.0 * z);
+        for smoothing in [0.0, 0.1] {
+            let loss = SoftmaxCrossEntropy::with_label_smoothing(smoothing);
+            assert!(loss.compute_batch(&large, &test).is_finite());
+            check(&loss, large.clone(), &test, 1e-6);
+        }
+        //the same loss as the shifted logits, which log-sum-exp makes exact
+        let shifted = logits().map(|z| z + 700.0);
+        let loss = SoftmaxCrossEntropy::new();
+        assert!((loss.compute_batch(&shifted, &test) - loss.compute_batch(&logits(), &test)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn label_smoothing_mixes_in_the_uniform_distribution() {
+        let smoothed = smooth_labels(&DVector::from_vec(vec![0.0, 1.0, 0.0]), 0.3);
+        assert_close("smoothed labels", smoothed.as_slice(), &[0.1, 0.8, 0.1], 1e-15);
+        //the best prediction is now the smoothed target itself, which pays its entropy
+        let entropy = -smoothed.iter().map(|t| t * t.ln()).sum::<f64>();
+        let best = CrossEntropy::with_label_smoothing(0.3).compute(&smoothed, &DVector::from_vec(vec![0.0, 1.0, 0.0]));
+        assert!((best - entropy).abs() < 1e-12);
+    }
+
+    //a loss written only as a tape expression gets compute and gradient from it
+    struct SumOfCubes;
+    impl Loss for SumOfCubes {
+        fn name(&self) -> String {
+            "sum_of_cubes".to_string()
+        }
+        fn compute_tape(&self, result : &Var, test : &DMatrix<f64>) -> Var {
+            result.add_constant(&-test).powf(3.0).sum().scale(1.0 / result.cols() as f64)
+        }
+    }
+
+    #[test]
+    fn tape_only_losses_get_compute_and_gradient() {
+        let test = targets();
+        let result = offset(&test, &[0.3, -0.7, 1.1]);
+        check(&SumOfCubes, result.clone(), &test, 1e-7);
+        let expected = (&result - &test).map(|d| 3.0 * d * d / result.ncols() as f64);
+        assert_close("sum_of_cubes gradient", expected.as_slice(), SumOfCubes.gradient_batch(&result, &test).as_slice(), 1e-12);
+    }
+}
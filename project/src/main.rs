@@ -1,15 +1,28 @@
-use nalgebra::{DMatrix, DVector};
+use nalgebra::DVector;
 use rand::Rng;
-mod neuralnetwork;
-use neuralnetwork::{NeuralNetwork, DenseLayer, Loss, MeanSquaredError};
-pub fn generate_data(samples : usize, features : usize, classes : usize) -> (Vec<DVector<f64>>, Vec<DVector<f64>>) {
-    let mut rVals = rand::thread_rng();
+use project::activation::{Identity, Relu};
+use project::datasets::mnist::Mnist;
+use project::datasets::{Dataset, InMemoryDataset, Subset};
+use project::init::Initializer;
+use project::loss::SoftmaxCrossEntropy;
+use project::neuralnetwork::{NeuralNetwork, DenseLayer};
+use project::optimizer::Adam;
+use project::rng;
+use project::scheduler::{Interval, ReduceOnPlateau};
+use project::trainer::{accuracy, Trainer};
+use project::tuning::{Params, RandomSearch, Scoring, SearchSpace, Setup, Tuner, Validation};
+const MNIST_DIR : &str = "data";
+const SEED : u64 = 42;
+const TUNING_SAMPLES : usize = 2000; //the search only sees this many training samples
+const TUNING_TRIALS : usize = 6;
+
+pub fn generate_data<R : Rng + ?Sized>(samples : usize, features : usize, classes : usize, r_vals : &mut R) -> InMemoryDataset {
     let mut images = Vec::new();
     let mut labels = Vec::new();
     for _ in 0..samples {
-        let image = DVector::from_iterator(features, (0..features).map(|_| rVals.gen_range(-1.0..1.0)));
+        let image = DVector::from_iterator(features, (0..features).map(|_| r_vals.gen_range(-1.0..1.0)));
         let label = DVector::from_fn(classes, |i, _| {
-            if i==rVals.gen_range(0..classes) {
+            if i==r_vals.gen_range(0..classes) {
                 1.0
             }
             else {0.0}
@@ -17,47 +30,77 @@ pub fn generate_data(samples : usize, features : usize, classes : usize) -> (Vec
         images.push(image);
         labels.push(label);
     }
-    return (images, labels);
+    InMemoryDataset::new(images, labels)
 }
 
 fn main() {
     let num_samples = 100;
     let num_features = 784;
     let num_classes = 10; 
+    //every layer init, shuffle and split below draws from this, so reruns are identical
+    rng::set_seed(SEED);
 
-    let (train_images, train_labels) = generate_data(num_samples, num_features, num_classes);
-    let (test_images, test_labels) = generate_data(num_samples / 2, num_features, num_classes);
+    //train on real MNIST when the IDX files are in data/, otherwise fall back to random data
+    let (train, test) : (Box<dyn Dataset>, Box<dyn Dataset>) = if Mnist::exists(MNIST_DIR) {
+        let (train, test) = Mnist::load_dir(MNIST_DIR).expect("failed to read MNIST files");
+        println!("Training on MNIST ({} train, {} test)", train.len(), test.len());
+        (Box::new(train), Box::new(test))
+    }
+    else {
+        println!("MNIST files not found in {}/, training on random data", MNIST_DIR);
+        let mut r_vals = rng::seeded(SEED);
+        let train = generate_data(num_samples, num_features, num_classes, &mut r_vals);
+        let test = generate_data(num_samples / 2, num_features, num_classes, &mut r_vals);
+        (Box::new(train), Box::new(test))
+    };
 
-    let layer_1 = DenseLayer::new(num_features, 128); 
-    let layer_2 = DenseLayer::new(128, num_classes);
+    let build = move |params : &Params| {
+        let hidden = params.usize("hidden");
+        let layer_1 = DenseLayer::with_initializer(num_features, hidden, Box::new(Relu), Initializer::HeUniform);
+        let layer_2 = DenseLayer::with_initializer(hidden, num_classes, Box::new(Identity), Initializer::XavierUniform);
+        let neural_net = NeuralNetwork::with_optimizer(
+            vec![
+                Box::new(layer_1),
+                Box::new(layer_2),
+            ],
+            Box::new(SoftmaxCrossEntropy::new()),
+            Box::new(Adam::new(params.get("learning_rate"))),
+        );
+        Setup::new(neural_net)
+            .with_epochs(params.usize("epochs"))
+            .with_batch_size(16)
+    };
 
-    let mut NeuralNet = NeuralNetwork::new(
-        vec![
-            Box::new(layer_1),
-            Box::new(layer_2),
-        ],
-        Box::new(MeanSquaredError),
-    );
+    //pick learning rate, hidden size and epochs on a slice of the training set
+    let space = SearchSpace::new()
+        .log_uniform("learning_rate", 1e-4, 1e-2)
+        .discrete("hidden", &[32.0, 64.0, 128.0])
+        .discrete("epochs", &[3.0, 5.0, 10.0]);
+    let tuning_set = Subset::new(&*train, (0..train.len().min(TUNING_SAMPLES)).collect());
+    let mut tuner = Tuner::new(Box::new(build))
+        .with_validation(Validation::Holdout(0.2))
+        .with_scoring(Scoring::Maximize(accuracy))
+        .with_stratify(true)
+        .with_verbose(true);
+    let trials = tuner.search(&mut RandomSearch::new(space, TUNING_TRIALS), &tuning_set);
+    let best = &trials[0].params;
+    println!("Best Hyperparameters: {} (Validation Accuracy: {})", best, trials[0].score);
 
-    let epochs = 10;
-    for epoch in 0..epochs {
-        let mut total_loss = 0.0;
-        for (i, image) in train_images.iter().enumerate() {
-            let label = &train_labels[i];
-            NeuralNet.train(image, label, 0.001, 1);
-            let loss = NeuralNet.test(image, label);
-            total_loss+=loss;
-        }
-        let avg_loss = total_loss/train_images.len() as f64;
-        println!("Epoch {} : Average Training Loss: {} ", epoch+1, avg_loss);
-    }
-    let mut total_loss = 0.0;
-    for (i, image) in test_images.iter().enumerate() {
-        let label = &test_labels[i];
-        let loss = NeuralNet.test(image, label);
-        total_loss += loss;
-       // println!("Test {}: Loss: {}", i+1, loss);
+    let setup = build(best);
+    let mut trainer = Trainer::from_dataset(setup.model, &*train)
+        .with_validation_split(0.2, true)
+        .with_batch_size(setup.batch_size)
+        .with_metric("Accuracy", accuracy)
+        .with_scheduler(Box::new(ReduceOnPlateau::new(0.5, 1)), Interval::Epoch) //halve the rate when validation loss stalls
+        .with_verbose(true);
+    let history = trainer.fit(setup.epochs);
+    if let Some(best) = history.best_epoch() {
+        println!("Best Validation Loss: {} (Epoch {})", history.val_loss[best], best+1);
     }
-    let avg_loss = total_loss / test_images.len() as f64;
-    println!("Average Loss: {}", avg_loss);
+    let test = InMemoryDataset::from_dataset(&*test);
+    let evaluation = trainer.model().evaluate(test.inputs(), test.targets());
+    let confusion = evaluation.confusion_matrix();
+    println!("Test Loss: {}, Accuracy: {}, Top-3 Accuracy: {}", evaluation.loss, evaluation.accuracy(), evaluation.top_k_accuracy(3));
+    println!("Macro Precision: {}, Macro Recall: {}, Macro F1: {}", confusion.macro_precision(), confusion.macro_recall(), confusion.macro_f1());
+    println!("Confusion Matrix (rows actual, columns predicted):\n{}", confusion);
 }
@@ -0,0 +1,311 @@
+use std::fmt;
+use nalgebra::{DMatrix, DVector};
+use crate::neuralnetwork::{gather, Loss};
+
+//every function here scores a batch with one sample per column, so the scalar ones can be
+//passed straight to Trainer::with_metric
+//classification metrics treat the largest entry of each column as the predicted/true class
+
+//samples per forward pass when predicting a whole dataset
+const PREDICT_BATCH : usize = 256;
+
+//fraction of columns whose largest output matches the largest target
+pub fn accuracy(output : &DMatrix<f64>, target : &DMatrix<f64>) -> f64 {
+    let correct = output.column_iter().zip(target.column_iter())
+        .filter(|(o, t)| o.imax() == t.imax())
+        .count();
+    correct as f64 / output.ncols() as f64
+}
+
+//fraction of columns whose true class is among the k largest outputs
+pub fn top_k_accuracy(output : &DMatrix<f64>, target : &DMatrix<f64>, k : usize) -> f64 {
+    let correct = output.column_iter().zip(target.column_iter())
+        .filter(|(o, t)| {
+            let truth = o[t.imax()];
+            //ties with the true class count in its favour
+            o.iter().filter(|&&value| value > truth).count() < k
+        })
+        .count();
+    correct as f64 / output.ncols() as f64
+}
+
+pub fn macro_f1(output : &DMatrix<f64>, target : &DMatrix<f64>) -> f64 {
+    ConfusionMatrix::from_batch(output, target).macro_f1()
+}
+
+pub fn mean_absolute_error(output : &DMatrix<f64>, target : &DMatrix<f64>) -> f64 {
+    (output - target).abs().mean()
+}
+
+pub fn root_mean_squared_error(output : &DMatrix<f64>, target : &DMatrix<f64>) -> f64 {
+    (output - target).map(|d| d * d).mean().sqrt()
+}
+
+//coefficient of determination, with each output row measured against its own mean
+//1 is a perfect fit, 0 is no better than predicting the mean
+pub fn r2_score(output : &DMatrix<f64>, target : &DMatrix<f64>) -> f64 {
+    let means = target.column_mean();
+    let residual = (output - target).map(|d| d * d).sum();
+    let total = target.column_iter().map(|t| (t - &means).norm_squared()).sum::<f64>();
+    if total == 0.0 {
+        return if residual == 0.0 {1.0} else {0.0};
+    }
+    1.0 - residual / total
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClassScores {
+    pub precision : f64,
+    pub recall : f64,
+    pub f1 : f64,
+    pub support : usize, //number of samples whose true class is this one
+}
+
+//counts[(actual, predicted)], built up one batch at a time
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfusionMatrix {
+    counts : DMatrix<usize>,
+}
+
+impl ConfusionMatrix {
+    pub fn new(classes : usize) -> Self {
+        Self { counts : DMatrix::zeros(classes, classes) }
+    }
+    pub fn from_batch(output : &DMatrix<f64>, target : &DMatrix<f64>) -> Self {
+        let mut matrix = Self::new(target.nrows());
+        matrix.add(output, target);
+        matrix
+    }
+    pub fn add(&mut self, output : &DMatrix<f64>, target : &DMatrix<f64>) {
+        assert_eq!(output.nrows(), self.classes(), "output has {} rows for {} classes", output.nrows(), self.classes());
+        for (o, t) in output.column_iter().zip(target.column_iter()) {
+            self.counts[(t.imax(), o.imax())] += 1;
+        }
+    }
+    pub fn counts(&self) -> &DMatrix<usize> {
+        &self.counts
+    }
+    pub fn classes(&self) -> usize {
+        self.counts.nrows()
+    }
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+    pub fn correct(&self) -> usize {
+        self.counts.diagonal().iter().sum()
+    }
+    pub fn accuracy(&self) -> f64 {
+        ratio(self.correct(), self.total())
+    }
+
+    fn predicted(&self, class : usize) -> usize {
+        self.counts.column(class).iter().sum()
+    }
+    fn actual(&self, class : usize) -> usize {
+        self.counts.row(class).iter().sum()
+    }
+    //a class that is never predicted (or never occurs) scores 0 rather than NaN
+    pub fn precision(&self, class : usize) -> f64 {
+        ratio(self.counts[(class, class)], self.predicted(class))
+    }
+    pub fn recall(&self, class : usize) -> f64 {
+        ratio(self.counts[(class, class)], self.actual(class))
+    }
+    pub fn f1(&self, class : usize) -> f64 {
+        harmonic_mean(self.precision(class), self.recall(class))
+    }
+    pub fn scores(&self, class : usize) -> ClassScores {
+        ClassScores {
+            precision : self.precision(class),
+            recall : self.recall(class),
+            f1 : self.f1(class),
+            support : self.actual(class),
+        }
+    }
+    pub fn per_class(&self) -> Vec<ClassScores> {
+        (0..self.classes()).map(|class| self.scores(class)).collect()
+    }
+
+    //unweighted means over classes, so rare classes count as much as common ones
+    pub fn macro_precision(&self) -> f64 {
+        self.class_mean(|class| self.precision(class))
+    }
+    pub fn macro_recall(&self) -> f64 {
+        self.class_mean(|class| self.recall(class))
+    }
+    pub fn macro_f1(&self) -> f64 {
+        self.class_mean(|class| self.f1(class))
+    }
+    fn class_mean(&self, score : impl Fn(usize) -> f64) -> f64 {
+        (0..self.classes()).map(score).sum::<f64>() / self.classes().max(1) as f64
+    }
+
+    //pooled over all samples; with one label per sample these all equal accuracy
+    pub fn micro_precision(&self) -> f64 {
+        let predicted = (0..self.classes()).map(|class| self.predicted(class)).sum();
+        ratio(self.correct(), predicted)
+    }
+    pub fn micro_recall(&self) -> f64 {
+        let actual = (0..self.classes()).map(|class| self.actual(class)).sum();
+        ratio(self.correct(), actual)
+    }
+    pub fn micro_f1(&self) -> f64 {
+        harmonic_mean(self.micro_precision(), self.micro_recall())
+    }
+}
+
+impl fmt::Display for ConfusionMatrix {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        let width = self.counts.iter().max().map_or(1, |max| max.to_string().len()).max(self.classes().to_string().len());
+        write!(f, "{:>w$} |", "", w = width)?;
+        for class in 0..self.classes() {
+            write!(f, " {:>w$}", class, w = width)?;
+        }
+        writeln!(f)?;
+        for (class, row) in self.counts.row_iter().enumerate() {
+            write!(f, "{:>w$} |", class, w = width)?;
+            for count in row.iter() {
+                write!(f, " {:>w$}", count, w = width)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+fn ratio(numerator : usize, denominator : usize) -> f64 {
+    if denominator == 0 {0.0} else {numerator as f64 / denominator as f64}
+}
+
+fn harmonic_mean(a : f64, b : f64) -> f64 {
+    if a + b == 0.0 {0.0} else {2.0 * a * b / (a + b)}
+}
+
+//a model's outputs on a dataset next to the targets, from NeuralNetwork::evaluate or CNN::evaluate
+#[derive(Debug, Clone)]
+pub struct Evaluation {
+    pub loss : f64,
+    pub output : DMatrix<f64>,
+    pub target : DMatrix<f64>,
+}
+
+impl Evaluation {
+    pub fn new(loss : &dyn Loss, output : DMatrix<f64>, targets : &[DVector<f64>]) -> Self {
+        let target = stack(targets, output.nrows());
+        Self { loss : loss.compute_batch(&output, &target), output, target }
+    }
+    pub fn accuracy(&self) -> f64 {
+        accuracy(&self.output, &self.target)
+    }
+    pub fn top_k_accuracy(&self, k : usize) -> f64 {
+        top_k_accuracy(&self.output, &self.target, k)
+    }
+    pub fn confusion_matrix(&self) -> ConfusionMatrix {
+        ConfusionMatrix::from_batch(&self.output, &self.target)
+    }
+    pub fn mean_absolute_error(&self) -> f64 {
+        mean_absolute_error(&self.output, &self.target)
+    }
+    pub fn root_mean_squared_error(&self) -> f64 {
+        root_mean_squared_error(&self.output, &self.target)
+    }
+    pub fn r2_score(&self) -> f64 {
+        r2_score(&self.output, &self.target)
+    }
+}
+
+//runs `forward` over the samples in fixed-size chunks and joins the outputs column by column
+pub(crate) fn predict_batches(inputs : &[DVector<f64>], mut forward : impl FnMut(&DMatrix<f64>) -> DMatrix<f64>) -> DMatrix<f64> {
+    let indices = (0..inputs.len()).collect::<Vec<usize>>();
+    let mut columns = Vec::with_capacity(inputs.len());
+    for chunk in indices.chunks(PREDICT_BATCH) {
+        let output = forward(&gather(inputs, chunk));
+        columns.extend(output.column_iter().map(|column| column.into_owned()));
+    }
+    stack(&columns, 0)
+}
+
+//from_columns panics on an empty slice
+fn stack(samples : &[DVector<f64>], rows : usize) -> DMatrix<f64> {
+    if samples.is_empty() {DMatrix::zeros(rows, 0)} else {DMatrix::from_columns(samples)}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //one-hot columns of the given classes
+    fn one_hot(classes : usize, labels : &[usize]) -> DMatrix<f64> {
+        DMatrix::from_fn(classes, labels.len(), |r, c| if r == labels[c] {1.0} else {0.0})
+    }
+
+    fn close(name : &str, value : f64, expected : f64) {
+        assert!((value - expected).abs() < 1e-12, "{} is {}, expected {}", name, value, expected);
+    }
+
+    #[test]
+    fn confusion_matrix_scores() {
+        //class 2 is never predicted and class 3 never occurs
+        let actual = [0, 0, 0, 1, 1, 2];
+        let predicted = [0, 0, 1, 1, 0, 0];
+        let matrix = ConfusionMatrix::from_batch(&one_hot(4, &predicted), &one_hot(4, &actual));
+        assert_eq!(matrix.counts().row(0).iter().copied().collect::<Vec<usize>>(), vec![2, 1, 0, 0]);
+        assert_eq!(matrix.counts()[(2, 0)], 1);
+        assert_eq!((matrix.total(), matrix.correct()), (6, 3));
+        let expected = [(0.5, 2.0 / 3.0, 4.0 / 7.0, 3), (0.5, 0.5, 0.5, 2), (0.0, 0.0, 0.0, 1), (0.0, 0.0, 0.0, 0)];
+        for (class, (scores, (precision, recall, f1, support))) in matrix.per_class().into_iter().zip(expected).enumerate() {
+            close(&format!("class {} precision", class), scores.precision, precision);
+            close(&format!("class {} recall", class), scores.recall, recall);
+            close(&format!("class {} f1", class), scores.f1, f1);
+            assert_eq!(scores.support, support);
+        }
+        close("macro precision", matrix.macro_precision(), 1.0 / 4.0);
+        close("macro recall", matrix.macro_recall(), (2.0 / 3.0 + 0.5) / 4.0);
+        close("macro f1", matrix.macro_f1(), (4.0 / 7.0 + 0.5) / 4.0);
+        for (name, value) in [("accuracy", matrix.accuracy()), ("micro precision", matrix.micro_precision()),
+                              ("micro recall", matrix.micro_recall()), ("micro f1", matrix.micro_f1())] {
+            close(name, value, 0.5);
+        }
+        close("macro_f1", macro_f1(&one_hot(4, &predicted), &one_hot(4, &actual)), matrix.macro_f1());
+        //nothing counted at all is 0 too
+        let empty = ConfusionMatrix::new(3);
+        assert_eq!((empty.accuracy(), empty.macro_f1(), empty.micro_f1()), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn top_k_counts_the_true_class_among_the_largest() {
+        let output = DMatrix::from_column_slice(4, 3, &[
+            0.1, 0.5, 0.3, 0.1, //true class 2 comes second
+            0.7, 0.1, 0.1, 0.1, //true class 0 comes first
+            0.4, 0.3, 0.2, 0.1, //true class 3 comes last
+        ]);
+        let target = one_hot(4, &[2, 0, 3]);
+        close("top 1", top_k_accuracy(&output, &target, 1), 1.0 / 3.0);
+        close("accuracy", accuracy(&output, &target), 1.0 / 3.0);
+        close("top 2", top_k_accuracy(&output, &target, 2), 2.0 / 3.0);
+        close("top 4", top_k_accuracy(&output, &target, 4), 1.0);
+        //a tie with the true class counts in its favour
+        let tied = DMatrix::from_column_slice(3, 1, &[0.5, 0.5, 0.0]);
+        close("tied top 1", top_k_accuracy(&tied, &one_hot(3, &[1]), 1), 1.0);
+    }
+
+    #[test]
+    fn regression_metrics() {
+        let target = DMatrix::from_row_slice(1, 4, &[1.0, 2.0, 3.0, 4.0]);
+        let output = DMatrix::from_row_slice(1, 4, &[1.0, 2.0, 3.0, 6.0]);
+        close("mae", mean_absolute_error(&output, &target), 0.5);
+        close("rmse", root_mean_squared_error(&output, &target), 1.0);
+        //residual 4 against a total of 5 around the mean 2.5
+        close("r2", r2_score(&output, &target), 0.2);
+        close("perfect r2", r2_score(&target, &target), 1.0);
+        close("mean r2", r2_score(&DMatrix::from_element(1, 4, 2.5), &target), 0.0);
+        //each row against its own mean
+        let rows = DMatrix::from_row_slice(2, 2, &[0.0, 2.0, 10.0, 30.0]);
+        let off = DMatrix::from_row_slice(2, 2, &[0.0, 2.0, 20.0, 20.0]);
+        close("two-row r2", r2_score(&off, &rows), 1.0 - 200.0 / 202.0);
+        //constant targets have no variance to explain
+        let constant = DMatrix::from_element(1, 3, 2.0);
+        close("constant r2, exact", r2_score(&constant, &constant), 1.0);
+        close("constant r2, off", r2_score(&DMatrix::from_row_slice(1, 3, &[2.0, 2.5, 1.0]), &constant), 0.0);
+    }
+}
@@ -1,65 +1,182 @@
 use nalgebra::{DMatrix, DVector};
+use std::io;
+use std::path::Path;
 use rand::Rng;
+use crate::activation::{Activation, Relu};
+use crate::autodiff::{Tape, Var};
+use crate::init::Initializer;
+use crate::metrics::{self, Evaluation};
+use crate::optimizer::{Optimizer, Parameter, Sgd};
+use crate::scheduler::{Interval, LrScheduler, Schedule};
+use crate::rng;
+use crate::serialization::{self, LayerSpec, ModelSpec};
+use crate::shape::{Shape, ShapeError};
+use crate::trainer::{self, Model};
 
 pub trait Loss {
-    fn compute(&self, result: &DVector<f64>, test : &DVector<f64>) -> f64;
-    fn gradient(&self, result : &DVector<f64>, test : &DVector<f64>) -> DVector<f64>;
+    //compute and gradient come from compute_tape unless a loss writes them out
+    fn compute(&self, result: &DVector<f64>, test : &DVector<f64>) -> f64 {
+        let tape = Tape::new();
+        self.compute_tape(&tape.var(to_batch(result)), &to_batch(test)).value()[0]
+    }
+    fn gradient(&self, result : &DVector<f64>, test : &DVector<f64>) -> DVector<f64> {
+        let tape = Tape::new();
+        let output = tape.var(to_batch(result));
+        self.compute_tape(&output, &to_batch(test)).backward().wrt(&output).column(0).into_owned()
+    }
+    fn name(&self) -> String; //round-trips through loss::from_name, used when saving models
+    //mean loss over a batch with one sample per column
+    fn compute_batch(&self, result : &DMatrix<f64>, test : &DMatrix<f64>) -> f64 {
+        let total = result.column_iter().zip(test.column_iter())
+            .map(|(r, t)| self.compute(&r.into_owned(), &t.into_owned()))
+            .sum::<f64>();
+        total / result.ncols() as f64
+    }
+    //gradient of compute_batch, so layers only have to sum over the columns
+    fn gradient_batch(&self, result : &DMatrix<f64>, test : &DMatrix<f64>) -> DMatrix<f64> {
+        let n = result.ncols() as f64;
+        let columns = result.column_iter().zip(test.column_iter())
+            .map(|(r, t)| self.gradient(&r.into_owned(), &t.into_owned()) / n)
+            .collect::<Vec<DVector<f64>>>();
+        DMatrix::from_columns(&columns)
+    }
+    //compute_batch as a 1×1 autodiff expression
+    fn compute_tape(&self, result : &Var, test : &DMatrix<f64>) -> Var;
 }
 pub trait Layer {
-    fn forward(&mut self, input : &DVector<f64>) -> DVector<f64>; //forward feed
-    fn backward(&mut self, error: &DVector<f64>, learn: f64) -> DVector <f64>; //backward feed for backprop
+    //inputs and errors hold one sample per column
+    fn forward(&mut self, input : &DMatrix<f64>) -> DMatrix<f64>; //forward feed
+    fn backward(&mut self, error: &DMatrix<f64>) -> DMatrix<f64>; //backward feed for backprop, stores gradients
+    fn parameters(&mut self) -> Vec<Parameter<'_>> { //trainable tensors paired with their last gradients
+        Vec::new()
+    }
+    fn spec(&self) -> Option<LayerSpec> { //architecture and weights for saving, None if unsupported
+        None
+    }
+    fn input_shape(&self) -> Option<Shape> { //the only input shape this layer accepts, if fixed
+        None
+    }
+    //checks the input shape, remembers it if the layer needs it, and returns the output shape
+    fn build(&mut self, input : Shape) -> Result<Shape, ShapeError> {
+        Ok(input)
+    }
+    //the layer as an autodiff expression, `params` are column-vector leaves in parameters() order;
+    //None for layers that only have a hand-written backward
+    fn forward_tape(&self, _input : &Var, _params : &[Var]) -> Option<Var> {
+        None
+    }
 }
 pub struct DenseLayer {
     weights : DMatrix<f64>,
     biases : DVector<f64>,
+    input : DMatrix<f64>, //cached input from the last forward pass
+    z : DMatrix<f64>, //cached pre-activation from the last forward pass
+    activation : Box<dyn Activation>,
+    grad_weights : DMatrix<f64>,
+    grad_biases : DVector<f64>,
 }
 
 impl DenseLayer {
     pub fn new(input : usize, output : usize) -> Self {
-        let mut rVals = rand::thread_rng();
-        let weights = DMatrix::<f64>::from_iterator(
-            output,
-            input,
-            (0..output*input).map(|_| rVals.gen_range(-0.1..0.1)));
-        let biases = DVector::<f64>::from_iterator(
-            output,
-            (0..output).map(|_| rVals.gen_range(-0.1..0.1)));
+        Self::with_activation(input, output, Box::new(Relu))
+    }
+    pub fn with_activation(input : usize, output : usize, activation : Box<dyn Activation>) -> Self {
+        rng::with_global(|r_vals| Self::with_activation_rng(input, output, activation, r_vals))
+    }
+    pub fn with_activation_rng<R : Rng + ?Sized>(input : usize, output : usize, activation : Box<dyn Activation>, r_vals : &mut R) -> Self {
+        let init = Initializer::Uniform(-0.1, 0.1);
+        let weights = init.dense(input, output, r_vals);
+        let biases = init.sample(output, 1, input, output, r_vals).column(0).into_owned();
+        Self::from_parts(weights, biases, activation)
+    }
+    //weights drawn from `init` using this layer's fan-in/fan-out, biases start at zero
+    pub fn with_initializer(input : usize, output : usize, activation : Box<dyn Activation>, init : Initializer) -> Self {
+        rng::with_global(|r_vals| Self::with_initializer_rng(input, output, activation, init, r_vals))
+    }
+    pub fn with_initializer_rng<R : Rng + ?Sized>(input : usize, output : usize, activation : Box<dyn Activation>, init : Initializer, r_vals : &mut R) -> Self {
+        let weights = init.dense(input, output, r_vals);
+        Self::from_parts(weights, DVector::zeros(output), activation)
+    }
+    pub fn from_parts(weights : DMatrix<f64>, biases : DVector<f64>, activation : Box<dyn Activation>) -> Self {
+        let (output, input) = weights.shape();
         Self {
             weights,
             biases,
+            input : DMatrix::zeros(input, 0),
+            z : DMatrix::zeros(output, 0),
+            activation,
+            grad_weights : DMatrix::zeros(output, input),
+            grad_biases : DVector::zeros(output),
         }
     }
 }
 
 impl Layer for DenseLayer {
-    fn forward(&mut self, input : &DVector<f64>) -> DVector<f64> {
-        let z = &self.weights * input + &self.biases;
-        let mut activated = z.clone();
-        activated.apply(|x| {*x = x.max(0.0);});
-        return activated;
-    }
-    fn backward(&mut self, error: &DVector<f64>, learn: f64) -> DVector<f64> {
-        let err_col = DMatrix::from_column_slice(self.weights.nrows(),1,error.as_slice());
-        let gradient_weight = err_col.clone() * DMatrix::from_row_slice(1, self.weights.ncols(), vec![1.0; self.weights.ncols()].as_slice());
-        self.weights-= learn * gradient_weight;
-        self.biases-=learn*err_col.column(0);
-        return DVector::from_element(self.weights.ncols(),0.1);
+    fn forward(&mut self, input : &DMatrix<f64>) -> DMatrix<f64> {
+        let mut z = &self.weights * input;
+        for mut column in z.column_iter_mut() {
+            column += &self.biases;
+        }
+        let activated = self.activation.apply(&z);
+        self.input = input.clone();
+        self.z = z;
+        activated
+    }
+    fn backward(&mut self, error: &DMatrix<f64>) -> DMatrix<f64> {
+        //dL/dz = dL/da * f'(z)
+        let delta = self.activation.backward(&self.z, error);
+        //the loss already divides by the batch size, so summing over columns averages
+        self.grad_weights = &delta * self.input.transpose();
+        self.grad_biases = delta.column_sum();
+        self.weights.transpose() * &delta
+    }
+    fn parameters(&mut self) -> Vec<Parameter<'_>> {
+        vec![
+            Parameter { value : self.weights.as_mut_slice(), grad : self.grad_weights.as_slice() },
+            Parameter { value : self.biases.as_mut_slice(), grad : self.grad_biases.as_slice() },
+        ]
+    }
+    fn spec(&self) -> Option<LayerSpec> {
+        Some(LayerSpec::Dense {
+            weights : self.weights.clone(),
+            biases : self.biases.clone(),
+            activation : self.activation.name(),
+        })
+    }
+    fn input_shape(&self) -> Option<Shape> {
+        Some(Shape::vector(self.weights.ncols()))
+    }
+    fn forward_tape(&self, input : &Var, params : &[Var]) -> Option<Var> {
+        let weights = params[0].reshape(self.weights.nrows(), self.weights.ncols());
+        let z = weights.matmul(input).add(&params[1].broadcast_columns(input.cols()));
+        Some(z.activation(&*self.activation))
+    }
+    fn build(&mut self, input : Shape) -> Result<Shape, ShapeError> {
+        if input.len() != self.weights.ncols() {
+            return Err(ShapeError::new(format!("expects {} inputs but receives {} values", self.weights.ncols(), input.len())));
+        }
+        Ok(Shape::vector(self.weights.nrows()))
     }
 }
 pub struct MeanSquaredError;
 impl Loss for MeanSquaredError{
+    fn name(&self) -> String {
+        "mean_squared_error".to_string()
+    }
     fn compute(&self, result : &DVector<f64>, test: &DVector<f64>)->f64 {
         let diff = result - test;
         let loss = diff.iter().map(|x| x * x).sum::<f64>()/result.len() as f64;
         if loss.is_nan() {
             eprintln!("NAN DETECTED");
         }
-        return loss;
+        loss
     }
     fn gradient(&self, result : &DVector<f64>, test: &DVector<f64>) -> DVector<f64> {
         let grad = 2.0 * (result-test)/result.len() as f64;
-        let grad = grad.map(|x| x.clamp(-1e10, 1e10));
-        return grad;
+        grad.map(|x| x.clamp(-1e10, 1e10))
+    }
+    fn compute_tape(&self, result : &Var, test : &DMatrix<f64>) -> Var {
+        result.add_constant(&-test).square().mean()
     }
 }
 impl Clone for MeanSquaredError {
@@ -71,28 +188,146 @@ impl Clone for MeanSquaredError {
 pub struct NeuralNetwork {
     layers : Vec<Box<dyn Layer>>,
     loss : Box<dyn Loss>,
+    optimizer : Box<dyn Optimizer>,
+    autodiff : bool, //forward_batch records a tape and backward_batch runs it instead of each layer's backward
+    tape : Option<(Var, Var, Vec<Var>)>, //input, output and parameter leaves of the last forward_batch with autodiff
+    tape_grads : Vec<DMatrix<f64>>, //parameter gradients of the last backward_batch with autodiff
+    schedule : Option<Schedule>, //applied by fit
 }
 
 impl NeuralNetwork {
     pub fn new(layers : Vec<Box<dyn Layer>>, loss : Box<dyn Loss>) -> Self {
+        Self::with_optimizer(layers, loss, Box::new(Sgd::new(0.01)))
+    }
+    pub fn with_optimizer(layers : Vec<Box<dyn Layer>>, loss : Box<dyn Loss>, optimizer : Box<dyn Optimizer>) -> Self {
         NeuralNetwork {
             layers,
-            loss
+            loss,
+            optimizer,
+            autodiff : false,
+            tape : None,
+            tape_grads : Vec::new(),
+            schedule : None,
         }
     }
+    pub fn set_optimizer(&mut self, optimizer : Box<dyn Optimizer>) {
+        self.optimizer = optimizer;
+    }
+    pub fn set_scheduler(&mut self, scheduler : Box<dyn LrScheduler>, interval : Interval) {
+        self.schedule = Some(Schedule::new(scheduler, interval));
+    }
+    //trains on gradients from the tape instead of the layers' backward; every layer must implement forward_tape
+    pub fn with_autodiff(mut self, autodiff : bool) -> Self {
+        self.autodiff = autodiff;
+        self
+    }
     pub fn forward(&mut self, input : &DVector<f64>) -> DVector<f64> {
+        let result = self.forward_batch(&to_batch(input));
+        result.column(0).into_owned()
+    }
+    pub fn forward_batch(&mut self, input : &DMatrix<f64>) -> DMatrix<f64> {
+        if self.autodiff {
+            let x = Tape::new().var(input.clone());
+            let (output, leaves) = self.forward_tape(&x);
+            let result = output.value();
+            self.tape = Some((x, output, leaves));
+            return result;
+        }
         let mut result = input.clone();
         for layer in self.layers.iter_mut() {
             result = layer.forward(&result);
         }
-        return result;
+        result
     }
     pub fn backprop(&mut self, input : &DVector<f64>, test : &DVector<f64>, learn : f64) {
-        let result = self.forward(input);
-        let mut error = self.loss.gradient(&result, test);
-        for layer in self.layers.iter_mut().rev() {
-            error = layer.backward(&error, learn);
+        self.optimizer.set_learning_rate(learn);
+        self.backprop_batch(&to_batch(input), &to_batch(test));
+    }
+    //one optimizer step on the batch-averaged gradient, returns the batch loss
+    pub fn backprop_batch(&mut self, input : &DMatrix<f64>, test : &DMatrix<f64>) -> f64 {
+        let result = self.forward_batch(input);
+        let loss = self.loss.compute_batch(&result, test);
+        let error = self.loss.gradient_batch(&result, test);
+        self.backward_batch(&error);
+        self.step();
+        loss
+    }
+    //records the network on `input`'s tape; the returned leaves stand for parameters() in order
+    pub fn forward_tape(&mut self, input : &Var) -> (Var, Vec<Var>) {
+        let leaves = self.leaves(input.tape());
+        (self.forward_leaves(input, &leaves), leaves)
+    }
+    //a column-vector leaf for every entry of parameters(), so several forward passes can share them
+    pub(crate) fn leaves(&mut self, tape : &Tape) -> Vec<Var> {
+        self.layers.iter_mut().flat_map(|layer| layer.parameters())
+            .map(|p| tape.var(DMatrix::from_column_slice(p.value.len(), 1, p.value)))
+            .collect()
+    }
+    pub(crate) fn forward_leaves(&mut self, input : &Var, leaves : &[Var]) -> Var {
+        let mut output = input.clone();
+        let mut start = 0;
+        for (i, layer) in self.layers.iter_mut().enumerate() {
+            let count = layer.parameters().len();
+            output = layer.forward_tape(&output, &leaves[start..start + count]).unwrap_or_else(|| panic!("layer {} has no autodiff implementation", i));
+            start += count;
         }
+        output
+    }
+    //one optimizer step with `grads` (in parameters() order) in place of the layers' own gradients
+    pub(crate) fn step_with(&mut self, grads : &[DMatrix<f64>]) {
+        self.optimizer.step(&mut with_grads(&mut self.layers, grads));
+    }
+    //d(output_i)/d(input_j) for one sample, outputs × inputs
+    pub fn jacobian(&mut self, input : &DVector<f64>) -> DMatrix<f64> {
+        self.derivatives(input, &DMatrix::identity(input.len(), input.len())).0
+    }
+    //d²(output)/d(input)², one inputs × inputs matrix per output
+    pub fn hessian(&mut self, input : &DVector<f64>) -> Vec<DMatrix<f64>> {
+        let n = input.len();
+        let (directions, pairs) = polarization(n);
+        let curvature = self.derivatives(input, &directions).1;
+        curvature.row_iter().map(|along| {
+            let mut hessian = DMatrix::from_diagonal(&along.columns(0, n).transpose());
+            for (k, &(i, j)) in pairs.iter().enumerate() {
+                let value = (along[n + k] - hessian[(i, i)] - hessian[(j, j)]) / 2.0;
+                hessian[(i, j)] = value;
+                hessian[(j, i)] = value;
+            }
+            hessian
+        }).collect()
+    }
+    //d²(output_i)/d(input_j)², outputs × inputs; cheaper than the full hessian
+    pub fn hessian_diagonal(&mut self, input : &DVector<f64>) -> DMatrix<f64> {
+        self.derivatives(input, &DMatrix::identity(input.len(), input.len())).1
+    }
+    //sum of the unmixed second derivatives of each output
+    pub fn laplacian(&mut self, input : &DVector<f64>) -> DVector<f64> {
+        self.hessian_diagonal(input).column_sum()
+    }
+    //first and second derivatives of every output along each column of `directions`, outputs × directions
+    fn derivatives(&mut self, input : &DVector<f64>, directions : &DMatrix<f64>) -> (DMatrix<f64>, DMatrix<f64>) {
+        let tape = Tape::new();
+        let leaves = self.leaves(&tape);
+        let (_, first, second) = self.input_jets(&tape, &leaves, &to_batch(input), directions);
+        (first.value(), second.value())
+    }
+    //the network at `points` (one per column) and its first and second derivatives along each column of
+    //`directions`, recorded on `tape` with `leaves` as the parameters so a loss on them can be trained;
+    //the derivatives are outputs × (directions · points), one block of points per direction
+    pub(crate) fn input_jets(&mut self, tape : &Tape, leaves : &[Var], points : &DMatrix<f64>, directions : &DMatrix<f64>) -> (Var, Var, Var) {
+        let (dims, n) = points.shape();
+        let blocks = directions.ncols();
+        let x = tape.var(DMatrix::from_fn(dims, n * blocks, |r, c| points[(r, c % n)]));
+        let output = self.forward_leaves(&x, leaves);
+        let (first, second) = output.jets(&x, DMatrix::from_fn(dims, n * blocks, |r, c| directions[(r, c / n)]));
+        (output.columns(0, n), first, second)
+    }
+    //shuffled mini-batch training at the optimizer's learning rate (or the scheduler's), returns the mean loss of each epoch
+    pub fn fit(&mut self, inputs : &[DVector<f64>], targets : &[DVector<f64>], batch_size : usize, epochs : usize) -> Vec<f64> {
+        let mut schedule = self.schedule.take();
+        let history = trainer::fit_samples(self, &mut schedule, inputs, targets, batch_size, epochs);
+        self.schedule = schedule;
+        history
     }
 
     pub fn train(&mut self, input : &DVector<f64>, test : &DVector<f64>, learn : f64, epochs : usize) {
@@ -104,4 +339,265 @@ impl NeuralNetwork {
         let result = self.forward(input);
         self.loss.compute(&result, target)
     }
-}
\ No newline at end of file
+    //outputs for every sample, one per column
+    pub fn predict(&mut self, inputs : &[DVector<f64>]) -> DMatrix<f64> {
+        metrics::predict_batches(inputs, |batch| self.forward_batch(batch))
+    }
+    //loss on the whole set, plus the outputs for accuracy, confusion matrix, r2 and friends
+    pub fn evaluate(&mut self, inputs : &[DVector<f64>], targets : &[DVector<f64>]) -> Evaluation {
+        let output = self.predict(inputs);
+        Evaluation::new(&*self.loss, output, targets)
+    }
+
+    pub fn spec(&self) -> io::Result<ModelSpec> {
+        Ok(ModelSpec {
+            stacks : vec![serialization::describe_layers(&self.layers)?],
+            loss : self.loss.name(),
+        })
+    }
+    pub fn from_spec(spec : ModelSpec) -> io::Result<Self> {
+        let loss = serialization::build_loss(&spec.loss)?;
+        let mut stacks = spec.stacks.into_iter();
+        let mut layers = match (stacks.next(), stacks.next()) {
+            (Some(layers), None) => serialization::build_layers(layers)?,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "expected a single layer stack")),
+        };
+        serialization::check_layers(&mut layers, "dense")?;
+        Ok(Self::new(layers, loss))
+    }
+    //versioned binary file with a checksum; the optimizer state is not saved
+    pub fn save<P : AsRef<Path>>(&self, path : P) -> io::Result<()> {
+        serialization::save_binary(&self.spec()?, path)
+    }
+    pub fn load<P : AsRef<Path>>(path : P) -> io::Result<Self> {
+        Self::from_spec(serialization::load_binary(path)?)
+    }
+    //human-readable variant of save
+    pub fn save_json<P : AsRef<Path>>(&self, path : P) -> io::Result<()> {
+        serialization::save_json(&self.spec()?, path)
+    }
+    pub fn load_json<P : AsRef<Path>>(path : P) -> io::Result<Self> {
+        Self::from_spec(serialization::load_json(path)?)
+    }
+}
+impl Model for NeuralNetwork {
+    fn forward_batch(&mut self, input : &DMatrix<f64>) -> DMatrix<f64> {
+        NeuralNetwork::forward_batch(self, input)
+    }
+    fn backward_batch(&mut self, error : &DMatrix<f64>) -> DMatrix<f64> {
+        if self.autodiff {
+            let (x, output, leaves) = self.tape.as_ref().expect("backward_batch called before forward_batch");
+            let grads = output.backward_with(error.clone());
+            self.tape_grads = leaves.iter().map(|leaf| grads.wrt(leaf)).collect();
+            return grads.wrt(x);
+        }
+        let mut error = error.clone();
+        for layer in self.layers.iter_mut().rev() {
+            error = layer.backward(&error);
+        }
+        error
+    }
+    fn parameters(&mut self) -> Vec<Parameter<'_>> {
+        if self.autodiff {
+            return with_grads(&mut self.layers, &self.tape_grads);
+        }
+        self.layers.iter_mut().flat_map(|layer| layer.parameters()).collect()
+    }
+    fn loss(&self) -> &dyn Loss {
+        &*self.loss
+    }
+    fn optimizer(&mut self) -> &mut dyn Optimizer {
+        &mut *self.optimizer
+    }
+    fn step(&mut self) {
+        let mut params = if self.autodiff {
+            with_grads(&mut self.layers, &self.tape_grads)
+        }
+        else {
+            self.layers.iter_mut().flat_map(|layer| layer.parameters()).collect()
+        };
+        self.optimizer.step(&mut params);
+    }
+}
+
+//the layers' parameters paired with gradients from a tape instead of their own
+fn with_grads<'a>(layers : &'a mut [Box<dyn Layer>], grads : &'a [DMatrix<f64>]) -> Vec<Parameter<'a>> {
+    layers.iter_mut().flat_map(|layer| layer.parameters()).zip(grads.iter())
+        .map(|(param, grad)| Parameter { value : param.value, grad : grad.as_slice() })
+        .collect()
+}
+
+//a single sample as a one-column batch
+pub fn to_batch(sample : &DVector<f64>) -> DMatrix<f64> {
+    DMatrix::from_column_slice(sample.len(), 1, sample.as_slice())
+}
+
+//u'Hu along e_i gives the diagonal of H and along e_i + e_j (every pair j < i) the rest:
+//H_ij = (u'Hu - H_ii - H_jj) / 2; returns the directions as columns, e_i first, and the pairs
+pub(crate) fn polarization(n : usize) -> (DMatrix<f64>, Vec<(usize, usize)>) {
+    let pairs = (0..n).flat_map(|i| (0..i).map(move |j| (i, j))).collect::<Vec<(usize, usize)>>();
+    let mut directions = DMatrix::zeros(n, n + pairs.len());
+    directions.columns_mut(0, n).fill_with_identity();
+    for (k, &(i, j)) in pairs.iter().enumerate() {
+        directions[(i, n + k)] = 1.0;
+        directions[(j, n + k)] = 1.0;
+    }
+    (directions, pairs)
+}
+
+//stacks the selected samples into a batch, one per column
+pub fn gather(samples : &[DVector<f64>], indices : &[usize]) -> DMatrix<f64> {
+    let columns = indices.iter().map(|&i| samples[i].clone()).collect::<Vec<DVector<f64>>>();
+    DMatrix::from_columns(&columns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::activation::{self, ActivationLayer, Gelu, Identity, Sigmoid, Tanh};
+    use crate::convnn::ConvLayer;
+    use crate::gradcheck::{assert_close, central_difference, ACTIVATIONS};
+    use crate::pooling::{AvgPool2d, GlobalAveragePool, MaxPool2d};
+    use crate::shape::{build_stack, Flatten, Reshape};
+
+    //batch loss of one dense layer under MSE
+    fn batch_loss(layer : &mut DenseLayer, input : &DMatrix<f64>, target : &DMatrix<f64>) -> f64 {
+        MeanSquaredError.compute_batch(&layer.forward(input), target)
+    }
+
+    #[test]
+    fn dense_backward_matches_central_differences() {
+        let mut r_vals = rng::seeded(1);
+        let activations : Vec<Box<dyn Activation>> = vec![Box::new(Identity), Box::new(Tanh), Box::new(Sigmoid)];
+        for activation in activations {
+            let name = activation.name();
+            let mut layer = DenseLayer::with_activation_rng(4, 3, activation, &mut r_vals);
+            let mut input = DMatrix::from_fn(4, 5, |_, _| r_vals.gen_range(-1.0..1.0));
+            let target = DMatrix::from_fn(3, 5, |_, _| r_vals.gen_range(-1.0..1.0));
+            let output = layer.forward(&input);
+            let grad_input = layer.backward(&MeanSquaredError.gradient_batch(&output, &target));
+            let (grad_weights, grad_biases) = (layer.grad_weights.clone(), layer.grad_biases.clone());
+
+            let numeric = central_difference(layer.weights.len(), |i, delta| {
+                layer.weights[i] += delta;
+                let loss = batch_loss(&mut layer, &input, &target);
+                layer.weights[i] -= delta;
+                loss
+            });
+            assert_close(&format!("{} weight gradient", name), grad_weights.as_slice(), &numeric, 1e-7);
+            let numeric = central_difference(layer.biases.len(), |i, delta| {
+                layer.biases[i] += delta;
+                let loss = batch_loss(&mut layer, &input, &target);
+                layer.biases[i] -= delta;
+                loss
+            });
+            assert_close(&format!("{} bias gradient", name), grad_biases.as_slice(), &numeric, 1e-7);
+            let numeric = central_difference(input.len(), |i, delta| {
+                input[i] += delta;
+                let loss = batch_loss(&mut layer, &input, &target);
+                input[i] -= delta;
+                loss
+            });
+            assert_close(&format!("{} input gradient", name), grad_input.as_slice(), &numeric, 1e-7);
+        }
+    }
+
+    #[test]
+    fn autodiff_matches_the_layers_backward() {
+        let build = |autodiff : bool| {
+            let mut r_vals = rng::seeded(2);
+            let layers : Vec<Box<dyn Layer>> = vec![
+                Box::new(DenseLayer::with_activation_rng(3, 4, Box::new(Tanh), &mut r_vals)),
+                Box::new(DenseLayer::with_activation_rng(4, 2, Box::new(Sigmoid), &mut r_vals)),
+            ];
+            NeuralNetwork::with_optimizer(layers, Box::new(MeanSquaredError), Box::new(Sgd::new(0.5))).with_autodiff(autodiff)
+        };
+        let (mut layers, mut tape) = (build(false), build(true));
+        let mut r_vals = rng::seeded(3);
+        let input = DMatrix::from_fn(3, 4, |_, _| r_vals.gen_range(-1.0..1.0));
+        let target = DMatrix::from_fn(2, 4, |_, _| r_vals.gen_range(0.0..1.0));
+        let mut grads = Vec::new();
+        for network in [&mut layers, &mut tape] {
+            let output = Model::forward_batch(network, &input);
+            let error = MeanSquaredError.gradient_batch(&output, &target);
+            let grad_input = network.backward_batch(&error);
+            let params = network.parameters().iter().flat_map(|p| p.grad.to_vec()).collect::<Vec<f64>>();
+            network.step();
+            let values = network.parameters().iter().flat_map(|p| p.value.to_vec()).collect::<Vec<f64>>();
+            grads.push((grad_input, params, values));
+        }
+        assert_close("input gradient", grads[0].0.as_slice(), grads[1].0.as_slice(), 1e-12);
+        assert_close("parameter gradient", &grads[0].1, &grads[1].1, 1e-12);
+        assert_close("parameters after a step", &grads[0].2, &grads[1].2, 1e-12);
+    }
+
+    //jacobian against central differences of forward, hessian against central differences of the
+    //jacobian, and laplacian against the hessian's traces
+    fn check_input_derivatives(name : &str, network : &mut NeuralNetwork, x : &DVector<f64>) {
+        let mut x = x.clone();
+        let jacobian = network.jacobian(&x);
+        let hessians = network.hessian(&x);
+        let laplacian = network.laplacian(&x);
+        for (o, hessian) in hessians.iter().enumerate() {
+            let numeric = central_difference(x.len(), |j, delta| {
+                x[j] += delta;
+                let value = network.forward(&x)[o];
+                x[j] -= delta;
+                value
+            });
+            assert_close(&format!("{} jacobian row {}", name, o), jacobian.row(o).transpose().as_slice(), &numeric, 1e-7);
+            for i in 0..x.len() {
+                let numeric = central_difference(x.len(), |j, delta| {
+                    x[j] += delta;
+                    let value = network.jacobian(&x)[(o, i)];
+                    x[j] -= delta;
+                    value
+                });
+                assert_close(&format!("{} hessian {} row {}", name, o, i), hessian.row(i).transpose().as_slice(), &numeric, 1e-6);
+            }
+            assert!((laplacian[o] - hessian.trace()).abs() < 1e-12, "{} laplacian does not match the hessian", name);
+        }
+    }
+
+    #[test]
+    fn input_derivatives_match_central_differences_for_every_activation() {
+        let mut r_vals = rng::seeded(11);
+        for name in ACTIVATIONS {
+            let activation = || activation::from_name(name).unwrap();
+            let layers : Vec<Box<dyn Layer>> = vec![
+                Box::new(DenseLayer::with_activation_rng(3, 6, activation(), &mut r_vals)),
+                Box::new(DenseLayer::with_activation_rng(6, 4, Box::new(Identity), &mut r_vals)),
+                Box::new(ActivationLayer::new(activation())),
+                Box::new(DenseLayer::with_activation_rng(4, 2, activation(), &mut r_vals)),
+            ];
+            //the default weights are small, scale them up so every activation bends
+            let mut network = NeuralNetwork::new(layers, Box::new(MeanSquaredError));
+            for param in Model::parameters(&mut network) {
+                param.value.iter_mut().for_each(|w| *w *= 12.0);
+            }
+            let x = DVector::from_fn(3, |_, _| r_vals.gen_range(-1.0..1.0));
+            check_input_derivatives(name, &mut network, &x);
+        }
+    }
+
+    #[test]
+    fn input_derivatives_through_conv_and_pooling() {
+        let mut r_vals = rng::seeded(12);
+        let mut layers : Vec<Box<dyn Layer>> = vec![
+            Box::new(Reshape::new(Shape::new(1, 5, 4))),
+            Box::new(ConvLayer::new_rng(1, 3, 3, 1, 1, &mut r_vals)),
+            Box::new(ActivationLayer::new(Box::new(Tanh))),
+            Box::new(MaxPool2d::new(2, 1, 0)),
+            Box::new(ConvLayer::new_rng(3, 2, 2, 1, 1, &mut r_vals)),
+            Box::new(ActivationLayer::new(Box::new(Gelu))),
+            Box::new(AvgPool2d::new(2, 2, 1)),
+            Box::new(GlobalAveragePool::new()),
+            Box::new(Flatten),
+            Box::new(DenseLayer::with_activation_rng(2, 2, Box::new(Tanh), &mut r_vals)),
+        ];
+        build_stack(&mut layers, Shape::new(1, 1, 20), "convolutional").unwrap();
+        let mut network = NeuralNetwork::new(layers, Box::new(MeanSquaredError));
+        let x = DVector::from_fn(20, |_, _| r_vals.gen_range(-1.0..1.0));
+        check_input_derivatives("conv", &mut network, &x);
+    }
+}
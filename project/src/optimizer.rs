@@ -0,0 +1,339 @@
+use nalgebra::DVector;
+
+//a view into one of a layer's parameter tensors and the gradient from the last backward pass
+pub struct Parameter<'a> {
+    pub value : &'a mut [f64],
+    pub grad : &'a [f64],
+}
+
+pub trait Optimizer {
+    //params arrive in the same order on every call, so state is kept per index
+    fn step(&mut self, params : &mut [Parameter]);
+    fn learning_rate(&self) -> f64;
+    fn set_learning_rate(&mut self, learn : f64);
+}
+
+//lazily sizes one state buffer per parameter tensor
+fn init_state(state : &mut Vec<DVector<f64>>, params : &[Parameter]) {
+    if state.len() != params.len() {
+        *state = params.iter().map(|p| DVector::zeros(p.value.len())).collect();
+    }
+}
+
+pub struct Sgd {
+    learn : f64,
+    momentum : f64,
+    nesterov : bool,
+    velocity : Vec<DVector<f64>>,
+}
+
+impl Sgd {
+    pub fn new(learn : f64) -> Self {
+        Self::with_momentum(learn, 0.0)
+    }
+    pub fn with_momentum(learn : f64, momentum : f64) -> Self {
+        Sgd {
+            learn,
+            momentum,
+            nesterov : false,
+            velocity : Vec::new(),
+        }
+    }
+    pub fn nesterov(learn : f64, momentum : f64) -> Self {
+        Sgd {
+            nesterov : true,
+            ..Self::with_momentum(learn, momentum)
+        }
+    }
+}
+
+impl Optimizer for Sgd {
+    fn step(&mut self, params : &mut [Parameter]) {
+        init_state(&mut self.velocity, params);
+        for (param, v) in params.iter_mut().zip(self.velocity.iter_mut()) {
+            for i in 0..param.value.len() {
+                let g = param.grad[i];
+                v[i] = self.momentum * v[i] + g;
+                let update = if self.nesterov {g + self.momentum * v[i]} else {v[i]};
+                param.value[i] -= self.learn * update;
+            }
+        }
+    }
+    fn learning_rate(&self) -> f64 {
+        self.learn
+    }
+    fn set_learning_rate(&mut self, learn : f64) {
+        self.learn = learn;
+    }
+}
+
+pub struct Adagrad {
+    learn : f64,
+    eps : f64,
+    sum_sq : Vec<DVector<f64>>,
+}
+
+impl Adagrad {
+    pub fn new(learn : f64) -> Self {
+        Adagrad {
+            learn,
+            eps : 1e-10,
+            sum_sq : Vec::new(),
+        }
+    }
+}
+
+impl Optimizer for Adagrad {
+    fn step(&mut self, params : &mut [Parameter]) {
+        init_state(&mut self.sum_sq, params);
+        for (param, s) in params.iter_mut().zip(self.sum_sq.iter_mut()) {
+            for i in 0..param.value.len() {
+                let g = param.grad[i];
+                s[i] += g * g;
+                param.value[i] -= self.learn * g / (s[i].sqrt() + self.eps);
+            }
+        }
+    }
+    fn learning_rate(&self) -> f64 {
+        self.learn
+    }
+    fn set_learning_rate(&mut self, learn : f64) {
+        self.learn = learn;
+    }
+}
+
+pub struct RmsProp {
+    learn : f64,
+    rho : f64,
+    eps : f64,
+    mean_sq : Vec<DVector<f64>>,
+}
+
+impl RmsProp {
+    pub fn new(learn : f64) -> Self {
+        Self::with_decay(learn, 0.9)
+    }
+    pub fn with_decay(learn : f64, rho : f64) -> Self {
+        RmsProp {
+            learn,
+            rho,
+            eps : 1e-8,
+            mean_sq : Vec::new(),
+        }
+    }
+}
+
+impl Optimizer for RmsProp {
+    fn step(&mut self, params : &mut [Parameter]) {
+        init_state(&mut self.mean_sq, params);
+        for (param, s) in params.iter_mut().zip(self.mean_sq.iter_mut()) {
+            for i in 0..param.value.len() {
+                let g = param.grad[i];
+                s[i] = self.rho * s[i] + (1.0 - self.rho) * g * g;
+                param.value[i] -= self.learn * g / (s[i].sqrt() + self.eps);
+            }
+        }
+    }
+    fn learning_rate(&self) -> f64 {
+        self.learn
+    }
+    fn set_learning_rate(&mut self, learn : f64) {
+        self.learn = learn;
+    }
+}
+
+pub struct Adam {
+    learn : f64,
+    beta1 : f64,
+    beta2 : f64,
+    eps : f64,
+    t : i32,
+    m : Vec<DVector<f64>>,
+    v : Vec<DVector<f64>>,
+}
+
+impl Adam {
+    pub fn new(learn : f64) -> Self {
+        Self::with_betas(learn, 0.9, 0.999)
+    }
+    pub fn with_betas(learn : f64, beta1 : f64, beta2 : f64) -> Self {
+        Adam {
+            learn,
+            beta1,
+            beta2,
+            eps : 1e-8,
+            t : 0,
+            m : Vec::new(),
+            v : Vec::new(),
+        }
+    }
+}
+
+impl Optimizer for Adam {
+    fn step(&mut self, params : &mut [Parameter]) {
+        init_state(&mut self.m, params);
+        init_state(&mut self.v, params);
+        self.t += 1;
+        let correct1 = 1.0 - self.beta1.powi(self.t);
+        let correct2 = 1.0 - self.beta2.powi(self.t);
+        for ((param, m), v) in params.iter_mut().zip(self.m.iter_mut()).zip(self.v.iter_mut()) {
+            for i in 0..param.value.len() {
+                let g = param.grad[i];
+                m[i] = self.beta1 * m[i] + (1.0 - self.beta1) * g;
+                v[i] = self.beta2 * v[i] + (1.0 - self.beta2) * g * g;
+                let m_hat = m[i] / correct1;
+                let v_hat = v[i] / correct2;
+                param.value[i] -= self.learn * m_hat / (v_hat.sqrt() + self.eps);
+            }
+        }
+    }
+    fn learning_rate(&self) -> f64 {
+        self.learn
+    }
+    fn set_learning_rate(&mut self, learn : f64) {
+        self.learn = learn;
+    }
+}
+
+//Adam with weight decay applied directly to the weights instead of through the gradient
+pub struct AdamW {
+    adam : Adam,
+    weight_decay : f64,
+}
+
+impl AdamW {
+    pub fn new(learn : f64, weight_decay : f64) -> Self {
+        AdamW {
+            adam : Adam::new(learn),
+            weight_decay,
+        }
+    }
+}
+
+impl Optimizer for AdamW {
+    fn step(&mut self, params : &mut [Parameter]) {
+        let decay = 1.0 - self.adam.learn * self.weight_decay;
+        for param in params.iter_mut() {
+            param.value.iter_mut().for_each(|w| *w *= decay);
+        }
+        self.adam.step(params);
+    }
+    fn learning_rate(&self) -> f64 {
+        self.adam.learn
+    }
+    fn set_learning_rate(&mut self, learn : f64) {
+        self.adam.learn = learn;
+    }
+}
+
+//sign-based update from "Symbolic Discovery of Optimization Algorithms" (Chen et al.)
+pub struct Lion {
+    learn : f64,
+    beta1 : f64,
+    beta2 : f64,
+    weight_decay : f64,
+    m : Vec<DVector<f64>>,
+}
+
+impl Lion {
+    pub fn new(learn : f64) -> Self {
+        Self::with_weight_decay(learn, 0.0)
+    }
+    pub fn with_weight_decay(learn : f64, weight_decay : f64) -> Self {
+        Lion {
+            learn,
+            beta1 : 0.9,
+            beta2 : 0.99,
+            weight_decay,
+            m : Vec::new(),
+        }
+    }
+}
+
+impl Optimizer for Lion {
+    fn step(&mut self, params : &mut [Parameter]) {
+        init_state(&mut self.m, params);
+        for (param, m) in params.iter_mut().zip(self.m.iter_mut()) {
+            for i in 0..param.value.len() {
+                let g = param.grad[i];
+                let c = self.beta1 * m[i] + (1.0 - self.beta1) * g;
+                let sign = if c > 0.0 {1.0} else if c < 0.0 {-1.0} else {0.0};
+                param.value[i] -= self.learn * (sign + self.weight_decay * param.value[i]);
+                m[i] = self.beta2 * m[i] + (1.0 - self.beta2) * g;
+            }
+        }
+    }
+    fn learning_rate(&self) -> f64 {
+        self.learn
+    }
+    fn set_learning_rate(&mut self, learn : f64) {
+        self.learn = learn;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //runs one step per gradient on a single two-entry parameter starting at [1, -2] and returns
+    //the value after each step
+    fn steps(optimizer : &mut dyn Optimizer, grads : &[[f64; 2]]) -> Vec<[f64; 2]> {
+        let mut value = [1.0, -2.0];
+        grads.iter().map(|grad| {
+            optimizer.step(&mut [Parameter { value : &mut value, grad }]);
+            value
+        }).collect()
+    }
+
+    //loose enough for the eps in the denominators
+    fn assert_values(name : &str, values : &[[f64; 2]], expected : &[[f64; 2]]) {
+        for (step, (value, expected)) in values.iter().zip(expected.iter()).enumerate() {
+            for i in 0..2 {
+                assert!((value[i] - expected[i]).abs() < 1e-7, "{} step {}: {:?}, expected {:?}", name, step + 1, value, expected);
+            }
+        }
+    }
+
+    const GRADS : [[f64; 2]; 2] = [[0.5, -1.0], [0.5, 1.0]];
+
+    #[test]
+    fn sgd_with_and_without_momentum() {
+        assert_values("sgd", &steps(&mut Sgd::new(0.1), &GRADS), &[[0.95, -1.9], [0.9, -2.0]]);
+        //v = 0.9 v + g, then w -= 0.1 v
+        assert_values("momentum", &steps(&mut Sgd::with_momentum(0.1, 0.9), &GRADS), &[[0.95, -1.9], [0.855, -1.91]]);
+        //w -= 0.1 (g + 0.9 v) with the updated v
+        assert_values("nesterov", &steps(&mut Sgd::nesterov(0.1, 0.9), &GRADS), &[[0.905, -1.81], [0.7695, -1.919]]);
+    }
+
+    #[test]
+    fn adagrad_and_rmsprop_scale_by_squared_gradients() {
+        //the sums of squares are [0.25, 1] then [0.5, 2]
+        assert_values("adagrad", &steps(&mut Adagrad::new(0.1), &GRADS), &[[0.9, -1.9], [0.9 - 0.05 / 0.5f64.sqrt(), -1.9 - 0.1 / 2f64.sqrt()]]);
+        //the running means are [0.025, 0.1] then [0.0475, 0.19]
+        let first = [1.0 - 0.005 / 0.025f64.sqrt(), -2.0 + 0.01 / 0.1f64.sqrt()];
+        assert_values("rmsprop", &steps(&mut RmsProp::new(0.01), &GRADS),
+                      &[first, [first[0] - 0.005 / 0.0475f64.sqrt(), first[1] - 0.01 / 0.19f64.sqrt()]]);
+    }
+
+    #[test]
+    fn adam_corrects_the_bias_of_its_moments() {
+        //bias-corrected, the first step is the learning rate times the gradient's sign
+        assert_values("adam", &steps(&mut Adam::new(0.1), &GRADS), &[[0.9, -1.9], [0.8, -1.9 - 0.1 / 19.0]]);
+    }
+
+    #[test]
+    fn adamw_decays_the_weights_apart_from_the_gradient() {
+        //w *= 1 - 0.1 * 0.5 before the Adam step, which a zero gradient leaves at zero
+        assert_values("adamw", &steps(&mut AdamW::new(0.1, 0.5), &[[0.0, 0.0]]), &[[0.95, -1.9]]);
+        //and the decay stays the same however large the gradient
+        assert_values("adamw", &steps(&mut AdamW::new(0.1, 0.5), &[[0.5, -1.0]]), &[[0.85, -1.8]]);
+        assert_values("adamw", &steps(&mut AdamW::new(0.1, 0.5), &[[500.0, -1000.0]]), &[[0.85, -1.8]]);
+    }
+
+    #[test]
+    fn lion_steps_by_the_sign_of_the_interpolated_momentum() {
+        //after the first step m = 0.01 g, which outweighs the second gradient's 0.1 share
+        assert_values("lion", &steps(&mut Lion::new(0.1), &[[0.5, -1.0], [-0.01, 0.05]]), &[[0.9, -1.9], [0.8, -1.8]]);
+        assert_values("lion", &steps(&mut Lion::with_weight_decay(0.1, 0.5), &[[0.5, -1.0]]), &[[0.85, -1.8]]);
+    }
+}
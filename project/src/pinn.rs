@@ -0,0 +1,309 @@
+use nalgebra::{DMatrix, DVector};
+use crate::autodiff::{Tape, Var};
+use crate::neuralnetwork::{polarization, NeuralNetwork};
+use crate::optimizer::Optimizer;
+use crate::trainer::Model;
+
+//physics-informed training: the network is fitted so that a residual built from its outputs and
+//their input derivatives vanishes at collocation points, alongside boundary/initial/data terms
+//
+//input derivatives come from NeuralNetwork::input_jets, the routine behind jacobian and hessian,
+//recorded on the same tape as the forward pass; one backward pass then gives the exact parameter
+//gradient of every residual
+
+//residual at a batch of points (one per column) given the network's derivatives there, one column per point
+pub type ResidualFn = Box<dyn Fn(&DMatrix<f64>, &Derivatives) -> Var>;
+
+//network output at a batch of points and its input derivatives up to the term's order, each outputs × points
+pub struct Derivatives {
+    value : Var,
+    first : Option<Var>, //one block of columns per direction of `polarization`, side by side
+    second : Option<Var>,
+    points : usize,
+    dims : usize,
+    pairs : Vec<(usize, usize)>,
+}
+
+impl Derivatives {
+    fn block(&self, var : &Var, direction : usize) -> Var {
+        var.columns(direction * self.points, self.points)
+    }
+    pub fn u(&self) -> Var {
+        self.value.clone()
+    }
+    //du/dx_i
+    pub fn du(&self, i : usize) -> Var {
+        let first = self.first.as_ref().expect("first derivatives need a term of order 1 or more");
+        self.block(first, i)
+    }
+    //d²u/dx_i dx_j
+    pub fn d2u(&self, i : usize, j : usize) -> Var {
+        let second = self.second.as_ref().expect("second derivatives need a term of order 2");
+        if i == j {
+            return self.block(second, i);
+        }
+        let k = self.pairs.iter().position(|&pair| pair == (i.max(j), i.min(j))).expect("input index out of range");
+        self.block(second, self.dims + k).sub(&self.block(second, i)).sub(&self.block(second, j)).scale(0.5)
+    }
+}
+
+//one sample per column; from_columns needs at least one
+fn batch(columns : &[DVector<f64>]) -> DMatrix<f64> {
+    if columns.is_empty() {DMatrix::zeros(0, 0)} else {DMatrix::from_columns(columns)}
+}
+
+enum Residual {
+    Closure(ResidualFn),
+    Targets(DMatrix<f64>), //u(x) - target, for boundary values and measurements
+}
+
+//one weighted mean-squared residual over a set of points
+pub struct Term {
+    name : String,
+    points : DMatrix<f64>, //one per column
+    order : usize, //highest input derivative the residual reads, 0 to 2
+    residual : Residual,
+    weight : f64,
+}
+
+impl Term {
+    pub fn new(name : &str, points : Vec<DVector<f64>>, order : usize, residual : ResidualFn) -> Self {
+        assert!(order <= 2, "only derivatives up to second order are supported");
+        Term { name : name.to_string(), points : batch(&points), order, residual : Residual::Closure(residual), weight : 1.0 }
+    }
+    //u(x) = value(x), e.g. Dirichlet boundaries and initial values
+    pub fn value(name : &str, points : Vec<DVector<f64>>, value : impl Fn(&DVector<f64>) -> DVector<f64>) -> Self {
+        let targets = points.iter().map(value).collect();
+        Self::data(name, points, targets)
+    }
+    //u(inputs[i]) = targets[i]
+    pub fn data(name : &str, inputs : Vec<DVector<f64>>, targets : Vec<DVector<f64>>) -> Self {
+        assert_eq!(inputs.len(), targets.len(), "{} inputs but {} targets", inputs.len(), targets.len());
+        let targets = Residual::Targets(batch(&targets));
+        Term { name : name.to_string(), points : batch(&inputs), order : 0, residual : targets, weight : 1.0 }
+    }
+    pub fn with_weight(mut self, weight : f64) -> Self {
+        self.weight = weight;
+        self
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    //the network and its input derivatives at every point, recorded on `tape`
+    fn derivatives(&self, network : &mut NeuralNetwork, tape : &Tape, leaves : &[Var]) -> Derivatives {
+        let (dims, points) = self.points.shape();
+        let (directions, pairs) = polarization(dims);
+        let (value, first, second) = match self.order {
+            0 => (network.forward_leaves(&tape.var(self.points.clone()), leaves), None, None),
+            1 => {
+                let (value, first, _) = network.input_jets(tape, leaves, &self.points, &directions.columns(0, dims).into_owned());
+                (value, Some(first), None)
+            }
+            _ => {
+                let (value, first, second) = network.input_jets(tape, leaves, &self.points, &directions);
+                (value, Some(first), Some(second))
+            }
+        };
+        Derivatives { value, first, second, points, dims, pairs }
+    }
+}
+
+//weighted loss of every term after the last step
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PinnLoss {
+    pub total : f64,
+    pub terms : Vec<(String, f64)>, //unweighted mean squared residual per term
+}
+
+pub struct Pinn {
+    network : NeuralNetwork,
+    terms : Vec<Term>,
+}
+
+impl Pinn {
+    //trains with the network's own optimizer; its loss is not used. Every layer must implement forward_tape
+    pub fn new(network : NeuralNetwork) -> Self {
+        Pinn { network, terms : Vec::new() }
+    }
+    //the PDE residual over the interior collocation points
+    pub fn with_residual(self, points : Vec<DVector<f64>>, order : usize, residual : ResidualFn) -> Self {
+        self.with_term(Term::new("residual", points, order, residual))
+    }
+    //boundary and initial conditions, measurements, or extra residuals
+    pub fn with_term(mut self, term : Term) -> Self {
+        self.terms.push(term);
+        self
+    }
+    pub fn model(&mut self) -> &mut NeuralNetwork {
+        &mut self.network
+    }
+    pub fn into_model(self) -> NeuralNetwork {
+        self.network
+    }
+    pub fn optimizer(&mut self) -> &mut dyn Optimizer {
+        self.network.optimizer()
+    }
+
+    //records every term on one tape; returns the weighted total, None without any points, and the
+    //parameter leaves
+    fn record(&mut self) -> (PinnLoss, Option<Var>, Vec<Var>) {
+        let tape = Tape::new();
+        let leaves = self.network.leaves(&tape);
+        let mut loss = PinnLoss::default();
+        let mut total : Option<Var> = None;
+        for term in self.terms.iter() {
+            let points = term.points.ncols();
+            if points == 0 {
+                loss.terms.push((term.name.clone(), 0.0));
+                continue;
+            }
+            let derivatives = term.derivatives(&mut self.network, &tape, &leaves);
+            let residual = match &term.residual {
+                Residual::Closure(f) => f(&term.points, &derivatives),
+                Residual::Targets(targets) => derivatives.u().add_constant(&-targets),
+            };
+            assert_eq!(residual.cols(), points, "the {} residual needs one column per point", term.name);
+            let mean = residual.square().sum().scale(1.0 / points as f64);
+            let value = mean.value()[0];
+            loss.total += term.weight * value;
+            loss.terms.push((term.name.clone(), value));
+            let weighted = mean.scale(term.weight);
+            total = Some(match total {
+                Some(total) => total.add(&weighted),
+                None => weighted,
+            });
+        }
+        (loss, total, leaves)
+    }
+
+    //current loss without updating the model
+    pub fn loss(&mut self) -> PinnLoss {
+        self.record().0
+    }
+    //one full-batch optimizer step on the weighted sum of all terms; returns the loss before the step
+    pub fn train_step(&mut self) -> PinnLoss {
+        let (loss, total, leaves) = self.record();
+        if let Some(total) = total {
+            let grads = total.backward();
+            let grads = leaves.iter().map(|leaf| grads.wrt(leaf)).collect::<Vec<DMatrix<f64>>>();
+            self.network.step_with(&grads);
+        }
+        loss
+    }
+    pub fn fit(&mut self, epochs : usize) -> Vec<f64> {
+        (0..epochs).map(|_| self.train_step().total).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use rand::Rng;
+    use super::*;
+    use crate::activation::{Activation, Gelu, Identity, Tanh};
+    use crate::gradcheck::{assert_close, central_difference};
+    use crate::init::Initializer;
+    use crate::neuralnetwork::{DenseLayer, Layer, MeanSquaredError};
+    use crate::optimizer::Adam;
+    use crate::rng;
+
+    fn network(widths : &[usize], hidden : fn() -> Box<dyn Activation>, seed : u64) -> NeuralNetwork {
+        let mut r_vals = rng::seeded(seed);
+        let layers = widths.windows(2).enumerate().map(|(i, pair)| {
+            let activation = if i + 2 == widths.len() {Box::new(Identity)} else {hidden()};
+            Box::new(DenseLayer::with_initializer_rng(pair[0], pair[1], activation, Initializer::XavierNormal, &mut r_vals)) as Box<dyn Layer>
+        }).collect();
+        NeuralNetwork::with_optimizer(layers, Box::new(MeanSquaredError), Box::new(Adam::new(0.01)))
+    }
+
+    fn point(values : &[f64]) -> DVector<f64> {
+        DVector::from_column_slice(values)
+    }
+
+    //relative L2 error of the trained network against `exact` on `grid`
+    fn relative_error(pinn : &mut Pinn, grid : &[DVector<f64>], exact : impl Fn(&DVector<f64>) -> f64) -> f64 {
+        let (mut error, mut norm) = (0.0, 0.0);
+        for x in grid {
+            error += (pinn.model().forward(x)[0] - exact(x)).powi(2);
+            norm += exact(x).powi(2);
+        }
+        (error / norm).sqrt()
+    }
+
+    //adds delta to entry i of the parameters taken end to end
+    fn nudge(pinn : &mut Pinn, mut i : usize, delta : f64) {
+        for param in pinn.model().parameters() {
+            if i < param.value.len() {
+                param.value[i] += delta;
+                return;
+            }
+            i -= param.value.len();
+        }
+    }
+
+    #[test]
+    fn parameter_gradient_matches_central_differences() {
+        let mut r_vals = rng::seeded(4);
+        let points = (0..5).map(|_| point(&[r_vals.gen_range(-1.0..1.0), r_vals.gen_range(-1.0..1.0)])).collect::<Vec<_>>();
+        //nonlinear in u and mixing every kind of derivative
+        let mut pinn = Pinn::new(network(&[2, 5, 4, 2], || Box::new(Gelu), 4))
+            .with_residual(points.clone(), 2, Box::new(|x, d| {
+                d.d2u(0, 1).add(&d.d2u(1, 1).scale(0.5)).add(&d.u().mul(&d.du(0))).add_constant(&DMatrix::from_fn(2, x.ncols(), |r, c| x[(r, c)].sin()))
+            }))
+            .with_term(Term::new("slope", points.clone(), 1, Box::new(|_, d| d.du(1).square())).with_weight(0.5))
+            .with_term(Term::value("value", points, |x| point(&[x[0], x[1] * x[1]])).with_weight(2.0));
+        let (_, total, leaves) = pinn.record();
+        let grads = total.unwrap().backward();
+        let exact = leaves.iter().flat_map(|leaf| grads.wrt(leaf).as_slice().to_vec()).collect::<Vec<f64>>();
+        let numeric = central_difference(exact.len(), |i, delta| {
+            nudge(&mut pinn, i, delta);
+            let loss = pinn.loss().total;
+            nudge(&mut pinn, i, -delta);
+            loss
+        });
+        assert_close("pinn parameter gradient", &exact, &numeric, 1e-6);
+    }
+
+    //the heat example, smaller and shorter
+    #[test]
+    fn heat_equation_converges() {
+        const ALPHA : f64 = 0.1;
+        const EPOCHS : usize = 300;
+        let mut r_vals = rng::seeded(7);
+        let interior = (0..80).map(|_| point(&[r_vals.gen_range(0.0..1.0), r_vals.gen_range(0.0..1.0)])).collect::<Vec<_>>();
+        let initial = (0..=16).map(|i| point(&[i as f64 / 16.0, 0.0])).collect::<Vec<_>>();
+        let boundary = (0..=8).flat_map(|i| [point(&[0.0, i as f64 / 8.0]), point(&[1.0, i as f64 / 8.0])]).collect::<Vec<_>>();
+        let mut pinn = Pinn::new(network(&[2, 12, 12, 1], || Box::new(Tanh), 7))
+            .with_residual(interior, 2, Box::new(|_, d| d.du(1).sub(&d.d2u(0, 0).scale(ALPHA))))
+            .with_term(Term::value("initial", initial, |x| DVector::from_element(1, (PI * x[0]).sin())).with_weight(10.0))
+            .with_term(Term::value("boundary", boundary, |_| DVector::zeros(1)).with_weight(10.0));
+        let history = pinn.fit(EPOCHS);
+        assert!(history[EPOCHS - 1] < history[0] * 1e-2, "loss went from {} to {}", history[0], history[EPOCHS - 1]);
+        let grid = (0..=10).flat_map(|i| (0..=10).map(move |j| point(&[i as f64 / 10.0, j as f64 / 10.0]))).collect::<Vec<_>>();
+        let error = relative_error(&mut pinn, &grid, |x| (-ALPHA * PI * PI * x[1]).exp() * (PI * x[0]).sin());
+        assert!(error < 0.1, "relative L2 error {}", error);
+    }
+
+    //the oscillator example, smaller and shorter
+    #[test]
+    fn oscillator_converges() {
+        const OMEGA : f64 = 2.0;
+        const ZETA : f64 = 0.1;
+        const EPOCHS : usize = 500;
+        let collocation = (0..=40).map(|i| point(&[1.5 * i as f64 / 40.0])).collect::<Vec<_>>();
+        let start = vec![DVector::zeros(1)];
+        let mut pinn = Pinn::new(network(&[1, 16, 16, 1], || Box::new(Tanh), 3))
+            .with_residual(collocation, 2, Box::new(|_, d| d.d2u(0, 0).add(&d.du(0).scale(2.0 * ZETA * OMEGA)).add(&d.u().scale(OMEGA * OMEGA))))
+            .with_term(Term::value("position", start.clone(), |_| DVector::from_element(1, 1.0)).with_weight(10.0))
+            .with_term(Term::new("velocity", start, 1, Box::new(|_, d| d.du(0))).with_weight(10.0));
+        pinn.fit(EPOCHS);
+        let damped = OMEGA * (1.0 - ZETA * ZETA).sqrt();
+        let grid = (0..=30).map(|i| point(&[1.5 * i as f64 / 30.0])).collect::<Vec<_>>();
+        let error = relative_error(&mut pinn, &grid, |x| {
+            let t = x[0];
+            (-ZETA * OMEGA * t).exp() * ((damped * t).cos() + ZETA * OMEGA / damped * (damped * t).sin())
+        });
+        assert!(error < 0.05, "relative L2 error {}", error);
+    }
+}
@@ -0,0 +1,309 @@
+use nalgebra::{DMatrix, DVector, DVectorSlice};
+use crate::autodiff::Var;
+use crate::convnn::{flatten, unflatten, Tensor3};
+use crate::neuralnetwork::Layer;
+use crate::serialization::LayerSpec;
+use crate::shape::{Shape, ShapeError};
+
+fn pooled_size(input : Shape, kernel : usize, stride : usize, padding : usize) -> Result<(usize, usize), ShapeError> {
+    if input.rows+2*padding < kernel || input.cols+2*padding < kernel {
+        return Err(ShapeError::new(format!("{}x{} pooling window does not fit {}x{} images with padding {}", kernel, kernel, input.rows, input.cols, padding)));
+    }
+    Ok(((input.rows+2*padding-kernel)/stride + 1, (input.cols+2*padding-kernel)/stride + 1))
+}
+
+fn expect_built(input : Shape) {
+    assert!(!input.is_empty(), "pooling layers need their input shape, use them inside a CNN or call build first");
+}
+
+//max over each window; padded cells never win
+pub struct MaxPool2d {
+    kernel : usize,
+    stride : usize,
+    padding : usize,
+    input : Shape,
+    argmax : Vec<Vec<Option<usize>>>, //per sample, per output cell: flat index of the winning input
+}
+
+impl MaxPool2d {
+    pub fn new(kernel : usize, stride : usize, padding : usize) -> Self {
+        MaxPool2d {
+            kernel,
+            stride : stride.max(1),
+            padding,
+            input : Shape::default(),
+            argmax : Vec::new(),
+        }
+    }
+    //flat index of the largest in-image input of every window, None when a window is all padding
+    fn winners(&self, sample : &DVectorSlice<f64>) -> Vec<Option<usize>> {
+        let Shape { channels, rows, cols } = self.input;
+        let (o_rows, o_cols) = pooled_size(self.input, self.kernel, self.stride, self.padding).unwrap();
+        let mut winners = Vec::with_capacity(channels * o_rows * o_cols);
+        for c in 0..channels {
+            for i in 0..o_rows {
+                for j in 0..o_cols {
+                    let mut best : Option<(usize, f64)> = None;
+                    for a in 0..self.kernel {
+                        for b in 0..self.kernel {
+                            //position in the unpadded image, skipping the padding border
+                            let (r, q) = ((i * self.stride + a).wrapping_sub(self.padding), (j * self.stride + b).wrapping_sub(self.padding));
+                            if r < rows && q < cols {
+                                let index = (c * rows + r) * cols + q;
+                                if best.is_none_or(|(_, v)| sample[index] > v) {
+                                    best = Some((index, sample[index]));
+                                }
+                            }
+                        }
+                    }
+                    winners.push(best.map(|(index, _)| index));
+                }
+            }
+        }
+        winners
+    }
+}
+
+impl Layer for MaxPool2d {
+    fn forward(&mut self, input : &DMatrix<f64>) -> DMatrix<f64> {
+        expect_built(self.input);
+        self.argmax = input.column_iter().map(|sample| self.winners(&sample)).collect();
+        let columns = input.column_iter().zip(self.argmax.iter()).map(|(sample, winners)| {
+            DVector::from_iterator(winners.len(), winners.iter().map(|winner| winner.map_or(0.0, |index| sample[index])))
+        }).collect::<Vec<DVector<f64>>>();
+        DMatrix::from_columns(&columns)
+    }
+    fn backward(&mut self, error : &DMatrix<f64>) -> DMatrix<f64> {
+        let mut gradient = DMatrix::zeros(self.input.len(), error.ncols());
+        for (n, winners) in self.argmax.iter().enumerate() {
+            for (cell, winner) in winners.iter().enumerate() {
+                if let Some(index) = winner {
+                    gradient[(*index, n)] += error[(cell, n)];
+                }
+            }
+        }
+        gradient
+    }
+    fn build(&mut self, input : Shape) -> Result<Shape, ShapeError> {
+        let (o_rows, o_cols) = pooled_size(input, self.kernel, self.stride, self.padding)?;
+        self.input = input;
+        Ok(Shape::new(input.channels, o_rows, o_cols))
+    }
+    fn spec(&self) -> Option<LayerSpec> {
+        Some(LayerSpec::MaxPool { kernel : self.kernel, stride : self.stride, padding : self.padding })
+    }
+    //the winners are picked on the values, the gradient then flows through a gather like backward
+    fn forward_tape(&self, input : &Var, _params : &[Var]) -> Option<Var> {
+        expect_built(self.input);
+        let value = input.value();
+        let len = self.input.len();
+        let mut index = Vec::new();
+        for (n, sample) in value.column_iter().enumerate() {
+            index.extend(self.winners(&sample).into_iter().map(|winner| winner.map(|i| n * len + i)));
+        }
+        let cells = index.len() / value.ncols().max(1);
+        Some(input.gather(cells, value.ncols(), index))
+    }
+}
+
+//mean over each window; padded cells count as zeros so every window divides by k*k
+pub struct AvgPool2d {
+    kernel : usize,
+    stride : usize,
+    padding : usize,
+    input : Shape,
+}
+
+impl AvgPool2d {
+    pub fn new(kernel : usize, stride : usize, padding : usize) -> Self {
+        AvgPool2d {
+            kernel,
+            stride : stride.max(1),
+            padding,
+            input : Shape::default(),
+        }
+    }
+    //calls visit(channel, input_row, input_col, output_index) for every in-image cell of every window
+    fn each_window<F : FnMut(usize, usize, usize, usize)>(&self, mut visit : F) {
+        let Shape { channels, rows, cols } = self.input;
+        let (o_rows, o_cols) = pooled_size(self.input, self.kernel, self.stride, self.padding).unwrap();
+        for c in 0..channels {
+            for i in 0..o_rows {
+                for j in 0..o_cols {
+                    for a in 0..self.kernel {
+                        for b in 0..self.kernel {
+                            let (r, q) = ((i * self.stride + a).wrapping_sub(self.padding), (j * self.stride + b).wrapping_sub(self.padding));
+                            if r < rows && q < cols {
+                                visit(c, r, q, (c * o_rows + i) * o_cols + j);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Layer for AvgPool2d {
+    fn forward(&mut self, input : &DMatrix<f64>) -> DMatrix<f64> {
+        expect_built(self.input);
+        let (o_rows, o_cols) = pooled_size(self.input, self.kernel, self.stride, self.padding).unwrap();
+        let (rows, cols) = (self.input.rows, self.input.cols);
+        let scale = 1.0 / (self.kernel * self.kernel) as f64;
+        let mut output = DMatrix::zeros(self.input.channels * o_rows * o_cols, input.ncols());
+        self.each_window(|c, r, q, cell| {
+            for n in 0..input.ncols() {
+                output[(cell, n)] += input[((c * rows + r) * cols + q, n)] * scale;
+            }
+        });
+        output
+    }
+    fn backward(&mut self, error : &DMatrix<f64>) -> DMatrix<f64> {
+        let (rows, cols) = (self.input.rows, self.input.cols);
+        let scale = 1.0 / (self.kernel * self.kernel) as f64;
+        let mut gradient = DMatrix::zeros(self.input.len(), error.ncols());
+        self.each_window(|c, r, q, cell| {
+            for n in 0..error.ncols() {
+                gradient[((c * rows + r) * cols + q, n)] += error[(cell, n)] * scale;
+            }
+        });
+        gradient
+    }
+    fn build(&mut self, input : Shape) -> Result<Shape, ShapeError> {
+        let (o_rows, o_cols) = pooled_size(input, self.kernel, self.stride, self.padding)?;
+        self.input = input;
+        Ok(Shape::new(input.channels, o_rows, o_cols))
+    }
+    fn spec(&self) -> Option<LayerSpec> {
+        Some(LayerSpec::AvgPool { kernel : self.kernel, stride : self.stride, padding : self.padding })
+    }
+    //gathers each window into a column of k*k entries (padding as None) and averages it
+    fn forward_tape(&self, input : &Var, _params : &[Var]) -> Option<Var> {
+        expect_built(self.input);
+        let (o_rows, o_cols) = pooled_size(self.input, self.kernel, self.stride, self.padding).unwrap();
+        let (rows, cols) = (self.input.rows, self.input.cols);
+        let (kk, cells, len, n) = (self.kernel * self.kernel, self.input.channels * o_rows * o_cols, self.input.len(), input.cols());
+        let mut window = vec![Vec::with_capacity(kk); cells];
+        self.each_window(|c, r, q, cell| window[cell].push((c * rows + r) * cols + q));
+        let mut index = Vec::with_capacity(kk * cells * n);
+        for sample in 0..n {
+            for members in window.iter() {
+                index.extend((0..kk).map(|k| members.get(k).map(|i| sample * len + i)));
+            }
+        }
+        Some(input.gather(kk, cells * n, index).column_sums().scale(1.0 / kk as f64).reshape(cells, n))
+    }
+}
+
+//averages each channel down to a single value: C×H×W -> C×1×1
+#[derive(Default)]
+pub struct GlobalAveragePool {
+    input : Shape,
+}
+
+impl GlobalAveragePool {
+    pub fn new() -> Self {
+        GlobalAveragePool { input : Shape::default() }
+    }
+}
+
+impl Layer for GlobalAveragePool {
+    fn forward(&mut self, input : &DMatrix<f64>) -> DMatrix<f64> {
+        expect_built(self.input);
+        let Shape { channels, rows, cols } = self.input;
+        let columns = input.column_iter().map(|sample| {
+            let values = sample.iter().cloned().collect::<Vec<f64>>();
+            let tensor = unflatten(&values, channels, rows, cols);
+            DVector::from_iterator(channels, tensor.iter().map(|channel| channel.mean()))
+        }).collect::<Vec<DVector<f64>>>();
+        DMatrix::from_columns(&columns)
+    }
+    fn backward(&mut self, error : &DMatrix<f64>) -> DMatrix<f64> {
+        let Shape { channels, rows, cols } = self.input;
+        let scale = 1.0 / (rows * cols) as f64;
+        let columns = error.column_iter().map(|sample| {
+            let tensor = (0..channels).map(|c| DMatrix::from_element(rows, cols, sample[c] * scale)).collect::<Tensor3>();
+            flatten(&tensor)
+        }).collect::<Vec<DVector<f64>>>();
+        DMatrix::from_columns(&columns)
+    }
+    fn build(&mut self, input : Shape) -> Result<Shape, ShapeError> {
+        self.input = input;
+        Ok(Shape::new(input.channels, 1, 1))
+    }
+    fn spec(&self) -> Option<LayerSpec> {
+        Some(LayerSpec::GlobalAveragePool)
+    }
+    fn forward_tape(&self, input : &Var, _params : &[Var]) -> Option<Var> {
+        expect_built(self.input);
+        let (channels, area, n) = (self.input.channels, self.input.rows * self.input.cols, input.cols());
+        //column-major, so each channel of each sample becomes one column of H*W values
+        Some(input.reshape(area, channels * n).column_sums().scale(1.0 / area as f64).reshape(channels, n))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use crate::gradcheck::{assert_close, central_difference};
+    use crate::neuralnetwork::{Loss, MeanSquaredError};
+    use crate::rng;
+
+    //(channels, kernel, stride, padding, rows, cols); the second leaves a remainder the stride never
+    //reaches and the third starts with a window that is all padding
+    const CONFIGS : [(usize, usize, usize, usize, usize, usize); 4] = [
+        (1, 2, 2, 0, 4, 4),
+        (2, 3, 2, 1, 7, 6),
+        (3, 2, 3, 2, 6, 8),
+        (2, 3, 1, 1, 5, 4),
+    ];
+
+    fn batch(rows : usize, cols : usize, seed : u64) -> DMatrix<f64> {
+        let mut r_vals = rng::seeded(seed);
+        DMatrix::from_fn(rows, cols, |_, _| r_vals.gen_range(-1.0..1.0))
+    }
+
+    fn check(name : &str, layer : &mut dyn Layer, input : Shape, seed : u64) {
+        let mut input = batch(input.len(), 3, seed);
+        let output = layer.forward(&input);
+        let target = batch(output.nrows(), 3, seed + 100);
+        let loss = MeanSquaredError;
+        let grad_input = layer.backward(&loss.gradient_batch(&output, &target));
+        let numeric = central_difference(input.len(), |i, delta| {
+            input[i] += delta;
+            let value = loss.compute_batch(&layer.forward(&input), &target);
+            input[i] -= delta;
+            value
+        });
+        assert_close(name, grad_input.as_slice(), &numeric, 1e-7);
+    }
+
+    #[test]
+    fn backward_matches_central_differences() {
+        for (n, &(channels, kernel, stride, padding, rows, cols)) in CONFIGS.iter().enumerate() {
+            let shape = Shape::new(channels, rows, cols);
+            let mut max = MaxPool2d::new(kernel, stride, padding);
+            max.build(shape).unwrap();
+            check(&format!("max pool {:?}", CONFIGS[n]), &mut max, shape, n as u64);
+            let mut avg = AvgPool2d::new(kernel, stride, padding);
+            avg.build(shape).unwrap();
+            check(&format!("average pool {:?}", CONFIGS[n]), &mut avg, shape, n as u64);
+            let mut global = GlobalAveragePool::new();
+            global.build(shape).unwrap();
+            check(&format!("global average pool {:?}", CONFIGS[n]), &mut global, shape, n as u64);
+        }
+    }
+
+    #[test]
+    fn max_pool_routes_the_error_to_each_winner() {
+        let mut layer = MaxPool2d::new(2, 3, 2);
+        layer.build(Shape::new(1, 3, 3)).unwrap();
+        //padded 7x7, windows start at -2 and 1: the first row and column of windows are all padding
+        let input = DMatrix::from_column_slice(9, 1, &[1.0, 5.0, 2.0, 3.0, -1.0, 4.0, 0.0, 7.0, 6.0]);
+        let output = layer.forward(&input);
+        assert_eq!(output.as_slice(), &[0.0, 0.0, 0.0, 7.0]);
+        let gradient = layer.backward(&DMatrix::from_column_slice(4, 1, &[1.0, 2.0, 3.0, 10.0]));
+        assert_eq!(gradient.as_slice(), &[0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 10.0, 0.0]);
+    }
+}
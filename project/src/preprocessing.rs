@@ -0,0 +1,199 @@
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use nalgebra::DVector;
+use serde_json::{json, Value};
+use crate::datasets::InMemoryDataset;
+use crate::serialization::{self, field, json_vector, str_field};
+
+//scalers are fitted on the training inputs only and the same statistics are then applied to
+//validation, test and inference data, so save them next to the model
+
+const JSON_FORMAT : &str = "rust-ai-scaler";
+
+fn invalid(message : String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn vector_json(v : &DVector<f64>) -> Value {
+    Value::from(v.iter().cloned().collect::<Vec<f64>>())
+}
+
+pub trait Scaler {
+    fn transform(&self, input : &DVector<f64>) -> DVector<f64>;
+    fn inverse_transform(&self, input : &DVector<f64>) -> DVector<f64>;
+    fn to_json(&self) -> Value;
+    fn transform_all(&self, inputs : &[DVector<f64>]) -> Vec<DVector<f64>> {
+        inputs.iter().map(|input| self.transform(input)).collect()
+    }
+    //scales the inputs, targets are left alone
+    fn transform_dataset(&self, dataset : &InMemoryDataset) -> InMemoryDataset {
+        InMemoryDataset::new(self.transform_all(dataset.inputs()), dataset.targets().to_vec())
+    }
+}
+
+//feature-wise (x - mean) / std; constant features are only centred
+#[derive(Debug, Clone, PartialEq)]
+pub struct StandardScaler {
+    pub mean : DVector<f64>,
+    pub std : DVector<f64>,
+}
+
+impl StandardScaler {
+    pub fn fit(inputs : &[DVector<f64>]) -> Self {
+        let n = inputs.len().max(1) as f64;
+        let features = inputs.first().map_or(0, |input| input.len());
+        let mean = inputs.iter().fold(DVector::zeros(features), |sum, input| sum + input) / n;
+        let var = inputs.iter().fold(DVector::zeros(features), |sum : DVector<f64>, input| sum + (input - &mean).map(|d| d * d)) / n;
+        Self { mean, std : var.map(|v| if v > 0.0 {v.sqrt()} else {1.0}) }
+    }
+}
+
+impl Scaler for StandardScaler {
+    fn transform(&self, input : &DVector<f64>) -> DVector<f64> {
+        (input - &self.mean).component_div(&self.std)
+    }
+    fn inverse_transform(&self, input : &DVector<f64>) -> DVector<f64> {
+        input.component_mul(&self.std) + &self.mean
+    }
+    fn to_json(&self) -> Value {
+        json!({
+            "format" : JSON_FORMAT,
+            "type" : "standard",
+            "mean" : vector_json(&self.mean),
+            "std" : vector_json(&self.std),
+        })
+    }
+}
+
+//feature-wise linear map of the fitted [min, max] onto [low, high]; constant features map to low
+#[derive(Debug, Clone, PartialEq)]
+pub struct MinMaxScaler {
+    pub min : DVector<f64>,
+    pub max : DVector<f64>,
+    pub low : f64,
+    pub high : f64,
+}
+
+impl MinMaxScaler {
+    pub fn fit(inputs : &[DVector<f64>]) -> Self {
+        Self::fit_range(inputs, 0.0, 1.0)
+    }
+    pub fn fit_range(inputs : &[DVector<f64>], low : f64, high : f64) -> Self {
+        let features = inputs.first().map_or(0, |input| input.len());
+        let min = inputs.iter().fold(DVector::from_element(features, f64::INFINITY), |m, input| m.zip_map(input, f64::min));
+        let max = inputs.iter().fold(DVector::from_element(features, f64::NEG_INFINITY), |m, input| m.zip_map(input, f64::max));
+        Self { min, max, low, high }
+    }
+    fn span(&self) -> DVector<f64> {
+        (&self.max - &self.min).map(|s| if s > 0.0 {s} else {1.0})
+    }
+}
+
+impl Scaler for MinMaxScaler {
+    fn transform(&self, input : &DVector<f64>) -> DVector<f64> {
+        ((input - &self.min).component_div(&self.span()) * (self.high - self.low)).add_scalar(self.low)
+    }
+    fn inverse_transform(&self, input : &DVector<f64>) -> DVector<f64> {
+        input.add_scalar(-self.low).component_mul(&self.span()) / (self.high - self.low) + &self.min
+    }
+    fn to_json(&self) -> Value {
+        json!({
+            "format" : JSON_FORMAT,
+            "type" : "min_max",
+            "min" : vector_json(&self.min),
+            "max" : vector_json(&self.max),
+            "low" : self.low,
+            "high" : self.high,
+        })
+    }
+}
+
+fn f64_field(v : &Value, key : &str) -> Result<f64> {
+    field(v, key)?.as_f64().ok_or_else(|| invalid(format!("field '{}' is not a number", key)))
+}
+
+pub fn from_json(v : &Value) -> Result<Box<dyn Scaler>> {
+    if field(v, "format")?.as_str() != Some(JSON_FORMAT) {
+        return Err(invalid("not a scaler file (bad format tag)".to_string()));
+    }
+    match str_field(v, "type")?.as_str() {
+        "standard" => Ok(Box::new(StandardScaler {
+            mean : json_vector(field(v, "mean")?)?,
+            std : json_vector(field(v, "std")?)?,
+        })),
+        "min_max" => Ok(Box::new(MinMaxScaler {
+            min : json_vector(field(v, "min")?)?,
+            max : json_vector(field(v, "max")?)?,
+            low : f64_field(v, "low")?,
+            high : f64_field(v, "high")?,
+        })),
+        other => Err(invalid(format!("unknown scaler type '{}'", other))),
+    }
+}
+
+pub fn save_json<P : AsRef<Path>>(scaler : &dyn Scaler, path : P) -> Result<()> {
+    serialization::write_json(&scaler.to_json(), path)
+}
+
+pub fn load_json<P : AsRef<Path>>(path : P) -> Result<Box<dyn Scaler>> {
+    from_json(&serialization::read_json(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //the second feature is constant
+    fn inputs() -> Vec<DVector<f64>> {
+        [[1.0, 4.0, -2.0], [3.0, 4.0, 0.0], [5.0, 4.0, 8.0]].iter().map(|x| DVector::from_row_slice(x)).collect()
+    }
+
+    fn assert_vec(name : &str, value : &DVector<f64>, expected : &[f64]) {
+        assert!(value.iter().zip(expected).all(|(v, e)| (v - e).abs() < 1e-12), "{}: {:?}, expected {:?}", name, value.as_slice(), expected);
+    }
+
+    //transforming and back gives the inputs, also after a trip through JSON text
+    fn round_trips(scaler : &dyn Scaler) {
+        let text = serde_json::to_string(&scaler.to_json()).unwrap();
+        let loaded = from_json(&serde_json::from_str(&text).unwrap()).unwrap();
+        for x in inputs() {
+            assert_vec("inverse", &scaler.inverse_transform(&scaler.transform(&x)), x.as_slice());
+            assert_eq!(loaded.transform(&x), scaler.transform(&x));
+        }
+    }
+
+    #[test]
+    fn standard_scaler_centres_and_scales() {
+        let scaler = StandardScaler::fit(&inputs());
+        assert_vec("mean", &scaler.mean, &[3.0, 4.0, 2.0]);
+        //population std; the constant feature keeps a std of 1 so it is only centred
+        assert_vec("std", &scaler.std, &[(8.0f64 / 3.0).sqrt(), 1.0, (56.0f64 / 3.0).sqrt()]);
+        let scaled = scaler.transform_all(&inputs());
+        assert_vec("constant feature", &DVector::from_iterator(3, scaled.iter().map(|x| x[1])), &[0.0, 0.0, 0.0]);
+        assert!(scaled.iter().all(|x| x.iter().all(|v| v.is_finite())));
+        round_trips(&scaler);
+    }
+
+    #[test]
+    fn min_max_scaler_maps_onto_the_range() {
+        let scaler = MinMaxScaler::fit_range(&inputs(), -1.0, 1.0);
+        assert_vec("first", &scaler.transform(&inputs()[0]), &[-1.0, -1.0, -1.0]);
+        assert_vec("middle", &scaler.transform(&inputs()[1]), &[0.0, -1.0, -0.6]);
+        assert_vec("last", &scaler.transform(&inputs()[2]), &[1.0, -1.0, 1.0]);
+        //unseen values extrapolate linearly
+        assert_vec("outside", &scaler.transform(&DVector::from_row_slice(&[7.0, 5.0, 18.0])), &[2.0, 1.0, 3.0]);
+        round_trips(&scaler);
+        round_trips(&MinMaxScaler::fit(&inputs()));
+    }
+
+    #[test]
+    fn from_json_rejects_other_files() {
+        let json = StandardScaler::fit(&inputs()).to_json();
+        let mut other = json.clone();
+        other["format"] = Value::from("something-else");
+        assert_eq!(from_json(&other).err().map(|e| e.kind()), Some(ErrorKind::InvalidData));
+        let mut unknown = json;
+        unknown["type"] = Value::from("robust");
+        assert_eq!(from_json(&unknown).err().map(|e| e.kind()), Some(ErrorKind::InvalidData));
+    }
+}
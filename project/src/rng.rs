@@ -0,0 +1,31 @@
+use std::sync::Mutex;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+//process-wide generator behind every constructor that doesn't take an rng explicitly
+//seeded from entropy on first use unless set_seed was called
+static GLOBAL : Mutex<Option<StdRng>> = Mutex::new(None);
+
+//reseeds the global generator so layer init, data shuffles and splits repeat exactly
+pub fn set_seed(seed : u64) {
+    *lock() = Some(StdRng::seed_from_u64(seed));
+}
+
+pub fn seeded(seed : u64) -> StdRng {
+    StdRng::seed_from_u64(seed)
+}
+
+//runs `f` with the global generator; don't call back into this from inside `f`
+pub fn with_global<T>(f : impl FnOnce(&mut StdRng) -> T) -> T {
+    let mut guard = lock();
+    f(guard.get_or_insert_with(StdRng::from_entropy))
+}
+
+//an independent generator split off the global one, for state that outlives a single call
+pub fn fork() -> StdRng {
+    with_global(|rng| StdRng::from_rng(rng).expect("StdRng seeding cannot fail"))
+}
+
+fn lock() -> std::sync::MutexGuard<'static, Option<StdRng>> {
+    GLOBAL.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
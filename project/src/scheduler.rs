@@ -0,0 +1,327 @@
+use std::f64::consts::PI;
+use crate::optimizer::Optimizer;
+
+//learning-rate schedules; a Schedule applies one to an optimizer from inside Trainer::fit,
+//NeuralNetwork::fit or CNN::fit, so the rate no longer has to be passed in by hand
+
+pub trait LrScheduler {
+    //rate for `step` (epochs or batches, from 0) given the optimizer's rate when training started
+    fn learning_rate(&mut self, base : f64, step : usize) -> f64;
+    //end-of-epoch hook with the monitored loss, for schedules that react to progress
+    fn observe(&mut self, _loss : f64) {}
+}
+
+//base * gamma^(step / step_size)
+pub struct StepDecay {
+    pub step_size : usize,
+    pub gamma : f64,
+}
+
+impl StepDecay {
+    pub fn new(step_size : usize, gamma : f64) -> Self {
+        StepDecay { step_size : step_size.max(1), gamma }
+    }
+}
+
+impl LrScheduler for StepDecay {
+    fn learning_rate(&mut self, base : f64, step : usize) -> f64 {
+        base * self.gamma.powi((step / self.step_size) as i32)
+    }
+}
+
+//base * gamma^step
+pub struct ExponentialDecay {
+    pub gamma : f64,
+}
+
+impl ExponentialDecay {
+    pub fn new(gamma : f64) -> Self {
+        ExponentialDecay { gamma }
+    }
+}
+
+impl LrScheduler for ExponentialDecay {
+    fn learning_rate(&mut self, base : f64, step : usize) -> f64 {
+        base * self.gamma.powi(step as i32)
+    }
+}
+
+//SGDR (Loshchilov & Hutter): half a cosine from base down to min_lr over `period` steps, then a
+//restart at base with the period multiplied by `mult`
+pub struct CosineAnnealing {
+    pub period : usize,
+    pub mult : usize,
+    pub min_lr : f64,
+}
+
+impl CosineAnnealing {
+    //restarts at base every `period` steps
+    pub fn new(period : usize, min_lr : f64) -> Self {
+        CosineAnnealing { period : period.max(1), mult : 1, min_lr }
+    }
+    pub fn with_restarts(period : usize, mult : usize, min_lr : f64) -> Self {
+        CosineAnnealing { period : period.max(1), mult : mult.max(1), min_lr }
+    }
+}
+
+impl LrScheduler for CosineAnnealing {
+    fn learning_rate(&mut self, base : f64, step : usize) -> f64 {
+        let (mut start, mut period) = (0, self.period);
+        while step >= start + period {
+            start += period;
+            period *= self.mult;
+        }
+        let progress = (step - start) as f64 / period as f64;
+        self.min_lr + (base - self.min_lr) * (1.0 + (PI * progress).cos()) / 2.0
+    }
+}
+
+//ramps linearly up to base over `steps`, then hands over to `after` (constant base if there is none)
+pub struct LinearWarmup {
+    pub steps : usize,
+    after : Option<Box<dyn LrScheduler>>,
+}
+
+impl LinearWarmup {
+    pub fn new(steps : usize) -> Self {
+        LinearWarmup { steps, after : None }
+    }
+    //`after` counts its steps from the end of the warmup
+    pub fn then(mut self, after : Box<dyn LrScheduler>) -> Self {
+        self.after = Some(after);
+        self
+    }
+}
+
+impl LrScheduler for LinearWarmup {
+    fn learning_rate(&mut self, base : f64, step : usize) -> f64 {
+        if step < self.steps {
+            return base * (step + 1) as f64 / self.steps as f64;
+        }
+        match self.after.as_mut() {
+            Some(after) => after.learning_rate(base, step - self.steps),
+            None => base,
+        }
+    }
+    fn observe(&mut self, loss : f64) {
+        if let Some(after) = self.after.as_mut() {
+            after.observe(loss);
+        }
+    }
+}
+
+//Smith's 1cycle: cosine up from max_lr / div to max_lr over the first `warmup` fraction of
+//`total` steps, then cosine down to max_lr / final_div; ignores the optimizer's own rate
+pub struct OneCycle {
+    pub max_lr : f64,
+    pub total : usize,
+    pub warmup : f64,
+    pub div : f64,
+    pub final_div : f64,
+}
+
+impl OneCycle {
+    pub fn new(max_lr : f64, total : usize) -> Self {
+        OneCycle { max_lr, total : total.max(1), warmup : 0.3, div : 25.0, final_div : 1e4 }
+    }
+    pub fn with_warmup(mut self, warmup : f64) -> Self {
+        self.warmup = warmup.clamp(0.0, 1.0);
+        self
+    }
+    pub fn with_divisors(mut self, div : f64, final_div : f64) -> Self {
+        self.div = div;
+        self.final_div = final_div;
+        self
+    }
+}
+
+//cosine from `from` at t = 0 to `to` at t = 1
+fn cosine(from : f64, to : f64, t : f64) -> f64 {
+    to + (from - to) * (1.0 + (PI * t.clamp(0.0, 1.0)).cos()) / 2.0
+}
+
+impl LrScheduler for OneCycle {
+    fn learning_rate(&mut self, _base : f64, step : usize) -> f64 {
+        let up = (self.warmup * self.total as f64).round() as usize;
+        let start = self.max_lr / self.div;
+        if step < up {
+            cosine(start, self.max_lr, step as f64 / up as f64)
+        }
+        else {
+            let down = (self.total - up.min(self.total)).max(1);
+            cosine(self.max_lr, start / self.final_div, (step - up) as f64 / down as f64)
+        }
+    }
+}
+
+//multiplies the rate by `factor` once the monitored loss (validation loss under Trainer) has not
+//improved by more than `threshold` (relative) for `patience` epochs, then waits `cooldown` epochs
+pub struct ReduceOnPlateau {
+    pub factor : f64,
+    pub patience : usize,
+    pub threshold : f64,
+    pub cooldown : usize,
+    pub min_lr : f64,
+    scale : f64,
+    best : f64,
+    bad_epochs : usize,
+    cooling : usize,
+}
+
+impl ReduceOnPlateau {
+    pub fn new(factor : f64, patience : usize) -> Self {
+        ReduceOnPlateau {
+            factor,
+            patience,
+            threshold : 1e-4,
+            cooldown : 0,
+            min_lr : 0.0,
+            scale : 1.0,
+            best : f64::INFINITY,
+            bad_epochs : 0,
+            cooling : 0,
+        }
+    }
+    pub fn with_threshold(mut self, threshold : f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+    pub fn with_cooldown(mut self, cooldown : usize) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+    pub fn with_min_lr(mut self, min_lr : f64) -> Self {
+        self.min_lr = min_lr;
+        self
+    }
+}
+
+impl LrScheduler for ReduceOnPlateau {
+    fn learning_rate(&mut self, base : f64, _step : usize) -> f64 {
+        (base * self.scale).max(self.min_lr)
+    }
+    fn observe(&mut self, loss : f64) {
+        if loss < self.best * (1.0 - self.threshold) {
+            self.best = loss;
+            self.bad_epochs = 0;
+        }
+        else {
+            self.bad_epochs += 1;
+        }
+        if self.cooling > 0 {
+            self.cooling -= 1;
+            self.bad_epochs = 0;
+        }
+        else if self.bad_epochs > self.patience {
+            self.scale *= self.factor;
+            self.cooling = self.cooldown;
+            self.bad_epochs = 0;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interval {
+    Epoch,
+    Batch,
+}
+
+//a scheduler attached to a training loop: remembers the starting rate and counts epochs and
+//batches across fit calls, so resumed training carries on where the schedule left off
+pub struct Schedule {
+    scheduler : Box<dyn LrScheduler>,
+    interval : Interval,
+    base : Option<f64>,
+    epoch : usize,
+    batch : usize,
+}
+
+impl Schedule {
+    pub fn new(scheduler : Box<dyn LrScheduler>, interval : Interval) -> Self {
+        Schedule { scheduler, interval, base : None, epoch : 0, batch : 0 }
+    }
+    pub fn start_epoch(&mut self, optimizer : &mut dyn Optimizer) {
+        let base = *self.base.get_or_insert(optimizer.learning_rate());
+        if self.interval == Interval::Epoch {
+            optimizer.set_learning_rate(self.scheduler.learning_rate(base, self.epoch));
+        }
+        self.epoch += 1;
+    }
+    pub fn start_batch(&mut self, optimizer : &mut dyn Optimizer) {
+        if self.interval == Interval::Batch {
+            let base = *self.base.get_or_insert(optimizer.learning_rate());
+            optimizer.set_learning_rate(self.scheduler.learning_rate(base, self.batch));
+            self.batch += 1;
+        }
+    }
+    pub fn end_epoch(&mut self, loss : f64) {
+        self.scheduler.observe(loss);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(name : &str, values : &[f64], expected : &[f64]) {
+        assert!(values.len() == expected.len() && values.iter().zip(expected).all(|(v, e)| (v - e).abs() < 1e-12),
+                "{}: {:?}, expected {:?}", name, values, expected);
+    }
+
+    //the rate for each step from 0 at a base of 1
+    fn rates(scheduler : &mut dyn LrScheduler, steps : usize) -> Vec<f64> {
+        (0..steps).map(|step| scheduler.learning_rate(1.0, step)).collect()
+    }
+
+    //the rate after each observed loss
+    fn observed(scheduler : &mut dyn LrScheduler, losses : &[f64]) -> Vec<f64> {
+        losses.iter().enumerate().map(|(epoch, &loss)| {
+            scheduler.observe(loss);
+            scheduler.learning_rate(1.0, epoch)
+        }).collect()
+    }
+
+    #[test]
+    fn reduce_on_plateau_waits_out_patience() {
+        //the second and third losses improve by less than the relative threshold
+        let mut plateau = ReduceOnPlateau::new(0.5, 2);
+        close("patience", &observed(&mut plateau, &[1.0, 0.99995, 0.9999, 1.2, 0.5, 0.6]), &[1.0, 1.0, 1.0, 0.5, 0.5, 0.5]);
+        let mut plateau = ReduceOnPlateau::new(0.5, 0).with_threshold(0.1);
+        close("threshold", &observed(&mut plateau, &[1.0, 0.8, 0.75, 0.6]), &[1.0, 1.0, 0.5, 0.5]);
+        //after a cut, `cooldown` epochs pass before the bad ones count again
+        let mut plateau = ReduceOnPlateau::new(0.5, 0).with_cooldown(2);
+        close("cooldown", &observed(&mut plateau, &[1.0; 6]), &[1.0, 0.5, 0.5, 0.5, 0.25, 0.25]);
+        let mut plateau = ReduceOnPlateau::new(0.5, 0).with_min_lr(0.3);
+        close("min_lr", &observed(&mut plateau, &[1.0; 4]), &[1.0, 0.5, 0.3, 0.3]);
+    }
+
+    #[test]
+    fn cosine_annealing_restarts() {
+        let quarter = (1.0 + (PI / 4.0).cos()) / 2.0;
+        close("fixed period", &rates(&mut CosineAnnealing::new(4, 0.1), 6), &[1.0, 0.1 + 0.9 * quarter, 0.55, 0.1 + 0.9 * (1.0 - quarter), 1.0, 0.1 + 0.9 * quarter]);
+        //periods of 2, 4 and then 8 steps
+        close("doubling periods", &rates(&mut CosineAnnealing::with_restarts(2, 2, 0.0), 7), &[1.0, 0.5, 1.0, quarter, 0.5, 1.0 - quarter, 1.0]);
+    }
+
+    #[test]
+    fn warmup_hands_over_to_the_next_schedule() {
+        close("constant", &rates(&mut LinearWarmup::new(4), 6), &[0.25, 0.5, 0.75, 1.0, 1.0, 1.0]);
+        //the step decay counts from the end of the warmup
+        close("then", &rates(&mut LinearWarmup::new(4).then(Box::new(StepDecay::new(2, 0.5))), 8), &[0.25, 0.5, 0.75, 1.0, 1.0, 1.0, 0.5, 0.5]);
+        let mut warmup = LinearWarmup::new(1).then(Box::new(ReduceOnPlateau::new(0.5, 0)));
+        close("observe", &observed(&mut warmup, &[1.0, 1.0]), &[1.0, 0.5]);
+    }
+
+    #[test]
+    fn one_cycle_rises_then_anneals() {
+        let mut cycle = OneCycle::new(1.0, 10);
+        //three steps up from 1/25, then seven down to 1/25 / 1e4, whatever the base
+        let rates = (0..=10).map(|step| cycle.learning_rate(123.0, step)).collect::<Vec<f64>>();
+        close("ends", &[rates[0], rates[3], rates[10]], &[0.04, 1.0, 0.04 / 1e4]);
+        close("rising", &rates[1..2], &[1.0 - 0.96 * 0.75]);
+        assert!(rates[..4].windows(2).all(|w| w[0] < w[1]) && rates[3..].windows(2).all(|w| w[0] > w[1]));
+        close("past the end", &[cycle.learning_rate(1.0, 50)], &[0.04 / 1e4]);
+        let mut cycle = OneCycle::new(1.0, 10).with_warmup(0.0).with_divisors(2.0, 5.0);
+        close("no warmup", &[cycle.learning_rate(1.0, 0), cycle.learning_rate(1.0, 10)], &[1.0, 0.1]);
+    }
+}
@@ -0,0 +1,683 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use nalgebra::{DMatrix, DVector};
+use serde_json::{json, Value};
+use crate::activation::{self, ActivationLayer};
+use crate::convnn::{ConvLayer, Tensor3};
+use crate::loss;
+use crate::neuralnetwork::{DenseLayer, Layer, Loss};
+use crate::pooling::{AvgPool2d, GlobalAveragePool, MaxPool2d};
+use crate::shape::{build_stack, Flatten, Reshape, Shape};
+
+//binary layout: MAGIC, u16 version, payload, u32 CRC-32 of everything before it.
+//all integers and floats are little-endian, strings and matrices are length-prefixed
+const MAGIC : &[u8; 4] = b"RSNN";
+pub const FORMAT_VERSION : u16 = 2;
+const JSON_FORMAT : &str = "rust-ai-model";
+
+//everything needed to rebuild a layer
+pub enum LayerSpec {
+    Dense {
+        weights : DMatrix<f64>,
+        biases : DVector<f64>,
+        activation : String,
+    },
+    Conv {
+        filters : Vec<Tensor3>,
+        bias : DVector<f64>,
+        stride : usize,
+        padding : usize,
+        in_rows : usize,
+        in_cols : usize,
+    },
+    Activation {
+        activation : String,
+    },
+    Flatten,
+    Reshape {
+        shape : Shape,
+    },
+    MaxPool {
+        kernel : usize,
+        stride : usize,
+        padding : usize,
+    },
+    AvgPool {
+        kernel : usize,
+        stride : usize,
+        padding : usize,
+    },
+    GlobalAveragePool,
+}
+
+//everything needed to rebuild a NeuralNetwork (one layer stack) or a CNN (two)
+pub struct ModelSpec {
+    pub stacks : Vec<Vec<LayerSpec>>,
+    pub loss : String,
+}
+
+fn invalid(message : String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn unknown_activation(name : &str) -> Error {
+    invalid(format!("unknown activation '{}'", name))
+}
+
+impl LayerSpec {
+    //a file with a valid checksum can still have been edited by hand, so the parameter shapes are
+    //checked against each other here rather than failing inside nalgebra on the first forward
+    fn check(&self) -> Result<()> {
+        match self {
+            LayerSpec::Dense { weights, biases, .. } => {
+                if weights.is_empty() {
+                    return Err(invalid(format!("dense layer has {}x{} weights", weights.nrows(), weights.ncols())));
+                }
+                if biases.len() != weights.nrows() {
+                    return Err(invalid(format!("dense layer has {} outputs but {} biases", weights.nrows(), biases.len())));
+                }
+            }
+            LayerSpec::Conv { filters, bias, stride, .. } => {
+                let channels = filters.first().map_or(0, |filter| filter.len());
+                let size = filters.first().and_then(|filter| filter.first()).map_or(0, |kernel| kernel.nrows());
+                if channels == 0 || size == 0 {
+                    return Err(invalid("conv layer has no filters".to_string()));
+                }
+                for (f, filter) in filters.iter().enumerate() {
+                    if filter.len() != channels {
+                        return Err(invalid(format!("conv filter {} has {} channels, filter 0 has {}", f, filter.len(), channels)));
+                    }
+                    if let Some(kernel) = filter.iter().find(|kernel| kernel.shape() != (size, size)) {
+                        return Err(invalid(format!("conv filter {} has a {}x{} kernel, expected {}x{}", f, kernel.nrows(), kernel.ncols(), size, size)));
+                    }
+                }
+                if bias.len() != filters.len() {
+                    return Err(invalid(format!("conv layer has {} filters but {} biases", filters.len(), bias.len())));
+                }
+                if *stride == 0 {
+                    return Err(invalid("conv layer has stride 0".to_string()));
+                }
+            }
+            LayerSpec::Reshape { shape } if shape.is_empty() => {
+                return Err(invalid(format!("cannot reshape into {}x{}x{}", shape.channels, shape.rows, shape.cols)));
+            }
+            LayerSpec::MaxPool { kernel : 0, .. } | LayerSpec::AvgPool { kernel : 0, .. } => {
+                return Err(invalid("pooling layer has a 0x0 window".to_string()));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+    pub fn build(self) -> Result<Box<dyn Layer>> {
+        self.check()?;
+        let layer : Box<dyn Layer> = match self {
+            LayerSpec::Dense { weights, biases, activation } => {
+                let activation = activation::from_name(&activation).ok_or_else(|| unknown_activation(&activation))?;
+                Box::new(DenseLayer::from_parts(weights, biases, activation))
+            }
+            LayerSpec::Conv { filters, bias, stride, padding, in_rows, in_cols } => {
+                Box::new(ConvLayer::from_parts(filters, bias, stride, padding).with_input_size(in_rows, in_cols))
+            }
+            LayerSpec::Activation { activation } => {
+                let activation = activation::from_name(&activation).ok_or_else(|| unknown_activation(&activation))?;
+                Box::new(ActivationLayer::new(activation))
+            }
+            LayerSpec::Flatten => Box::new(Flatten),
+            LayerSpec::Reshape { shape } => Box::new(Reshape::new(shape)),
+            LayerSpec::MaxPool { kernel, stride, padding } => Box::new(MaxPool2d::new(kernel, stride, padding)),
+            LayerSpec::AvgPool { kernel, stride, padding } => Box::new(AvgPool2d::new(kernel, stride, padding)),
+            LayerSpec::GlobalAveragePool => Box::new(GlobalAveragePool::new()),
+        };
+        Ok(layer)
+    }
+}
+
+//describes every layer, failing on layers that do not support saving
+pub fn describe_layers(layers : &[Box<dyn Layer>]) -> Result<Vec<LayerSpec>> {
+    layers.iter().enumerate()
+        .map(|(i, layer)| layer.spec().ok_or_else(|| Error::new(ErrorKind::Unsupported, format!("layer {} cannot be saved", i))))
+        .collect()
+}
+
+pub fn build_layers(specs : Vec<LayerSpec>) -> Result<Vec<Box<dyn Layer>>> {
+    specs.into_iter().map(LayerSpec::build).collect()
+}
+
+//checks that consecutive layers fit together, from the first layer that fixes its input shape on
+pub fn check_layers(layers : &mut [Box<dyn Layer>], name : &str) -> Result<()> {
+    if let Some(start) = layers.iter().position(|layer| layer.input_shape().is_some()) {
+        let input = layers[start].input_shape().unwrap();
+        build_stack(&mut layers[start..], input, name).map_err(|e| invalid(e.to_string()))?;
+    }
+    Ok(())
+}
+
+pub fn build_loss(name : &str) -> Result<Box<dyn Loss>> {
+    loss::from_name(name).ok_or_else(|| invalid(format!("unknown loss '{}'", name)))
+}
+
+//bitwise CRC-32 (IEEE polynomial), small models do not need a lookup table
+pub fn crc32(bytes : &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {(crc >> 1) ^ 0xEDB8_8320} else {crc >> 1};
+        }
+    }
+    !crc
+}
+
+struct Writer {
+    bytes : Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, v : u8) {
+        self.bytes.push(v);
+    }
+    fn u64(&mut self, v : usize) {
+        self.bytes.extend_from_slice(&(v as u64).to_le_bytes());
+    }
+    fn str(&mut self, v : &str) {
+        self.u64(v.len());
+        self.bytes.extend_from_slice(v.as_bytes());
+    }
+    fn matrix(&mut self, m : &DMatrix<f64>) {
+        self.u64(m.nrows());
+        self.u64(m.ncols());
+        for x in m.iter() {
+            self.bytes.extend_from_slice(&x.to_le_bytes());
+        }
+    }
+    fn vector(&mut self, v : &DVector<f64>) {
+        self.u64(v.len());
+        for x in v.iter() {
+            self.bytes.extend_from_slice(&x.to_le_bytes());
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes : &'a [u8],
+    pos : usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n : usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(n).filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| invalid(format!("model file truncated at byte {}", self.pos)))?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+    fn u64(&mut self) -> Result<usize> {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf) as usize)
+    }
+    fn f64s(&mut self, n : usize) -> Result<Vec<f64>> {
+        let bytes = self.take(n.checked_mul(8).ok_or_else(|| invalid("matrix size overflows".to_string()))?)?;
+        Ok(bytes.chunks(8).map(|c| {
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(c);
+            f64::from_le_bytes(buf)
+        }).collect())
+    }
+    fn str(&mut self) -> Result<String> {
+        let len = self.u64()?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|e| invalid(e.to_string()))
+    }
+    fn matrix(&mut self) -> Result<DMatrix<f64>> {
+        let rows = self.u64()?;
+        let cols = self.u64()?;
+        let data = self.f64s(rows.saturating_mul(cols))?;
+        Ok(DMatrix::from_vec(rows, cols, data))
+    }
+    fn vector(&mut self) -> Result<DVector<f64>> {
+        let len = self.u64()?;
+        Ok(DVector::from_vec(self.f64s(len)?))
+    }
+}
+
+const TAG_DENSE : u8 = 0;
+const TAG_CONV : u8 = 1;
+const TAG_ACTIVATION : u8 = 2;
+const TAG_FLATTEN : u8 = 3;
+const TAG_RESHAPE : u8 = 4;
+const TAG_MAX_POOL : u8 = 5;
+const TAG_AVG_POOL : u8 = 6;
+const TAG_GLOBAL_AVG_POOL : u8 = 7;
+
+pub fn to_bytes(spec : &ModelSpec) -> Vec<u8> {
+    let mut w = Writer { bytes : Vec::new() };
+    w.bytes.extend_from_slice(MAGIC);
+    w.bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    w.str(&spec.loss);
+    w.u64(spec.stacks.len());
+    for stack in spec.stacks.iter() {
+        w.u64(stack.len());
+        for layer in stack.iter() {
+            match layer {
+                LayerSpec::Dense { weights, biases, activation } => {
+                    w.u8(TAG_DENSE);
+                    w.str(activation);
+                    w.matrix(weights);
+                    w.vector(biases);
+                }
+                LayerSpec::Conv { filters, bias, stride, padding, in_rows, in_cols } => {
+                    w.u8(TAG_CONV);
+                    w.u64(*stride);
+                    w.u64(*padding);
+                    w.u64(*in_rows);
+                    w.u64(*in_cols);
+                    w.u64(filters.len());
+                    for filter in filters.iter() {
+                        w.u64(filter.len());
+                        for kernel in filter.iter() {
+                            w.matrix(kernel);
+                        }
+                    }
+                    w.vector(bias);
+                }
+                LayerSpec::Activation { activation } => {
+                    w.u8(TAG_ACTIVATION);
+                    w.str(activation);
+                }
+                LayerSpec::Flatten => w.u8(TAG_FLATTEN),
+                LayerSpec::Reshape { shape } => {
+                    w.u8(TAG_RESHAPE);
+                    w.u64(shape.channels);
+                    w.u64(shape.rows);
+                    w.u64(shape.cols);
+                }
+                LayerSpec::MaxPool { kernel, stride, padding } | LayerSpec::AvgPool { kernel, stride, padding } => {
+                    w.u8(if matches!(layer, LayerSpec::MaxPool { .. }) {TAG_MAX_POOL} else {TAG_AVG_POOL});
+                    w.u64(*kernel);
+                    w.u64(*stride);
+                    w.u64(*padding);
+                }
+                LayerSpec::GlobalAveragePool => w.u8(TAG_GLOBAL_AVG_POOL),
+            }
+        }
+    }
+    let checksum = crc32(&w.bytes);
+    w.bytes.extend_from_slice(&checksum.to_le_bytes());
+    w.bytes
+}
+
+pub fn from_bytes(bytes : &[u8]) -> Result<ModelSpec> {
+    if bytes.len() < MAGIC.len() + 2 + 4 || &bytes[..4] != MAGIC {
+        return Err(invalid("not a model file (bad magic)".to_string()));
+    }
+    let (body, tail) = bytes.split_at(bytes.len() - 4);
+    let stored = u32::from_le_bytes([tail[0], tail[1], tail[2], tail[3]]);
+    if crc32(body) != stored {
+        return Err(invalid("model file checksum mismatch".to_string()));
+    }
+    let version = u16::from_le_bytes([body[4], body[5]]);
+    if version != FORMAT_VERSION {
+        return Err(invalid(format!("unsupported model format version {}", version)));
+    }
+    let mut r = Reader { bytes : body, pos : 6 };
+    let loss = r.str()?;
+    let mut stacks = Vec::new();
+    for _ in 0..r.u64()? {
+        let mut stack = Vec::new();
+        for _ in 0..r.u64()? {
+            let layer = match r.u8()? {
+                TAG_DENSE => {
+                    let activation = r.str()?;
+                    LayerSpec::Dense { activation, weights : r.matrix()?, biases : r.vector()? }
+                }
+                TAG_CONV => {
+                    let stride = r.u64()?;
+                    let padding = r.u64()?;
+                    let in_rows = r.u64()?;
+                    let in_cols = r.u64()?;
+                    let mut filters = Vec::new();
+                    for _ in 0..r.u64()? {
+                        filters.push((0..r.u64()?).map(|_| r.matrix()).collect::<Result<Tensor3>>()?);
+                    }
+                    LayerSpec::Conv { filters, bias : r.vector()?, stride, padding, in_rows, in_cols }
+                }
+                TAG_ACTIVATION => LayerSpec::Activation { activation : r.str()? },
+                TAG_FLATTEN => LayerSpec::Flatten,
+                TAG_RESHAPE => LayerSpec::Reshape { shape : Shape::new(r.u64()?, r.u64()?, r.u64()?) },
+                TAG_MAX_POOL => LayerSpec::MaxPool { kernel : r.u64()?, stride : r.u64()?, padding : r.u64()? },
+                TAG_AVG_POOL => LayerSpec::AvgPool { kernel : r.u64()?, stride : r.u64()?, padding : r.u64()? },
+                TAG_GLOBAL_AVG_POOL => LayerSpec::GlobalAveragePool,
+                tag => return Err(invalid(format!("unknown layer tag {}", tag))),
+            };
+            stack.push(layer);
+        }
+        stacks.push(stack);
+    }
+    if r.pos != body.len() {
+        return Err(invalid("trailing bytes after model".to_string()));
+    }
+    Ok(ModelSpec { stacks, loss })
+}
+
+//matrices are written as arrays of rows so the file reads like the math
+fn matrix_json(m : &DMatrix<f64>) -> Value {
+    Value::from(m.row_iter().map(|row| row.iter().cloned().collect::<Vec<f64>>()).collect::<Vec<Vec<f64>>>())
+}
+
+fn json_matrix(v : &Value) -> Result<DMatrix<f64>> {
+    let rows = v.as_array().ok_or_else(|| invalid("expected a matrix".to_string()))?;
+    let data = rows.iter().map(|row| {
+        row.as_array().ok_or_else(|| invalid("expected a matrix row".to_string()))?
+            .iter().map(|x| x.as_f64().ok_or_else(|| invalid("expected a number".to_string())))
+            .collect::<Result<Vec<f64>>>()
+    }).collect::<Result<Vec<Vec<f64>>>>()?;
+    let cols = data.first().map_or(0, |row| row.len());
+    if data.iter().any(|row| row.len() != cols) {
+        return Err(invalid("matrix rows have different lengths".to_string()));
+    }
+    let flat = data.iter().flatten().cloned().collect::<Vec<f64>>();
+    Ok(DMatrix::from_row_slice(data.len(), cols, &flat))
+}
+
+pub(crate) fn json_vector(v : &Value) -> Result<DVector<f64>> {
+    let data = v.as_array().ok_or_else(|| invalid("expected a vector".to_string()))?
+        .iter().map(|x| x.as_f64().ok_or_else(|| invalid("expected a number".to_string())))
+        .collect::<Result<Vec<f64>>>()?;
+    Ok(DVector::from_vec(data))
+}
+
+pub(crate) fn field<'a>(v : &'a Value, key : &str) -> Result<&'a Value> {
+    v.get(key).ok_or_else(|| invalid(format!("missing field '{}'", key)))
+}
+
+pub(crate) fn str_field(v : &Value, key : &str) -> Result<String> {
+    field(v, key)?.as_str().map(|s| s.to_string()).ok_or_else(|| invalid(format!("field '{}' is not a string", key)))
+}
+
+pub(crate) fn usize_field(v : &Value, key : &str) -> Result<usize> {
+    field(v, key)?.as_u64().map(|n| n as usize).ok_or_else(|| invalid(format!("field '{}' is not an integer", key)))
+}
+
+pub fn to_json(spec : &ModelSpec) -> Value {
+    let stacks = spec.stacks.iter().map(|stack| {
+        Value::from(stack.iter().map(|layer| match layer {
+            LayerSpec::Dense { weights, biases, activation } => json!({
+                "type" : "dense",
+                "activation" : activation,
+                "weights" : matrix_json(weights),
+                "biases" : biases.iter().cloned().collect::<Vec<f64>>(),
+            }),
+            LayerSpec::Conv { filters, bias, stride, padding, in_rows, in_cols } => json!({
+                "type" : "conv",
+                "stride" : stride,
+                "padding" : padding,
+                "in_rows" : in_rows,
+                "in_cols" : in_cols,
+                "filters" : filters.iter().map(|filter| filter.iter().map(matrix_json).collect::<Vec<Value>>()).collect::<Vec<Vec<Value>>>(),
+                "bias" : bias.iter().cloned().collect::<Vec<f64>>(),
+            }),
+            LayerSpec::Activation { activation } => json!({
+                "type" : "activation",
+                "activation" : activation,
+            }),
+            LayerSpec::Flatten => json!({
+                "type" : "flatten",
+            }),
+            LayerSpec::Reshape { shape } => json!({
+                "type" : "reshape",
+                "shape" : [shape.channels, shape.rows, shape.cols],
+            }),
+            LayerSpec::MaxPool { kernel, stride, padding } => json!({
+                "type" : "max_pool",
+                "kernel" : kernel,
+                "stride" : stride,
+                "padding" : padding,
+            }),
+            LayerSpec::AvgPool { kernel, stride, padding } => json!({
+                "type" : "avg_pool",
+                "kernel" : kernel,
+                "stride" : stride,
+                "padding" : padding,
+            }),
+            LayerSpec::GlobalAveragePool => json!({
+                "type" : "global_avg_pool",
+            }),
+        }).collect::<Vec<Value>>())
+    }).collect::<Vec<Value>>();
+    json!({
+        "format" : JSON_FORMAT,
+        "version" : FORMAT_VERSION,
+        "loss" : spec.loss,
+        "stacks" : stacks,
+    })
+}
+
+pub fn from_json(v : &Value) -> Result<ModelSpec> {
+    if field(v, "format")?.as_str() != Some(JSON_FORMAT) {
+        return Err(invalid("not a model file (bad format tag)".to_string()));
+    }
+    let version = usize_field(v, "version")?;
+    if version != FORMAT_VERSION as usize {
+        return Err(invalid(format!("unsupported model format version {}", version)));
+    }
+    let stacks = field(v, "stacks")?.as_array().ok_or_else(|| invalid("'stacks' is not an array".to_string()))?
+        .iter().map(|stack| {
+            stack.as_array().ok_or_else(|| invalid("layer stack is not an array".to_string()))?
+                .iter().map(|layer| {
+                    let spec = match str_field(layer, "type")?.as_str() {
+                        "dense" => LayerSpec::Dense {
+                            weights : json_matrix(field(layer, "weights")?)?,
+                            biases : json_vector(field(layer, "biases")?)?,
+                            activation : str_field(layer, "activation")?,
+                        },
+                        "conv" => LayerSpec::Conv {
+                            filters : field(layer, "filters")?.as_array().ok_or_else(|| invalid("'filters' is not an array".to_string()))?
+                                .iter().map(|filter| {
+                                    filter.as_array().ok_or_else(|| invalid("filter is not an array".to_string()))?
+                                        .iter().map(json_matrix).collect::<Result<Tensor3>>()
+                                }).collect::<Result<Vec<Tensor3>>>()?,
+                            bias : json_vector(field(layer, "bias")?)?,
+                            stride : usize_field(layer, "stride")?,
+                            padding : usize_field(layer, "padding")?,
+                            in_rows : usize_field(layer, "in_rows")?,
+                            in_cols : usize_field(layer, "in_cols")?,
+                        },
+                        "activation" => LayerSpec::Activation { activation : str_field(layer, "activation")? },
+                        "flatten" => LayerSpec::Flatten,
+                        "reshape" => {
+                            let dims = json_vector(field(layer, "shape")?)?;
+                            if dims.len() != 3 {
+                                return Err(invalid("'shape' must have 3 entries".to_string()));
+                            }
+                            LayerSpec::Reshape { shape : Shape::new(dims[0] as usize, dims[1] as usize, dims[2] as usize) }
+                        }
+                        "max_pool" => LayerSpec::MaxPool {
+                            kernel : usize_field(layer, "kernel")?,
+                            stride : usize_field(layer, "stride")?,
+                            padding : usize_field(layer, "padding")?,
+                        },
+                        "avg_pool" => LayerSpec::AvgPool {
+                            kernel : usize_field(layer, "kernel")?,
+                            stride : usize_field(layer, "stride")?,
+                            padding : usize_field(layer, "padding")?,
+                        },
+                        "global_avg_pool" => LayerSpec::GlobalAveragePool,
+                        other => return Err(invalid(format!("unknown layer type '{}'", other))),
+                    };
+                    Ok(spec)
+                }).collect::<Result<Vec<LayerSpec>>>()
+        }).collect::<Result<Vec<Vec<LayerSpec>>>>()?;
+    Ok(ModelSpec { stacks, loss : str_field(v, "loss")? })
+}
+
+pub fn save_binary<P : AsRef<Path>>(spec : &ModelSpec, path : P) -> Result<()> {
+    fs::write(path, to_bytes(spec))
+}
+
+pub fn load_binary<P : AsRef<Path>>(path : P) -> Result<ModelSpec> {
+    from_bytes(&fs::read(path)?)
+}
+
+pub fn save_json<P : AsRef<Path>>(spec : &ModelSpec, path : P) -> Result<()> {
+    write_json(&to_json(spec), path)
+}
+
+pub fn load_json<P : AsRef<Path>>(path : P) -> Result<ModelSpec> {
+    from_json(&read_json(path)?)
+}
+
+pub(crate) fn write_json<P : AsRef<Path>>(value : &Value, path : P) -> Result<()> {
+    let text = serde_json::to_string_pretty(value).map_err(|e| invalid(e.to_string()))?;
+    fs::write(path, text)
+}
+
+pub(crate) fn read_json<P : AsRef<Path>>(path : P) -> Result<Value> {
+    serde_json::from_str(&fs::read_to_string(path)?).map_err(|e| invalid(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use rand::Rng;
+    use crate::activation::{ActivationLayer, Gelu, Relu, Tanh};
+    use crate::convnn::{ConvLayer, CNN};
+    use crate::loss::SoftmaxCrossEntropy;
+    use crate::neuralnetwork::{DenseLayer, NeuralNetwork};
+    use crate::rng;
+    use crate::shape::{Flatten, Shape};
+
+    fn dense(inputs : usize, outputs : usize, biases : usize) -> LayerSpec {
+        LayerSpec::Dense { weights : DMatrix::from_element(outputs, inputs, 0.1), biases : DVector::zeros(biases), activation : "tanh".to_string() }
+    }
+
+    //through bytes with a valid checksum, the way a hand-edited file would arrive
+    fn load(stack : Vec<LayerSpec>) -> Result<NeuralNetwork> {
+        let bytes = to_bytes(&ModelSpec { stacks : vec![stack], loss : "mean_squared_error".to_string() });
+        NeuralNetwork::from_spec(from_bytes(&bytes)?)
+    }
+
+    fn expect_invalid(stack : Vec<LayerSpec>, what : &str) {
+        match load(stack) {
+            Err(e) => assert_eq!(e.kind(), ErrorKind::InvalidData, "{}: {}", what, e),
+            Ok(_) => panic!("{} loaded without error", what),
+        }
+    }
+
+    #[test]
+    fn consistent_models_load() {
+        let mut network = load(vec![dense(3, 4, 4), dense(4, 2, 2)]).unwrap();
+        assert_eq!(network.forward(&DVector::zeros(3)).len(), 2);
+    }
+
+    #[test]
+    fn mismatched_dimensions_are_invalid_data() {
+        expect_invalid(vec![dense(3, 4, 5)], "bias count");
+        expect_invalid(vec![dense(3, 4, 4), dense(5, 2, 2)], "consecutive dense sizes");
+        expect_invalid(vec![dense(0, 0, 0)], "empty weights");
+        let kernel = |n : usize| DMatrix::zeros(n, n);
+        let conv = |filters : Vec<Tensor3>, bias : usize| LayerSpec::Conv { filters, bias : DVector::zeros(bias), stride : 1, padding : 0, in_rows : 4, in_cols : 4 };
+        expect_invalid(vec![conv(vec![vec![kernel(3)], vec![kernel(2)]], 2)], "kernel sizes");
+        expect_invalid(vec![conv(vec![vec![kernel(3)], vec![kernel(3), kernel(3)]], 2)], "filter channels");
+        expect_invalid(vec![conv(vec![vec![DMatrix::zeros(3, 2)]], 1)], "non-square kernel");
+        expect_invalid(vec![conv(vec![vec![kernel(3)]], 2)], "conv bias count");
+        expect_invalid(vec![conv(vec![vec![kernel(3)]], 1), LayerSpec::Flatten, dense(9, 2, 2)], "conv output into dense");
+    }
+
+    //a file in the temp directory that is removed again when dropped
+    struct TempFile(PathBuf);
+    impl TempFile {
+        fn new(name : &str) -> Self {
+            TempFile(std::env::temp_dir().join(format!("project-{}-{}", std::process::id(), name)))
+        }
+    }
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn network() -> NeuralNetwork {
+        let mut r_vals = rng::seeded(9);
+        NeuralNetwork::new(vec![
+            Box::new(DenseLayer::with_activation_rng(4, 6, Box::new(Relu), &mut r_vals)),
+            Box::new(ActivationLayer::new(Box::new(Gelu))),
+            Box::new(DenseLayer::with_activation_rng(6, 3, Box::new(Tanh), &mut r_vals)),
+        ], Box::new(SoftmaxCrossEntropy::with_label_smoothing(0.1)))
+    }
+
+    fn cnn() -> CNN {
+        let mut r_vals = rng::seeded(10);
+        CNN::new(Shape::new(2, 6, 5), vec![
+            Box::new(ConvLayer::new_rng(2, 3, 3, 1, 1, &mut r_vals)),
+            Box::new(ActivationLayer::new(Box::new(Relu))),
+            Box::new(MaxPool2d::new(2, 2, 1)),
+            Box::new(ConvLayer::new_rng(3, 2, 2, 1, 0, &mut r_vals)),
+            Box::new(AvgPool2d::new(2, 1, 0)),
+        ], vec![
+            Box::new(Flatten),
+            Box::new(DenseLayer::with_activation_rng(4, 3, Box::new(Tanh), &mut r_vals)),
+        ], Box::new(SoftmaxCrossEntropy::new())).unwrap()
+    }
+
+    fn inputs(len : usize) -> Vec<DVector<f64>> {
+        let mut r_vals = rng::seeded(11);
+        (0..3).map(|_| DVector::from_fn(len, |_, _| r_vals.gen_range(-1.0..1.0))).collect()
+    }
+
+    #[test]
+    fn saved_models_load_with_identical_outputs() {
+        let mut original = network();
+        let binary = TempFile::new("dense.bin");
+        let json = TempFile::new("dense.json");
+        original.save(&binary.0).unwrap();
+        original.save_json(&json.0).unwrap();
+        for mut loaded in [NeuralNetwork::load(&binary.0).unwrap(), NeuralNetwork::load_json(&json.0).unwrap()] {
+            assert_eq!(loaded.spec().unwrap().loss, original.spec().unwrap().loss);
+            for x in inputs(4) {
+                assert_eq!(loaded.forward(&x), original.forward(&x));
+            }
+        }
+
+        let mut original = cnn();
+        let binary = TempFile::new("cnn.bin");
+        let json = TempFile::new("cnn.json");
+        original.save(&binary.0).unwrap();
+        original.save_json(&json.0).unwrap();
+        for (which, mut loaded) in [("binary", CNN::load(&binary.0).unwrap()), ("json", CNN::load_json(&json.0).unwrap())] {
+            assert_eq!(loaded.input_shape(), original.input_shape());
+            for x in inputs(60) {
+                assert_eq!(loaded.forward(&x), original.forward(&x), "{}", which);
+            }
+        }
+    }
+
+    #[test]
+    fn corrupted_files_fail_the_checksum() {
+        let bytes = to_bytes(&cnn().spec().unwrap());
+        assert!(from_bytes(&bytes).is_ok());
+        //a flipped bit anywhere past the magic and version, including in the checksum itself
+        for position in [6, bytes.len() / 2, bytes.len() - 5, bytes.len() - 1] {
+            let mut corrupted = bytes.clone();
+            corrupted[position] ^= 0x10;
+            let error = from_bytes(&corrupted).err().expect("corrupted bytes loaded");
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+            assert!(error.to_string().contains("checksum"), "byte {}: {}", position, error);
+        }
+        let file = TempFile::new("corrupted.bin");
+        let mut corrupted = bytes.clone();
+        corrupted[bytes.len() / 3] ^= 0xFF;
+        fs::write(&file.0, corrupted).unwrap();
+        assert_eq!(CNN::load(&file.0).err().map(|e| e.kind()), Some(ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn crc32_matches_the_reference_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+}
@@ -0,0 +1,126 @@
+use std::fmt;
+use nalgebra::DMatrix;
+use crate::autodiff::Var;
+use crate::neuralnetwork::Layer;
+use crate::serialization::LayerSpec;
+
+//C×H×W; plain vectors are 1×1×N
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Shape {
+    pub channels : usize,
+    pub rows : usize,
+    pub cols : usize,
+}
+
+impl Shape {
+    pub fn new(channels : usize, rows : usize, cols : usize) -> Self {
+        Shape { channels, rows, cols }
+    }
+    pub fn vector(len : usize) -> Self {
+        Shape { channels : 1, rows : 1, cols : len }
+    }
+    pub fn len(&self) -> usize {
+        self.channels * self.rows * self.cols
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn is_vector(&self) -> bool {
+        self.channels == 1 && self.rows == 1
+    }
+}
+
+impl fmt::Display for Shape {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        if self.is_vector() {
+            write!(f, "{}", self.cols)
+        }
+        else {
+            write!(f, "{}x{}x{}", self.channels, self.rows, self.cols)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShapeError {
+    pub message : String,
+}
+
+impl ShapeError {
+    pub fn new(message : String) -> Self {
+        ShapeError { message }
+    }
+    //prefixes the message with where in the model it happened
+    pub fn context(self, location : &str) -> Self {
+        ShapeError { message : format!("{}: {}", location, self.message) }
+    }
+}
+
+impl fmt::Display for ShapeError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ShapeError {}
+
+//runs `build` through a stack of layers, returning the final output shape
+pub fn build_stack(layers : &mut [Box<dyn Layer>], input : Shape, name : &str) -> Result<Shape, ShapeError> {
+    layers.iter_mut().enumerate().try_fold(input, |shape, (i, layer)| {
+        layer.build(shape).map_err(|e| e.context(&format!("{} layer {} (input {})", name, i, shape)))
+    })
+}
+
+//turns a C×H×W tensor into a plain vector; data is already stored flat so only the shape changes
+#[derive(Default)]
+pub struct Flatten;
+
+impl Layer for Flatten {
+    fn forward(&mut self, input : &DMatrix<f64>) -> DMatrix<f64> {
+        input.clone()
+    }
+    fn backward(&mut self, error : &DMatrix<f64>) -> DMatrix<f64> {
+        error.clone()
+    }
+    fn build(&mut self, input : Shape) -> Result<Shape, ShapeError> {
+        Ok(Shape::vector(input.len()))
+    }
+    fn spec(&self) -> Option<LayerSpec> {
+        Some(LayerSpec::Flatten)
+    }
+    fn forward_tape(&self, input : &Var, _params : &[Var]) -> Option<Var> {
+        Some(input.clone())
+    }
+}
+
+//reinterprets the input as `shape`, which must hold the same number of values
+pub struct Reshape {
+    shape : Shape,
+}
+
+impl Reshape {
+    pub fn new(shape : Shape) -> Self {
+        Reshape { shape }
+    }
+}
+
+impl Layer for Reshape {
+    fn forward(&mut self, input : &DMatrix<f64>) -> DMatrix<f64> {
+        input.clone()
+    }
+    fn backward(&mut self, error : &DMatrix<f64>) -> DMatrix<f64> {
+        error.clone()
+    }
+    fn build(&mut self, input : Shape) -> Result<Shape, ShapeError> {
+        if input.len() != self.shape.len() {
+            return Err(ShapeError::new(format!("cannot reshape {} ({} values) into {} ({} values)", input, input.len(), self.shape, self.shape.len())));
+        }
+        Ok(self.shape)
+    }
+    fn spec(&self) -> Option<LayerSpec> {
+        Some(LayerSpec::Reshape { shape : self.shape })
+    }
+    fn forward_tape(&self, input : &Var, _params : &[Var]) -> Option<Var> {
+        Some(input.clone())
+    }
+}
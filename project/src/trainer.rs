@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use nalgebra::{DMatrix, DVector};
+use rand::rngs::StdRng;
+use rand::Rng;
+use crate::datasets::{split_rng, DataLoader, Dataset, InMemoryDataset, Subset};
+use crate::neuralnetwork::Loss;
+use crate::optimizer::{Optimizer, Parameter};
+use crate::rng;
+use crate::scheduler::{Interval, LrScheduler, Schedule};
+
+//anything the trainer can run: NeuralNetwork and CNN both implement this
+pub trait Model {
+    fn forward_batch(&mut self, input : &DMatrix<f64>) -> DMatrix<f64>;
+    //pushes dL/doutput back through every layer, storing each layer's gradients
+    fn backward_batch(&mut self, error : &DMatrix<f64>) -> DMatrix<f64>;
+    fn parameters(&mut self) -> Vec<Parameter<'_>>;
+    //the loss the model was built with, which training and evaluation both use
+    fn loss(&self) -> &dyn Loss;
+    fn optimizer(&mut self) -> &mut dyn Optimizer;
+    //one optimizer step on the gradients left by backward_batch
+    fn step(&mut self);
+}
+
+//lets a trainer borrow a model instead of owning it
+impl<M : Model + ?Sized> Model for &mut M {
+    fn forward_batch(&mut self, input : &DMatrix<f64>) -> DMatrix<f64> {
+        (**self).forward_batch(input)
+    }
+    fn backward_batch(&mut self, error : &DMatrix<f64>) -> DMatrix<f64> {
+        (**self).backward_batch(error)
+    }
+    fn parameters(&mut self) -> Vec<Parameter<'_>> {
+        (**self).parameters()
+    }
+    fn loss(&self) -> &dyn Loss {
+        (**self).loss()
+    }
+    fn optimizer(&mut self) -> &mut dyn Optimizer {
+        (**self).optimizer()
+    }
+    fn step(&mut self) {
+        (**self).step()
+    }
+}
+
+//scores a batch of outputs against targets, one sample per column
+pub type Metric = fn(&DMatrix<f64>, &DMatrix<f64>) -> f64;
+
+//the ready-made ones live in metrics
+pub use crate::metrics::accuracy;
+
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    pub train_loss : Vec<f64>,
+    pub val_loss : Vec<f64>,
+    pub train_metrics : HashMap<String, Vec<f64>>,
+    pub val_metrics : HashMap<String, Vec<f64>>,
+    pub learning_rate : Vec<f64>, //optimizer's rate at the end of each epoch
+}
+
+impl History {
+    pub fn epochs(&self) -> usize {
+        self.train_loss.len()
+    }
+    //epoch index with the lowest validation loss, if a validation set was used
+    pub fn best_epoch(&self) -> Option<usize> {
+        self.val_loss.iter().enumerate()
+            .min_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(epoch, _)| epoch)
+    }
+}
+
+pub struct Trainer<M : Model> {
+    model : M,
+    train : InMemoryDataset,
+    validation : InMemoryDataset,
+    batch_size : usize,
+    shuffle : bool,
+    verbose : bool,
+    metrics : Vec<(String, Metric)>,
+    schedule : Option<Schedule>,
+    rng : StdRng, //drives the validation split and per-epoch shuffles
+}
+
+impl<M : Model> Trainer<M> {
+    //trains with the model's own loss and optimizer
+    pub fn new(model : M, inputs : Vec<DVector<f64>>, targets : Vec<DVector<f64>>) -> Self {
+        Trainer {
+            model,
+            train : InMemoryDataset::new(inputs, targets),
+            validation : InMemoryDataset::new(Vec::new(), Vec::new()),
+            batch_size : 32,
+            shuffle : true,
+            verbose : false,
+            metrics : Vec::new(),
+            schedule : None,
+            rng : rng::fork(),
+        }
+    }
+    //copies the samples out, so any Dataset (CSV, MNIST, a Subset from a split) can be trained on
+    pub fn from_dataset(model : M, dataset : &dyn Dataset) -> Self {
+        let (inputs, targets) = InMemoryDataset::from_dataset(dataset).into_parts();
+        Self::new(model, inputs, targets)
+    }
+    pub fn with_validation(mut self, inputs : Vec<DVector<f64>>, targets : Vec<DVector<f64>>) -> Self {
+        self.validation = InMemoryDataset::new(inputs, targets);
+        self
+    }
+    pub fn with_validation_dataset(mut self, dataset : &dyn Dataset) -> Self {
+        self.validation = InMemoryDataset::from_dataset(dataset);
+        self
+    }
+    //replaces the generator split off the global one at construction, call before with_validation_split
+    pub fn with_seed(mut self, seed : u64) -> Self {
+        self.rng = rng::seeded(seed);
+        self
+    }
+    //moves a random `fraction` of the training samples into the validation set; `stratify` as in split
+    pub fn with_validation_split(mut self, fraction : f64, stratify : bool) -> Self {
+        let split = split_rng(&self.train, fraction.clamp(0.0, 1.0), 0.0, stratify, &mut self.rng);
+        self.validation = InMemoryDataset::from_dataset(&Subset::new(&self.train, split.validation));
+        self.train = InMemoryDataset::from_dataset(&Subset::new(&self.train, split.train));
+        self
+    }
+    pub fn with_batch_size(mut self, batch_size : usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+    pub fn with_shuffle(mut self, shuffle : bool) -> Self {
+        self.shuffle = shuffle;
+        self
+    }
+    pub fn with_verbose(mut self, verbose : bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+    pub fn with_metric(mut self, name : &str, metric : Metric) -> Self {
+        self.metrics.push((name.to_string(), metric));
+        self
+    }
+    //sets the optimizer's rate every epoch or batch; ReduceOnPlateau watches the validation loss,
+    //or the training loss when there is no validation set
+    pub fn with_scheduler(mut self, scheduler : Box<dyn LrScheduler>, interval : Interval) -> Self {
+        self.schedule = Some(Schedule::new(scheduler, interval));
+        self
+    }
+    pub fn model(&mut self) -> &mut M {
+        &mut self.model
+    }
+    pub fn into_model(self) -> M {
+        self.model
+    }
+    pub fn optimizer(&mut self) -> &mut dyn Optimizer {
+        self.model.optimizer()
+    }
+
+    //one pass over the training set, returns the mean loss and mean metrics
+    pub fn train_epoch(&mut self) -> (f64, Vec<f64>) {
+        let mut total_loss = 0.0;
+        let mut totals = vec![0.0; self.metrics.len()];
+        if let Some(schedule) = self.schedule.as_mut() {
+            schedule.start_epoch(self.model.optimizer());
+        }
+        let mut loader = DataLoader::new(&self.train, self.batch_size).with_shuffle(self.shuffle).with_seed(self.rng.gen());
+        for (input, target) in loader.epoch() {
+            if let Some(schedule) = self.schedule.as_mut() {
+                schedule.start_batch(self.model.optimizer());
+            }
+            let output = self.model.forward_batch(&input);
+            let weight = input.ncols() as f64;
+            total_loss += self.model.loss().compute_batch(&output, &target) * weight;
+            for (total, (_, metric)) in totals.iter_mut().zip(self.metrics.iter()) {
+                *total += metric(&output, &target) * weight;
+            }
+            let error = self.model.loss().gradient_batch(&output, &target);
+            self.model.backward_batch(&error);
+            self.model.step();
+        }
+        let n = self.train.len().max(1) as f64;
+        (total_loss / n, totals.iter().map(|total| total / n).collect())
+    }
+
+    //loss and metrics on the given samples without updating the model
+    pub fn evaluate(&mut self, dataset : &dyn Dataset) -> (f64, Vec<f64>) {
+        evaluate(&mut self.model, &self.metrics, DataLoader::new(dataset, self.batch_size))
+    }
+
+    pub fn fit(&mut self, epochs : usize) -> History {
+        let mut history = History::default();
+        for epoch in 0..epochs {
+            let (train_loss, train_metrics) = self.train_epoch();
+            history.train_loss.push(train_loss);
+            for ((name, _), value) in self.metrics.iter().zip(train_metrics) {
+                history.train_metrics.entry(name.clone()).or_default().push(value);
+            }
+            history.learning_rate.push(self.model.optimizer().learning_rate());
+            let mut monitored = train_loss;
+            let mut line = format!("Epoch {} : Training Loss: {}", epoch+1, train_loss);
+            if !self.validation.is_empty() {
+                let (val_loss, val_metrics) = evaluate(&mut self.model, &self.metrics, DataLoader::new(&self.validation, self.batch_size));
+                history.val_loss.push(val_loss);
+                monitored = val_loss;
+                line += &format!(", Validation Loss: {}", val_loss);
+                for ((name, _), value) in self.metrics.iter().zip(val_metrics) {
+                    line += &format!(", Validation {}: {}", name, value);
+                    history.val_metrics.entry(name.clone()).or_default().push(value);
+                }
+            }
+            if let Some(schedule) = self.schedule.as_mut() {
+                schedule.end_epoch(monitored);
+            }
+            if self.verbose {
+                println!("{}", line);
+            }
+        }
+        history
+    }
+}
+
+//the loop behind NeuralNetwork::fit and CNN::fit: trains on the samples with the model's own schedule,
+//handed back afterwards so it carries on in the next call, and returns each epoch's mean loss
+pub(crate) fn fit_samples<M : Model + ?Sized>(model : &mut M, schedule : &mut Option<Schedule>, inputs : &[DVector<f64>], targets : &[DVector<f64>],
+                                              batch_size : usize, epochs : usize) -> Vec<f64> {
+    let mut trainer = Trainer::new(model, inputs.to_vec(), targets.to_vec()).with_batch_size(batch_size);
+    trainer.schedule = schedule.take();
+    let history = trainer.fit(epochs);
+    *schedule = trainer.schedule;
+    history.train_loss
+}
+
+fn evaluate<M : Model>(model : &mut M, metrics : &[(String, Metric)], mut loader : DataLoader) -> (f64, Vec<f64>) {
+    let mut total_loss = 0.0;
+    let mut totals = vec![0.0; metrics.len()];
+    let mut n = 0;
+    for (input, target) in loader.epoch() {
+        let output = model.forward_batch(&input);
+        let weight = input.ncols() as f64;
+        total_loss += model.loss().compute_batch(&output, &target) * weight;
+        for (total, (_, metric)) in totals.iter_mut().zip(metrics.iter()) {
+            *total += metric(&output, &target) * weight;
+        }
+        n += input.ncols();
+    }
+    let n = n.max(1) as f64;
+    (total_loss / n, totals.iter().map(|total| total / n).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::activation::Identity;
+    use crate::loss::MeanAbsoluteError;
+    use crate::neuralnetwork::{DenseLayer, NeuralNetwork};
+    use crate::optimizer::Sgd;
+    use crate::scheduler::StepDecay;
+
+    #[test]
+    fn trains_with_the_models_loss_and_optimizer() {
+        let mut r_vals = rng::seeded(5);
+        let layer = DenseLayer::with_activation_rng(2, 1, Box::new(Identity), &mut r_vals);
+        let network = NeuralNetwork::with_optimizer(vec![Box::new(layer)], Box::new(MeanAbsoluteError), Box::new(Sgd::new(0.05)));
+        let inputs = (0..8).map(|i| DVector::from_vec(vec![i as f64 / 8.0, 1.0])).collect::<Vec<_>>();
+        let targets = inputs.iter().map(|x| DVector::from_element(1, 3.0 * x[0])).collect::<Vec<_>>();
+        let mut trainer = Trainer::new(network, inputs.clone(), targets.clone()).with_seed(5).with_shuffle(false).with_batch_size(8);
+        let before = trainer.model().evaluate(&inputs, &targets).loss;
+        let history = trainer.fit(3);
+        assert_eq!(history.learning_rate, vec![0.05; 3]);
+        assert!((history.train_loss[0] - before).abs() < 1e-12, "first epoch loss {} is not the model's MAE {}", history.train_loss[0], before);
+        assert!(history.train_loss[2] < history.train_loss[0]);
+    }
+
+    #[test]
+    fn network_fit_resumes_its_schedule() {
+        let mut r_vals = rng::seeded(6);
+        let layer = DenseLayer::with_activation_rng(2, 1, Box::new(Identity), &mut r_vals);
+        let mut network = NeuralNetwork::with_optimizer(vec![Box::new(layer)], Box::new(MeanAbsoluteError), Box::new(Sgd::new(0.1)));
+        network.set_scheduler(Box::new(StepDecay::new(2, 0.5)), Interval::Epoch);
+        let inputs = (0..6).map(|i| DVector::from_vec(vec![i as f64 / 6.0, 1.0])).collect::<Vec<_>>();
+        let targets = inputs.iter().map(|x| DVector::from_element(1, x[0])).collect::<Vec<_>>();
+        assert_eq!(network.fit(&inputs, &targets, 4, 3).len(), 3);
+        network.fit(&inputs, &targets, 4, 2);
+        //five epochs in, the rate has been halved twice
+        assert!((Model::optimizer(&mut network).learning_rate() - 0.025).abs() < 1e-12);
+    }
+}
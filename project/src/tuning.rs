@@ -0,0 +1,792 @@
+use std::cmp::Ordering;
+use std::fmt;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use crate::datasets::{k_fold_rng, split_rng, Dataset, InMemoryDataset, Split, Subset};
+use crate::init::standard_normal;
+use crate::rng;
+use crate::scheduler::{Interval, LrScheduler};
+use crate::trainer::{Metric, Model, Trainer};
+
+//hyperparameter search: a SearchSpace says what may vary, a Search proposes configurations, and a
+//Tuner builds, trains and scores a model for each one on held-out data
+
+//how one hyperparameter is drawn; integer-valued ones (hidden size, epochs) are discrete lists
+#[derive(Debug, Clone, PartialEq)]
+pub enum Distribution {
+    Discrete(Vec<f64>),
+    Uniform(f64, f64),
+    LogUniform(f64, f64), //uniform in ln(x), for learning rates and other scales
+}
+
+impl Distribution {
+    pub fn sample<R : Rng + ?Sized>(&self, r_vals : &mut R) -> f64 {
+        match self {
+            Distribution::Discrete(values) => *values.choose(r_vals).expect("a discrete hyperparameter needs at least one value"),
+            Distribution::Uniform(low, high) => r_vals.gen_range(*low..=*high),
+            Distribution::LogUniform(low, high) => r_vals.gen_range(low.ln()..=high.ln()).exp(),
+        }
+    }
+    //every value of a discrete list, or `points` evenly spaced (geometrically for log-uniform) over a range
+    pub fn grid(&self, points : usize) -> Vec<f64> {
+        //fractions of the way along the range, with both ends hit exactly
+        let steps = (0..points).map(|i| if points == 1 {0.5} else {i as f64 / (points - 1) as f64});
+        let spaced = |at : &dyn Fn(f64) -> f64, low : f64, high : f64| steps.clone()
+            .map(|t| if t == 0.0 {low} else if t == 1.0 {high} else {at(t)})
+            .collect::<Vec<f64>>();
+        match self {
+            Distribution::Discrete(values) => values.clone(),
+            Distribution::Uniform(low, high) => spaced(&|t| low + (high - low) * t, *low, *high),
+            Distribution::LogUniform(low, high) => spaced(&|t| low * (high / low).powf(t), *low, *high),
+        }
+    }
+}
+
+//named hyperparameters in declaration order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchSpace {
+    params : Vec<(String, Distribution)>,
+}
+
+impl SearchSpace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with(mut self, name : &str, distribution : Distribution) -> Self {
+        assert!(self.params.iter().all(|(existing, _)| existing != name), "hyperparameter '{}' is declared twice", name);
+        if let Distribution::LogUniform(low, _) = distribution {
+            assert!(low > 0.0, "log-uniform range for '{}' must be positive", name);
+        }
+        self.params.push((name.to_string(), distribution));
+        self
+    }
+    pub fn discrete(self, name : &str, values : &[f64]) -> Self {
+        self.with(name, Distribution::Discrete(values.to_vec()))
+    }
+    pub fn uniform(self, name : &str, low : f64, high : f64) -> Self {
+        self.with(name, Distribution::Uniform(low, high))
+    }
+    pub fn log_uniform(self, name : &str, low : f64, high : f64) -> Self {
+        self.with(name, Distribution::LogUniform(low, high))
+    }
+    pub fn params(&self) -> &[(String, Distribution)] {
+        &self.params
+    }
+    pub fn sample<R : Rng + ?Sized>(&self, r_vals : &mut R) -> Params {
+        Params { values : self.params.iter().map(|(name, d)| (name.clone(), d.sample(r_vals))).collect() }
+    }
+    //cartesian product of every parameter's grid, the last parameter varying fastest
+    pub fn grid(&self, points : usize) -> Vec<Params> {
+        let mut grid = vec![Params::default()];
+        for (name, distribution) in self.params.iter() {
+            let values = distribution.grid(points);
+            grid = grid.into_iter()
+                .flat_map(|params| values.iter().map(move |&value| params.clone().with(name, value)))
+                .collect();
+        }
+        grid
+    }
+}
+
+//one configuration, as handed to the model builder
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Params {
+    values : Vec<(String, f64)>,
+}
+
+impl Params {
+    pub fn with(mut self, name : &str, value : f64) -> Self {
+        match self.values.iter_mut().find(|(existing, _)| existing == name) {
+            Some(entry) => entry.1 = value,
+            None => self.values.push((name.to_string(), value)),
+        }
+        self
+    }
+    pub fn get(&self, name : &str) -> f64 {
+        self.values.iter().find(|(existing, _)| existing == name)
+            .unwrap_or_else(|| panic!("no hyperparameter named '{}'", name)).1
+    }
+    //rounded, for sizes and counts declared as discrete lists
+    pub fn usize(&self, name : &str) -> usize {
+        self.get(name).round().max(0.0) as usize
+    }
+    pub fn values(&self) -> &[(String, f64)] {
+        &self.values
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        for (i, (name, value)) in self.values.iter().enumerate() {
+            write!(f, "{}{}={}", if i == 0 {""} else {", "}, name, value)?;
+        }
+        Ok(())
+    }
+}
+
+//what the builder returns for one configuration: an untrained model (with its loss and optimizer)
+//and how long to train it
+pub struct Setup<M : Model> {
+    pub model : M,
+    pub epochs : usize,
+    pub batch_size : usize,
+    pub scheduler : Option<(Box<dyn LrScheduler>, Interval)>,
+}
+
+impl<M : Model> Setup<M> {
+    pub fn new(model : M) -> Self {
+        Setup { model, epochs : 10, batch_size : 32, scheduler : None }
+    }
+    pub fn with_epochs(mut self, epochs : usize) -> Self {
+        self.epochs = epochs;
+        self
+    }
+    pub fn with_batch_size(mut self, batch_size : usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+    pub fn with_scheduler(mut self, scheduler : Box<dyn LrScheduler>, interval : Interval) -> Self {
+        self.scheduler = Some((scheduler, interval));
+        self
+    }
+}
+
+pub type Builder<M> = Box<dyn Fn(&Params) -> Setup<M>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Validation {
+    Holdout(f64), //train on the rest, score on this fraction
+    KFold(usize), //every sample is scored once, by the model that did not train on it
+}
+
+//what a trial is ranked by, measured on the held-out samples
+#[derive(Clone, Copy)]
+pub enum Scoring {
+    Loss, //the model's own loss, lower is better
+    Minimize(Metric),
+    Maximize(Metric),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trial {
+    pub params : Params,
+    pub score : f64, //mean over the folds
+    pub folds : Vec<f64>, //score on each fold
+    pub epochs : usize, //how long the configuration had trained when it was scored
+    pub rung : Option<usize>, //the halving rung it was scored at, None when the setup chose its own epochs
+    maximize : bool,
+}
+
+impl Trial {
+    //the score turned so that lower is always better
+    pub fn cost(&self) -> f64 {
+        if self.maximize {-self.score} else {self.score}
+    }
+    pub fn std(&self) -> f64 {
+        let n = self.folds.len().max(1) as f64;
+        (self.folds.iter().map(|s| (s - self.score).powi(2)).sum::<f64>() / n).sqrt()
+    }
+}
+
+//proposes configurations one at a time; `trials` holds everything scored so far, which under
+//successive halving includes one trial per rung a configuration reached
+pub trait Search {
+    fn next(&mut self, trials : &[Trial]) -> Option<Params>;
+}
+
+//how many distinct configurations `trials` were scored for
+pub fn configurations(trials : &[Trial]) -> usize {
+    trials.iter().filter(|trial| trial.rung.is_none_or(|rung| rung == 0)).count()
+}
+
+//every combination of the space's values, continuous ranges cut into `points` values each
+pub struct GridSearch {
+    grid : Vec<Params>,
+    position : usize,
+}
+
+impl GridSearch {
+    pub fn new(space : &SearchSpace, points : usize) -> Self {
+        GridSearch { grid : space.grid(points), position : 0 }
+    }
+    pub fn len(&self) -> usize {
+        self.grid.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.grid.is_empty()
+    }
+}
+
+impl Search for GridSearch {
+    fn next(&mut self, _trials : &[Trial]) -> Option<Params> {
+        let params = self.grid.get(self.position).cloned();
+        self.position += 1;
+        params
+    }
+}
+
+//`trials` independent draws from the space
+pub struct RandomSearch {
+    space : SearchSpace,
+    trials : usize,
+    rng : StdRng,
+}
+
+impl RandomSearch {
+    pub fn new(space : SearchSpace, trials : usize) -> Self {
+        RandomSearch { space, trials, rng : rng::fork() }
+    }
+    pub fn with_seed(mut self, seed : u64) -> Self {
+        self.rng = rng::seeded(seed);
+        self
+    }
+}
+
+impl Search for RandomSearch {
+    fn next(&mut self, trials : &[Trial]) -> Option<Params> {
+        (configurations(trials) < self.trials).then(|| self.space.sample(&mut self.rng))
+    }
+}
+
+//Tree-structured Parzen Estimator (Bergstra et al. 2011): after `startup` random draws the trials so
+//far are split into the best `gamma` fraction and the rest, each side becomes a density per
+//hyperparameter, and the next configuration is the candidate drawn from the good density with the
+//highest good(x) / bad(x). scores after different budgets don't compare, so as in BOHB (Falkner et
+//al. 2018) the densities are fitted on the largest budget that has `startup` trials
+pub struct Tpe {
+    space : SearchSpace,
+    trials : usize,
+    startup : usize,
+    gamma : f64,
+    candidates : usize,
+    rng : StdRng,
+}
+
+impl Tpe {
+    pub fn new(space : SearchSpace, trials : usize) -> Self {
+        Tpe { space, trials, startup : 10, gamma : 0.25, candidates : 24, rng : rng::fork() }
+    }
+    pub fn with_startup(mut self, startup : usize) -> Self {
+        self.startup = startup;
+        self
+    }
+    pub fn with_gamma(mut self, gamma : f64) -> Self {
+        assert!(gamma > 0.0 && gamma < 1.0, "gamma must be between 0 and 1");
+        self.gamma = gamma;
+        self
+    }
+    pub fn with_candidates(mut self, candidates : usize) -> Self {
+        self.candidates = candidates.max(1);
+        self
+    }
+    pub fn with_seed(mut self, seed : u64) -> Self {
+        self.rng = rng::seeded(seed);
+        self
+    }
+}
+
+impl Search for Tpe {
+    fn next(&mut self, trials : &[Trial]) -> Option<Params> {
+        if configurations(trials) >= self.trials {
+            return None;
+        }
+        let budget = |trial : &Trial| trial.rung.map(|_| trial.epochs);
+        let mut budgets = trials.iter().map(budget).collect::<Vec<Option<usize>>>();
+        budgets.sort();
+        budgets.dedup();
+        let fitted = budgets.into_iter().rev()
+            .map(|b| trials.iter().filter(|trial| budget(trial) == b).cloned().collect::<Vec<Trial>>())
+            .find(|same| same.len() >= self.startup.max(2));
+        let Some(mut sorted) = fitted else {
+            return Some(self.space.sample(&mut self.rng));
+        };
+        rank(&mut sorted);
+        let n_good = ((self.gamma * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len() - 1);
+        let (good, bad) = sorted.split_at(n_good);
+        let observed = |side : &[Trial], name : &str| side.iter().map(|trial| trial.params.get(name)).collect::<Vec<f64>>();
+        let densities = self.space.params().iter()
+            .map(|(name, distribution)| (name, Parzen::new(distribution, &observed(good, name)), Parzen::new(distribution, &observed(bad, name))))
+            .collect::<Vec<_>>();
+        let mut best : Option<(f64, Params)> = None;
+        for _ in 0..self.candidates {
+            let mut params = Params::default();
+            let mut ratio = 0.0;
+            for (name, good, bad) in densities.iter() {
+                let value = good.sample(&mut self.rng);
+                ratio += good.log_density(value) - bad.log_density(value);
+                params = params.with(name, value);
+            }
+            if best.as_ref().is_none_or(|(best, _)| ratio > *best) {
+                best = Some((ratio, params));
+            }
+        }
+        best.map(|(_, params)| params)
+    }
+}
+
+//density over one hyperparameter built from observed values, with the prior mixed in as one more
+//component so untried regions keep some mass
+enum Parzen {
+    Discrete { values : Vec<f64>, weights : Vec<f64> },
+    //log-uniform ranges are modelled in ln(x)
+    Continuous { centers : Vec<f64>, width : f64, low : f64, high : f64, log : bool },
+}
+
+impl Parzen {
+    fn new(distribution : &Distribution, observed : &[f64]) -> Self {
+        match distribution {
+            Distribution::Discrete(values) => {
+                let mut weights = vec![1.0; values.len()];
+                for x in observed {
+                    weights[nearest(values, *x)] += 1.0;
+                }
+                let total = weights.iter().sum::<f64>();
+                Parzen::Discrete { values : values.clone(), weights : weights.iter().map(|w| w / total).collect() }
+            }
+            Distribution::Uniform(low, high) | Distribution::LogUniform(low, high) => {
+                let log = matches!(distribution, Distribution::LogUniform(..));
+                let scale = |x : f64| if log {x.ln()} else {x};
+                let (low, high) = (scale(*low), scale(*high));
+                let centers = observed.iter().map(|&x| scale(x).clamp(low, high)).collect::<Vec<f64>>();
+                //narrows as evidence accumulates, but never below 1% of the range
+                let width = ((high - low) / (centers.len() + 1) as f64).max((high - low) * 0.01);
+                Parzen::Continuous { centers, width, low, high, log }
+            }
+        }
+    }
+    fn sample<R : Rng + ?Sized>(&self, r_vals : &mut R) -> f64 {
+        match self {
+            Parzen::Discrete { values, weights } => {
+                let mut u = r_vals.gen::<f64>();
+                for (value, weight) in values.iter().zip(weights.iter()) {
+                    if u < *weight {
+                        return *value;
+                    }
+                    u -= weight;
+                }
+                values[values.len() - 1]
+            }
+            Parzen::Continuous { centers, width, low, high, log } => {
+                //component 0 is the prior
+                let component = r_vals.gen_range(0..=centers.len());
+                let x = if component == 0 || low == high {
+                    r_vals.gen_range(*low..=*high)
+                }
+                else {
+                    (centers[component - 1] + width * standard_normal(r_vals)).clamp(*low, *high)
+                };
+                if *log {x.exp()} else {x}
+            }
+        }
+    }
+    fn log_density(&self, x : f64) -> f64 {
+        match self {
+            Parzen::Discrete { values, weights } => weights[nearest(values, x)].ln(),
+            Parzen::Continuous { centers, width, low, high, log } => {
+                let x = if *log {x.ln()} else {x};
+                let prior = 1.0 / (high - low).max(f64::MIN_POSITIVE);
+                let kernels = centers.iter()
+                    .map(|c| (-0.5 * ((x - c) / width).powi(2)).exp() / (width * (2.0 * std::f64::consts::PI).sqrt()))
+                    .sum::<f64>();
+                ((prior + kernels) / (centers.len() + 1) as f64).ln()
+            }
+        }
+    }
+}
+
+fn nearest(values : &[f64], x : f64) -> usize {
+    (0..values.len()).min_by(|&a, &b| (values[a] - x).abs().total_cmp(&(values[b] - x).abs())).unwrap_or(0)
+}
+
+pub struct Tuner<M : Model> {
+    builder : Builder<M>,
+    validation : Validation,
+    scoring : Scoring,
+    stratify : bool,
+    verbose : bool,
+    rng : StdRng, //drives the folds and each trainer's shuffles
+}
+
+impl<M : Model> Tuner<M> {
+    pub fn new(builder : Builder<M>) -> Self {
+        Tuner {
+            builder,
+            validation : Validation::Holdout(0.2),
+            scoring : Scoring::Loss,
+            stratify : false,
+            verbose : false,
+            rng : rng::fork(),
+        }
+    }
+    pub fn with_validation(mut self, validation : Validation) -> Self {
+        if let Validation::KFold(k) = validation {
+            assert!(k >= 2, "k-fold validation needs at least 2 folds");
+        }
+        self.validation = validation;
+        self
+    }
+    pub fn with_scoring(mut self, scoring : Scoring) -> Self {
+        self.scoring = scoring;
+        self
+    }
+    //keeps the class balance (largest target entry) in every fold
+    pub fn with_stratify(mut self, stratify : bool) -> Self {
+        self.stratify = stratify;
+        self
+    }
+    pub fn with_verbose(mut self, verbose : bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+    pub fn with_seed(mut self, seed : u64) -> Self {
+        self.rng = rng::seeded(seed);
+        self
+    }
+
+    //train and held-out (validation) indices of each fold; drawn once per search so every trial sees the same folds
+    pub fn folds(&mut self, dataset : &dyn Dataset) -> Vec<Split> {
+        match self.validation {
+            Validation::Holdout(fraction) => vec![split_rng(dataset, fraction, 0.0, self.stratify, &mut self.rng)],
+            Validation::KFold(k) => k_fold_rng(dataset, k, self.stratify, &mut self.rng),
+        }
+    }
+
+    //builds, trains and scores one configuration on every fold
+    pub fn evaluate(&mut self, params : &Params, dataset : &dyn Dataset, folds : &[Split]) -> Trial {
+        let held_out = held_out(dataset, folds);
+        let mut run = self.start(params, dataset, folds);
+        let epochs = run.target;
+        self.advance(&mut run, epochs, None, &held_out)
+    }
+    fn start(&mut self, params : &Params, dataset : &dyn Dataset, folds : &[Split]) -> Run<M> {
+        let mut target = 0;
+        let trainers = folds.iter().map(|fold| {
+            let setup = (self.builder)(params);
+            target = setup.epochs;
+            let mut trainer = Trainer::from_dataset(setup.model, &Subset::new(dataset, fold.train.clone()))
+                .with_seed(self.rng.gen())
+                .with_batch_size(setup.batch_size);
+            if let Some((scheduler, interval)) = setup.scheduler {
+                trainer = trainer.with_scheduler(scheduler, interval);
+            }
+            match self.scoring {
+                Scoring::Minimize(metric) | Scoring::Maximize(metric) => trainer.with_metric("score", metric),
+                Scoring::Loss => trainer,
+            }
+        }).collect();
+        Run { params : params.clone(), trainers, epochs : 0, target }
+    }
+    //trains every fold on to `epochs` in total and scores it
+    fn advance(&mut self, run : &mut Run<M>, epochs : usize, rung : Option<usize>, held_out : &[InMemoryDataset]) -> Trial {
+        let scores = run.trainers.iter_mut().zip(held_out.iter()).map(|(trainer, held_out)| {
+            trainer.fit(epochs.saturating_sub(run.epochs));
+            let (loss, metrics) = trainer.evaluate(held_out);
+            match self.scoring {
+                Scoring::Loss => loss,
+                _ => metrics[0],
+            }
+        }).collect::<Vec<f64>>();
+        run.epochs = run.epochs.max(epochs);
+        let score = scores.iter().sum::<f64>() / scores.len().max(1) as f64;
+        Trial { params : run.params.clone(), score, folds : scores, epochs : run.epochs, rung, maximize : matches!(self.scoring, Scoring::Maximize(_)) }
+    }
+    fn report(&self, number : usize, trial : &Trial) {
+        if self.verbose {
+            println!("Trial {} : {} -> {} (± {}) after {} epochs", number, trial.params, trial.score, trial.std(), trial.epochs);
+        }
+    }
+
+    //runs the search to completion and returns every trial, best first
+    pub fn search(&mut self, search : &mut dyn Search, dataset : &dyn Dataset) -> Vec<Trial> {
+        let folds = self.folds(dataset);
+        let held_out = held_out(dataset, &folds);
+        let mut trials = Vec::new();
+        while let Some(params) = search.next(&trials) {
+            let mut run = self.start(&params, dataset, &folds);
+            let epochs = run.target;
+            let trial = self.advance(&mut run, epochs, None, &held_out);
+            self.report(trials.len() + 1, &trial);
+            trials.push(trial);
+        }
+        rank(&mut trials);
+        trials
+    }
+
+    //draws `configs` configurations from `search` and trains them rung by rung, keeping the best
+    //1/eta after each rung; the setups' own epochs are ignored in favour of the schedule's.
+    //returns each configuration's last trial, those that got furthest first
+    pub fn successive_halving(&mut self, schedule : &SuccessiveHalving, search : &mut dyn Search, dataset : &dyn Dataset) -> Vec<Trial> {
+        let folds = self.folds(dataset);
+        let held_out = held_out(dataset, &folds);
+        let mut history = Vec::new();
+        self.halve(schedule, search, dataset, &folds, &held_out, &mut history)
+    }
+    //successive halving over every bracket, from many short runs to a few full-length ones; `search`
+    //must allow Hyperband::configurations() configurations for every bracket to run
+    pub fn hyperband(&mut self, schedule : &Hyperband, search : &mut dyn Search, dataset : &dyn Dataset) -> Vec<Trial> {
+        let folds = self.folds(dataset);
+        let held_out = held_out(dataset, &folds);
+        let mut history = Vec::new();
+        let mut results = Vec::new();
+        for bracket in schedule.brackets() {
+            results.extend(self.halve(&bracket, search, dataset, &folds, &held_out, &mut history));
+        }
+        rank_by_budget(&mut results);
+        results
+    }
+    //`history` collects every trial, including the rungs a configuration was stopped after, so the
+    //search sees all of them. each configuration is scored on the first rung as soon as it is drawn,
+    //so the search counts it before proposing the next
+    fn halve(&mut self, schedule : &SuccessiveHalving, search : &mut dyn Search, dataset : &dyn Dataset,
+             folds : &[Split], held_out : &[InMemoryDataset], history : &mut Vec<Trial>) -> Vec<Trial> {
+        let rungs = schedule.rungs();
+        let mut scored = Vec::new();
+        while scored.len() < schedule.configs {
+            match search.next(history) {
+                Some(params) => {
+                    let run = self.start(&params, dataset, folds);
+                    scored.push(self.rung(run, rungs[0], 0, held_out, history));
+                }
+                None => break,
+            }
+        }
+        let mut results = Vec::new();
+        for rung in 0..rungs.len() {
+            if rung > 0 {
+                scored = scored.into_iter().map(|(_, run)| self.rung(run, rungs[rung], rung, held_out, history)).collect();
+            }
+            scored.sort_by(|a, b| compare(&a.0, &b.0));
+            let keep = if rung + 1 == rungs.len() {0} else {(scored.len() / schedule.eta).max(1).min(scored.len())};
+            for (trial, _) in scored.drain(keep..) {
+                results.push(trial);
+            }
+        }
+        rank_by_budget(&mut results);
+        results
+    }
+    fn rung(&mut self, mut run : Run<M>, epochs : usize, rung : usize, held_out : &[InMemoryDataset], history : &mut Vec<Trial>) -> (Trial, Run<M>) {
+        let trial = self.advance(&mut run, epochs, Some(rung), held_out);
+        self.report(history.len() + 1, &trial);
+        history.push(trial.clone());
+        (trial, run)
+    }
+}
+
+//the held-out samples of every fold, copied out once per search
+fn held_out(dataset : &dyn Dataset, folds : &[Split]) -> Vec<InMemoryDataset> {
+    folds.iter().map(|fold| InMemoryDataset::from_dataset(&Subset::new(dataset, fold.validation.clone()))).collect()
+}
+
+//a configuration with one trainer per fold, kept alive so halving can resume it
+struct Run<M : Model> {
+    params : Params,
+    trainers : Vec<Trainer<M>>,
+    epochs : usize, //trained so far
+    target : usize, //what the setup asked for
+}
+
+//train `configs` configurations briefly, keep the best 1/eta, train those eta times longer, and
+//repeat until max_epochs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SuccessiveHalving {
+    configs : usize,
+    min_epochs : usize,
+    max_epochs : usize,
+    eta : usize,
+}
+
+impl SuccessiveHalving {
+    pub fn new(configs : usize, min_epochs : usize, max_epochs : usize) -> Self {
+        assert!(min_epochs >= 1 && min_epochs <= max_epochs, "need 1 <= min_epochs <= max_epochs");
+        SuccessiveHalving { configs, min_epochs, max_epochs, eta : 3 }
+    }
+    pub fn with_eta(mut self, eta : usize) -> Self {
+        assert!(eta >= 2, "eta must be at least 2");
+        self.eta = eta;
+        self
+    }
+    //total epochs at each rung: max_epochs / eta^k for every k that stays at or above min_epochs
+    pub fn rungs(&self) -> Vec<usize> {
+        let mut rungs = vec![self.max_epochs];
+        while rungs[0] / self.eta >= self.min_epochs {
+            rungs.insert(0, rungs[0] / self.eta);
+        }
+        rungs
+    }
+}
+
+//Li et al. 2018: successive halving brackets that trade the number of configurations against how
+//early they are judged, so neither a small nor a large minimum budget has to be guessed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hyperband {
+    max_epochs : usize,
+    min_epochs : usize,
+    eta : usize,
+}
+
+impl Hyperband {
+    pub fn new(max_epochs : usize) -> Self {
+        assert!(max_epochs >= 1, "max_epochs must be at least 1");
+        Hyperband { max_epochs, min_epochs : 1, eta : 3 }
+    }
+    pub fn with_min_epochs(mut self, min_epochs : usize) -> Self {
+        assert!(min_epochs >= 1 && min_epochs <= self.max_epochs, "need 1 <= min_epochs <= max_epochs");
+        self.min_epochs = min_epochs;
+        self
+    }
+    pub fn with_eta(mut self, eta : usize) -> Self {
+        assert!(eta >= 2, "eta must be at least 2");
+        self.eta = eta;
+        self
+    }
+    pub fn brackets(&self) -> Vec<SuccessiveHalving> {
+        //s_max = floor(log_eta(max / min)), computed without floating point
+        let mut s_max = 0;
+        while self.min_epochs * self.eta.pow(s_max + 1) <= self.max_epochs {
+            s_max += 1;
+        }
+        (0..=s_max).rev().map(|s| {
+            let configs = ((s_max + 1) as f64 / (s + 1) as f64 * self.eta.pow(s) as f64).ceil() as usize;
+            let min_epochs = (self.max_epochs / self.eta.pow(s)).max(self.min_epochs);
+            SuccessiveHalving::new(configs, min_epochs, self.max_epochs).with_eta(self.eta)
+        }).collect()
+    }
+    //distinct configurations drawn over all brackets
+    pub fn configurations(&self) -> usize {
+        self.brackets().iter().map(|bracket| bracket.configs).sum()
+    }
+}
+
+//sorts best first; NaN scores (diverged runs) go last
+pub fn rank(trials : &mut [Trial]) {
+    trials.sort_by(compare);
+}
+
+//ranks trials trained for longer ahead of any stopped earlier, then by score
+pub fn rank_by_budget(trials : &mut [Trial]) {
+    trials.sort_by(|a, b| b.epochs.cmp(&a.epochs).then_with(|| compare(a, b)));
+}
+
+fn compare(a : &Trial, b : &Trial) -> Ordering {
+    match (a.cost().is_nan(), b.cost().is_nan()) {
+        (false, true) => Ordering::Less,
+        (true, false) => Ordering::Greater,
+        _ => a.cost().total_cmp(&b.cost()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::DVector;
+    use crate::activation::Identity;
+    use crate::neuralnetwork::{DenseLayer, MeanSquaredError, NeuralNetwork};
+    use crate::optimizer::Sgd;
+
+    fn tuner() -> Tuner<NeuralNetwork> {
+        Tuner::new(Box::new(|params : &Params| {
+            let layer = DenseLayer::with_activation_rng(2, 1, Box::new(Identity), &mut rng::seeded(3));
+            Setup::new(NeuralNetwork::with_optimizer(vec![Box::new(layer)], Box::new(MeanSquaredError), Box::new(Sgd::new(params.get("learning_rate")))))
+                .with_batch_size(4)
+        })).with_seed(3)
+    }
+
+    fn trial(x : f64, score : f64, epochs : usize, rung : usize) -> Trial {
+        Trial { params : Params::default().with("x", x), score, folds : vec![score], epochs, rung : Some(rung), maximize : false }
+    }
+
+    fn dataset() -> InMemoryDataset {
+        let inputs = (0..20).map(|i| DVector::from_vec(vec![i as f64 / 20.0, 1.0])).collect::<Vec<_>>();
+        let targets = inputs.iter().map(|x| DVector::from_element(1, 2.0 * x[0])).collect::<Vec<_>>();
+        InMemoryDataset::new(inputs, targets)
+    }
+
+    #[test]
+    fn grid_hits_the_endpoints_with_the_last_parameter_fastest() {
+        let space = SearchSpace::new().discrete("a", &[2.0, 5.0]).uniform("b", 0.0, 1.0).log_uniform("lr", 1e-4, 1e-2);
+        let grid = space.grid(3);
+        assert_eq!(grid.len(), 2 * 3 * 3);
+        let values = |params : &Params| params.values().iter().map(|(_, v)| *v).collect::<Vec<f64>>();
+        assert_eq!(values(&grid[0]), vec![2.0, 0.0, 1e-4]);
+        assert_eq!(values(&grid[17]), vec![5.0, 1.0, 1e-2]);
+        //geometric spacing for the log-uniform range, arithmetic for the uniform one
+        assert!((grid[1].get("lr") - 1e-3).abs() < 1e-15);
+        assert_eq!(values(&grid[3])[..2], [2.0, 0.5]);
+        assert_eq!(grid[9].get("a"), 5.0);
+        //a single point sits in the middle of each range
+        assert_eq!(Distribution::Uniform(0.0, 1.0).grid(1), vec![0.5]);
+        assert!((Distribution::LogUniform(1e-4, 1e-2).grid(1)[0] - 1e-3).abs() < 1e-15);
+        assert_eq!(GridSearch::new(&space, 3).len(), 18);
+    }
+
+    #[test]
+    fn random_search_stops_after_its_trials() {
+        let space = SearchSpace::new().log_uniform("lr", 1e-4, 1e-2).discrete("hidden", &[8.0, 16.0]);
+        let mut search = RandomSearch::new(space, 5).with_seed(4);
+        let mut trials = Vec::new();
+        while let Some(params) = search.next(&trials) {
+            assert!((1e-4..=1e-2).contains(&params.get("lr")));
+            assert!([8, 16].contains(&params.usize("hidden")));
+            trials.push(Trial { params, score : 0.0, folds : vec![0.0], epochs : 1, rung : None, maximize : false });
+        }
+        assert_eq!(trials.len(), 5);
+    }
+
+    #[test]
+    fn search_ranks_trials_and_scores_every_fold() {
+        let space = SearchSpace::new().discrete("learning_rate", &[1e-4, 0.02, 0.1]);
+        for (validation, folds) in [(Validation::KFold(4), 4), (Validation::Holdout(0.25), 1)] {
+            let trials = tuner().with_validation(validation).search(&mut GridSearch::new(&space, 1), &dataset());
+            assert_eq!(trials.len(), 3);
+            for trial in trials.iter() {
+                assert_eq!(trial.folds.len(), folds);
+                assert!((trial.score - trial.folds.iter().sum::<f64>() / folds as f64).abs() < 1e-12);
+                assert_eq!((trial.epochs, trial.rung), (10, None));
+            }
+            assert!(trials.windows(2).all(|pair| pair[0].score <= pair[1].score), "not ranked best first");
+            //barely moving from the initial weights loses to the others
+            assert_eq!(trials[2].params.get("learning_rate"), 1e-4);
+        }
+    }
+
+    #[test]
+    fn hyperband_counts_configurations_not_rungs() {
+        let dataset = dataset();
+        let space = SearchSpace::new().log_uniform("learning_rate", 1e-3, 1e-1);
+        let schedule = Hyperband::new(9);
+        assert_eq!(schedule.configurations(), 9 + 5 + 3);
+        for trials in [12, schedule.configurations()] {
+            let mut search = RandomSearch::new(space.clone(), trials).with_seed(3);
+            let results = tuner().hyperband(&schedule, &mut search, &dataset);
+            assert_eq!(results.len(), trials);
+            //one survivor per halving bracket, and the last bracket trains all three for the full 9 epochs
+            assert_eq!(results.iter().filter(|trial| trial.epochs == 9).count(), if trials == 12 {1 + 1} else {1 + 1 + 3});
+        }
+        //the search running dry partway leaves the later brackets empty
+        for trials in [0, 9] {
+            let mut search = RandomSearch::new(space.clone(), trials).with_seed(3);
+            assert_eq!(tuner().hyperband(&schedule, &mut search, &dataset).len(), trials);
+        }
+        let mut search = RandomSearch::new(space, 0);
+        assert!(tuner().successive_halving(&SuccessiveHalving::new(9, 1, 9), &mut search, &dataset).is_empty());
+    }
+
+    #[test]
+    fn tpe_fits_the_largest_budget_with_enough_trials() {
+        let space = SearchSpace::new().uniform("x", 0.0, 1.0);
+        //short runs favour small x, the longer ones large x
+        let mut trials = (0..12).map(|i| trial(i as f64 / 12.0, i as f64, 1, 0)).collect::<Vec<_>>();
+        trials.extend((1..9).map(|i| trial(i as f64 / 10.0, -(i as f64), 3, 1)));
+        let mut tpe = Tpe::new(space.clone(), 100).with_startup(4).with_candidates(64).with_seed(3);
+        let x = tpe.next(&trials).unwrap().get("x");
+        assert!(x > 0.7, "proposed {} from the 1-epoch trials", x);
+        //too few 9-epoch trials to fit on, so those are ignored
+        trials.extend((0..3).map(|i| trial(i as f64 / 10.0, -10.0, 9, 2)));
+        let x = tpe.next(&trials).unwrap().get("x");
+        assert!(x > 0.7, "proposed {} from the 9-epoch trials", x);
+        //the trial limit counts configurations, so rung 1 and 2 trials don't use it up
+        assert!(Tpe::new(space.clone(), 13).next(&trials).is_some());
+        assert!(Tpe::new(space, 12).next(&trials).is_none());
+    }
+}